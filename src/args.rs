@@ -13,6 +13,34 @@ pub enum SupportedProvider {
     AWS,
     #[serde(rename = "digitalocean")]
     DigitalOcean,
+    #[serde(rename = "exec")]
+    Exec,
+    #[serde(rename = "docker")]
+    Docker,
+    #[serde(rename = "proxmox")]
+    Proxmox,
+    #[serde(rename = "lxd")]
+    Lxd,
+    #[serde(rename = "etcd")]
+    Etcd,
+    #[serde(rename = "eureka")]
+    Eureka,
+    #[serde(rename = "tfstate")]
+    Tfstate,
+    #[serde(rename = "ansible")]
+    Ansible,
+    #[serde(rename = "aws-ecs")]
+    ECS,
+    #[serde(rename = "aws-cloudmap")]
+    CloudMap,
+    #[serde(rename = "aws-targetgroup")]
+    TargetGroup,
+    #[serde(rename = "tailscale")]
+    Tailscale,
+    #[serde(rename = "netbox")]
+    NetBox,
+    #[serde(rename = "ibmcloud")]
+    IBMCloud,
 }
 
 impl Display for SupportedProvider {
@@ -165,11 +193,11 @@ mod test {
             let args = format!("provider=aws region=eu-west-1 {}", malformed_arg);
             let res = ParsedArgs::try_from(args);
             assert!(res.is_err());
-            if malformed_arg.ends_with("=") {
+            if let Some(key) = malformed_arg.strip_suffix('=') {
                 assert_eq!(
                     res.unwrap_err(),
                     DiscoverError::MalformedArgument(
-                        malformed_arg[..malformed_arg.len() - 1].to_string(),
+                        key.to_string(),
                         "Expected an argument on the format: key=value".to_string(),
                     )
                 );
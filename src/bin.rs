@@ -1,6 +1,23 @@
 use log::error;
 use node_discover::get_addresses;
-#[cfg(any(feature = "aws", feature = "digitalocean"))]
+#[cfg(any(
+    feature = "aws",
+    feature = "digitalocean",
+    feature = "exec",
+    feature = "docker",
+    feature = "proxmox",
+    feature = "lxd",
+    feature = "etcd",
+    feature = "eureka",
+    feature = "tfstate",
+    feature = "ansible",
+    feature = "aws-ecs",
+    feature = "aws-cloudmap",
+    feature = "aws-targetgroup",
+    feature = "tailscale",
+    feature = "netbox",
+    feature = "ibmcloud"
+))]
 use node_discover::Provider;
 
 const GLOBAL_HELP: &str = "The options for discovering ip addresses are provided as a
@@ -19,19 +36,131 @@ pub fn help(provider: &str) {
             // Only print AWS help if it is enabled
             #[cfg(feature = "aws")]
             {
-                return println!("{}", node_discover::AWSProvider::help());
+                println!("{}", node_discover::AWSProvider::help());
             }
         }
         "digitalocean" => {
             // Only print DO help if it is enabled
             #[cfg(feature = "digitalocean")]
             {
-                return println!("{}", node_discover::DOProvider::help());
+                println!("{}", node_discover::DOProvider::help());
+            }
+        }
+        "exec" => {
+            // Only print Exec help if it is enabled
+            #[cfg(feature = "exec")]
+            {
+                println!("{}", node_discover::ExecProvider::help());
+            }
+        }
+        "docker" => {
+            // Only print Docker help if it is enabled
+            #[cfg(feature = "docker")]
+            {
+                println!("{}", node_discover::DockerProvider::help());
+            }
+        }
+        "proxmox" => {
+            // Only print Proxmox VE help if it is enabled
+            #[cfg(feature = "proxmox")]
+            {
+                println!("{}", node_discover::ProxmoxProvider::help());
+            }
+        }
+        "lxd" => {
+            // Only print LXD help if it is enabled
+            #[cfg(feature = "lxd")]
+            {
+                println!("{}", node_discover::LxdProvider::help());
+            }
+        }
+        "etcd" => {
+            // Only print etcd help if it is enabled
+            #[cfg(feature = "etcd")]
+            {
+                println!("{}", node_discover::EtcdProvider::help());
+            }
+        }
+        "eureka" => {
+            // Only print Eureka help if it is enabled
+            #[cfg(feature = "eureka")]
+            {
+                println!("{}", node_discover::EurekaProvider::help());
+            }
+        }
+        "tfstate" => {
+            // Only print Terraform state help if it is enabled
+            #[cfg(feature = "tfstate")]
+            {
+                println!("{}", node_discover::TfstateProvider::help());
+            }
+        }
+        "ansible" => {
+            // Only print Ansible help if it is enabled
+            #[cfg(feature = "ansible")]
+            {
+                println!("{}", node_discover::AnsibleProvider::help());
+            }
+        }
+        "aws-ecs" => {
+            // Only print ECS help if it is enabled
+            #[cfg(feature = "aws-ecs")]
+            {
+                println!("{}", node_discover::ECSProvider::help());
+            }
+        }
+        "aws-cloudmap" => {
+            // Only print Cloud Map help if it is enabled
+            #[cfg(feature = "aws-cloudmap")]
+            {
+                println!("{}", node_discover::CloudMapProvider::help());
+            }
+        }
+        "aws-targetgroup" => {
+            // Only print ELB target group help if it is enabled
+            #[cfg(feature = "aws-targetgroup")]
+            {
+                println!("{}", node_discover::TargetGroupProvider::help());
+            }
+        }
+        "tailscale" => {
+            // Only print Tailscale help if it is enabled
+            #[cfg(feature = "tailscale")]
+            {
+                println!("{}", node_discover::TailscaleProvider::help());
+            }
+        }
+        "netbox" => {
+            // Only print NetBox help if it is enabled
+            #[cfg(feature = "netbox")]
+            {
+                println!("{}", node_discover::NetBoxProvider::help());
+            }
+        }
+        "ibmcloud" => {
+            // Only print IBM Cloud help if it is enabled
+            #[cfg(feature = "ibmcloud")]
+            {
+                println!("{}", node_discover::IBMCloudProvider::help());
             }
         }
         _ => {
             help("aws");
             help("digitalocean");
+            help("exec");
+            help("docker");
+            help("proxmox");
+            help("lxd");
+            help("etcd");
+            help("eureka");
+            help("tfstate");
+            help("ansible");
+            help("aws-ecs");
+            help("aws-cloudmap");
+            help("aws-targetgroup");
+            help("tailscale");
+            help("netbox");
+            help("ibmcloud");
         }
     }
 }
@@ -48,7 +177,7 @@ async fn get_addrs(args: Vec<String>) {
 }
 
 fn get_help(args: Vec<String>) {
-    let provider = args.get(0);
+    let provider = args.first();
     match provider {
         Some(provider) => match &provider[..] {
             "aws" => {
@@ -57,6 +186,48 @@ fn get_help(args: Vec<String>) {
             "digitalocean" => {
                 help("digitalocean");
             }
+            "exec" => {
+                help("exec");
+            }
+            "docker" => {
+                help("docker");
+            }
+            "proxmox" => {
+                help("proxmox");
+            }
+            "lxd" => {
+                help("lxd");
+            }
+            "etcd" => {
+                help("etcd");
+            }
+            "eureka" => {
+                help("eureka");
+            }
+            "tfstate" => {
+                help("tfstate");
+            }
+            "ansible" => {
+                help("ansible");
+            }
+            "aws-ecs" => {
+                help("aws-ecs");
+            }
+            "aws-cloudmap" => {
+                help("aws-cloudmap");
+            }
+            "aws-targetgroup" => {
+                help("aws-targetgroup");
+            }
+            "tailscale" => {
+                help("tailscale");
+            }
+            "netbox" => {
+                help("netbox");
+            }
+            "ibmcloud" => {
+                help("ibmcloud");
+            }
             _ => {
                 help("all");
             }
@@ -16,4 +16,18 @@ pub enum DiscoverError {
         "Unsupported provider `{0}`. Either the provider is not supported or it is not enabled."
     )]
     UnsupportedProvider(String),
+    /// The accounts of a multi-account lookup that failed, with their error.
+    #[error("Unable to retrieve data from some accounts. Errors: {}", format_failures(.0))]
+    AccountsFailed(Vec<(String, DiscoverError)>),
+    /// The regions of a multi-region lookup that failed, with their error.
+    #[error("Unable to retrieve data from some regions. Errors: {}", format_failures(.0))]
+    RegionsFailed(Vec<(String, DiscoverError)>),
+}
+
+fn format_failures(failures: &[(String, DiscoverError)]) -> String {
+    failures
+        .iter()
+        .map(|(scope, error)| format!("`{}`: {}", scope, error))
+        .collect::<Vec<_>>()
+        .join("; ")
 }
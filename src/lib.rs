@@ -39,10 +39,38 @@ use args::ParsedArgs;
 pub use args::SupportedProvider;
 use errors::DiscoverError;
 
+#[cfg(feature = "ansible")]
+pub use providers::ansible::AnsibleProvider;
 #[cfg(feature = "aws")]
 pub use providers::aws::AWSProvider;
+#[cfg(feature = "aws-cloudmap")]
+pub use providers::aws_cloudmap::CloudMapProvider;
+#[cfg(feature = "aws-ecs")]
+pub use providers::aws_ecs::ECSProvider;
+#[cfg(feature = "aws-targetgroup")]
+pub use providers::aws_targetgroup::TargetGroupProvider;
 #[cfg(feature = "digitalocean")]
 pub use providers::digitalocean::DOProvider;
+#[cfg(feature = "docker")]
+pub use providers::docker::DockerProvider;
+#[cfg(feature = "etcd")]
+pub use providers::etcd::EtcdProvider;
+#[cfg(feature = "eureka")]
+pub use providers::eureka::EurekaProvider;
+#[cfg(feature = "exec")]
+pub use providers::exec::ExecProvider;
+#[cfg(feature = "ibmcloud")]
+pub use providers::ibmcloud::IBMCloudProvider;
+#[cfg(feature = "lxd")]
+pub use providers::lxd::LxdProvider;
+#[cfg(feature = "netbox")]
+pub use providers::netbox::NetBoxProvider;
+#[cfg(feature = "proxmox")]
+pub use providers::proxmox::ProxmoxProvider;
+#[cfg(feature = "tailscale")]
+pub use providers::tailscale::TailscaleProvider;
+#[cfg(feature = "tfstate")]
+pub use providers::tfstate::TfstateProvider;
 pub use providers::*;
 
 pub async fn get_addresses(args: String) -> Result<Vec<String>, DiscoverError> {
@@ -66,5 +94,131 @@ pub async fn get_addresses(args: String) -> Result<Vec<String>, DiscoverError> {
             #[allow(unreachable_code)]
             Err(DiscoverError::UnsupportedProvider("digitalocean".into()))
         }
+        SupportedProvider::Exec => {
+            #[cfg(feature = "exec")]
+            {
+                let p = ExecProvider::try_from(args)?;
+                return p.addrs().await;
+            }
+            #[allow(unreachable_code)]
+            Err(DiscoverError::UnsupportedProvider("exec".into()))
+        }
+        SupportedProvider::Docker => {
+            #[cfg(feature = "docker")]
+            {
+                let p = DockerProvider::try_from(args)?;
+                return p.addrs().await;
+            }
+            #[allow(unreachable_code)]
+            Err(DiscoverError::UnsupportedProvider("docker".into()))
+        }
+        SupportedProvider::Proxmox => {
+            #[cfg(feature = "proxmox")]
+            {
+                let p = ProxmoxProvider::try_from(args)?;
+                return p.addrs().await;
+            }
+            #[allow(unreachable_code)]
+            Err(DiscoverError::UnsupportedProvider("proxmox".into()))
+        }
+        SupportedProvider::Lxd => {
+            #[cfg(feature = "lxd")]
+            {
+                let p = LxdProvider::try_from(args)?;
+                return p.addrs().await;
+            }
+            #[allow(unreachable_code)]
+            Err(DiscoverError::UnsupportedProvider("lxd".into()))
+        }
+        SupportedProvider::Etcd => {
+            #[cfg(feature = "etcd")]
+            {
+                let p = EtcdProvider::try_from(args)?;
+                return p.addrs().await;
+            }
+            #[allow(unreachable_code)]
+            Err(DiscoverError::UnsupportedProvider("etcd".into()))
+        }
+        SupportedProvider::Eureka => {
+            #[cfg(feature = "eureka")]
+            {
+                let p = EurekaProvider::try_from(args)?;
+                return p.addrs().await;
+            }
+            #[allow(unreachable_code)]
+            Err(DiscoverError::UnsupportedProvider("eureka".into()))
+        }
+        SupportedProvider::Tfstate => {
+            #[cfg(feature = "tfstate")]
+            {
+                let p = TfstateProvider::try_from(args)?;
+                return p.addrs().await;
+            }
+            #[allow(unreachable_code)]
+            Err(DiscoverError::UnsupportedProvider("tfstate".into()))
+        }
+        SupportedProvider::Ansible => {
+            #[cfg(feature = "ansible")]
+            {
+                let p = AnsibleProvider::try_from(args)?;
+                return p.addrs().await;
+            }
+            #[allow(unreachable_code)]
+            Err(DiscoverError::UnsupportedProvider("ansible".into()))
+        }
+        SupportedProvider::ECS => {
+            #[cfg(feature = "aws-ecs")]
+            {
+                let p = ECSProvider::try_from(args)?;
+                return p.addrs().await;
+            }
+            #[allow(unreachable_code)]
+            Err(DiscoverError::UnsupportedProvider("aws-ecs".into()))
+        }
+        SupportedProvider::CloudMap => {
+            #[cfg(feature = "aws-cloudmap")]
+            {
+                let p = CloudMapProvider::try_from(args)?;
+                return p.addrs().await;
+            }
+            #[allow(unreachable_code)]
+            Err(DiscoverError::UnsupportedProvider("aws-cloudmap".into()))
+        }
+        SupportedProvider::TargetGroup => {
+            #[cfg(feature = "aws-targetgroup")]
+            {
+                let p = TargetGroupProvider::try_from(args)?;
+                return p.addrs().await;
+            }
+            #[allow(unreachable_code)]
+            Err(DiscoverError::UnsupportedProvider("aws-targetgroup".into()))
+        }
+        SupportedProvider::Tailscale => {
+            #[cfg(feature = "tailscale")]
+            {
+                let p = TailscaleProvider::try_from(args)?;
+                return p.addrs().await;
+            }
+            #[allow(unreachable_code)]
+            Err(DiscoverError::UnsupportedProvider("tailscale".into()))
+        }
+        SupportedProvider::NetBox => {
+            #[cfg(feature = "netbox")]
+            {
+                let p = NetBoxProvider::try_from(args)?;
+                return p.addrs().await;
+            }
+            #[allow(unreachable_code)]
+            Err(DiscoverError::UnsupportedProvider("netbox".into()))
+        }
+        SupportedProvider::IBMCloud => {
+            #[cfg(feature = "ibmcloud")]
+            {
+                let p = IBMCloudProvider::try_from(args)?;
+                return p.addrs().await;
+            }
+            #[allow(unreachable_code)]
+            Err(DiscoverError::UnsupportedProvider("ibmcloud".into()))
+        }
     }
 }
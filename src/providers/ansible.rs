@@ -0,0 +1,573 @@
+use log::{debug, info};
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryFrom,
+    fs,
+    path::PathBuf,
+};
+
+use crate::{args::ParsedArgs, SupportedProvider};
+
+use super::{DiscoverError, Provider};
+
+#[derive(Debug, Clone, Default)]
+struct Group {
+    pub hosts: Vec<String>,
+    pub children: Vec<String>,
+}
+
+/// The hosts and groups of a static inventory.
+#[derive(Debug, Clone, Default)]
+struct Inventory {
+    /// Host names in the order they first appear in the inventory
+    pub hosts: Vec<String>,
+    /// The `ansible_host` of hosts that set it
+    pub ansible_hosts: HashMap<String, String>,
+    pub groups: HashMap<String, Group>,
+}
+
+impl Inventory {
+    fn add_host(&mut self, group: &str, host: String, ansible_host: Option<String>) {
+        if !self.hosts.contains(&host) {
+            self.hosts.push(host.clone());
+        }
+        if let Some(ansible_host) = ansible_host {
+            self.ansible_hosts.insert(host.clone(), ansible_host);
+        }
+        let group = self.groups.entry(group.to_string()).or_default();
+        if !group.hosts.contains(&host) {
+            group.hosts.push(host);
+        }
+    }
+
+    fn add_child(&mut self, group: &str, child: String) {
+        self.groups.entry(child.clone()).or_default();
+        let group = self.groups.entry(group.to_string()).or_default();
+        if !group.children.contains(&child) {
+            group.children.push(child);
+        }
+    }
+
+    /// Returns the hosts of `group` and of all its descendant groups.
+    fn resolve(&self, group: &str) -> Option<Vec<String>> {
+        if group == "all" {
+            return Some(self.hosts.clone());
+        }
+        self.groups.get(group)?;
+
+        let mut hosts = Vec::new();
+        let mut visited = HashSet::new();
+        let mut pending = vec![group.to_string()];
+        while let Some(name) = pending.pop() {
+            if !visited.insert(name.clone()) {
+                continue;
+            }
+            let group = match self.groups.get(&name) {
+                Some(group) => group,
+                None => continue,
+            };
+            for host in &group.hosts {
+                if !hosts.contains(host) {
+                    hosts.push(host.clone());
+                }
+            }
+            // Visit the children in the order they are listed
+            pending.extend(group.children.iter().rev().cloned());
+        }
+
+        Some(hosts)
+    }
+}
+
+fn malformed(msg: String) -> DiscoverError {
+    DiscoverError::ProviderRequestFailed(format!("Malformed inventory: {}", msg))
+}
+
+/// Expands host ranges like `web[01:10].example.com` or `db-[a:c]`, with an
+/// optional stride like `[0:10:2]`.
+fn expand_hosts(pattern: &str) -> Result<Vec<String>, DiscoverError> {
+    let start = match pattern.find('[') {
+        Some(start) => start,
+        None => return Ok(vec![pattern.to_string()]),
+    };
+    let end = pattern[start..]
+        .find(']')
+        .map(|end| start + end)
+        .ok_or_else(|| malformed(format!("unterminated range in {}", pattern)))?;
+
+    let (prefix, range, suffix) = (
+        &pattern[..start],
+        &pattern[start + 1..end],
+        &pattern[end + 1..],
+    );
+    let parts = range.split(':').collect::<Vec<_>>();
+    let (first, last, stride) = match parts[..] {
+        [first, last] => (first, last, 1),
+        [first, last, stride] => (
+            first,
+            last,
+            stride
+                .parse::<usize>()
+                .ok()
+                .filter(|stride| *stride > 0)
+                .ok_or_else(|| malformed(format!("invalid stride in {}", pattern)))?,
+        ),
+        _ => return Err(malformed(format!("invalid range in {}", pattern))),
+    };
+
+    let reversed = || malformed(format!("range ends before it starts in {}", pattern));
+    let items = if let (Ok(from), Ok(to)) = (first.parse::<usize>(), last.parse::<usize>()) {
+        if from > to {
+            return Err(reversed());
+        }
+        // Keep the zero padding of the start of the range, e.g. [01:10]
+        let width = if first.starts_with('0') {
+            first.len()
+        } else {
+            0
+        };
+        (from..=to)
+            .step_by(stride)
+            .map(|i| format!("{:0width$}", i, width = width))
+            .collect::<Vec<_>>()
+    } else {
+        match (first.as_bytes(), last.as_bytes()) {
+            ([from], [to]) if from.is_ascii_alphabetic() && to.is_ascii_alphabetic() => {
+                if from > to {
+                    return Err(reversed());
+                }
+                (*from..=*to)
+                    .step_by(stride)
+                    .map(|c| (c as char).to_string())
+                    .collect()
+            }
+            _ => return Err(malformed(format!("invalid range in {}", pattern))),
+        }
+    };
+
+    let mut hosts = Vec::new();
+    for item in items {
+        for rest in expand_hosts(suffix)? {
+            hosts.push(format!("{}{}{}", prefix, item, rest));
+        }
+    }
+    Ok(hosts)
+}
+
+fn unquote(value: &str) -> String {
+    value.trim_matches(|c| c == '"' || c == '\'').to_string()
+}
+
+fn parse_ini(content: &str) -> Result<Inventory, DiscoverError> {
+    enum Section {
+        Hosts(String),
+        Children(String),
+        Vars,
+    }
+
+    let mut inventory = Inventory::default();
+    let mut section = Section::Hosts("ungrouped".to_string());
+
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        if line.starts_with('[') && line.ends_with(']') {
+            let name = &line[1..line.len() - 1];
+            section = match name.split_once(':') {
+                None => Section::Hosts(name.to_string()),
+                Some((group, "children")) => Section::Children(group.to_string()),
+                Some((_, "vars")) => Section::Vars,
+                Some(_) => return Err(malformed(format!("unknown section [{}]", name))),
+            };
+            if let Section::Hosts(group) | Section::Children(group) = &section {
+                inventory.groups.entry(group.clone()).or_default();
+            }
+            continue;
+        }
+
+        let mut tokens = line.split_whitespace();
+        let name = tokens.next().unwrap_or_default();
+        match &section {
+            Section::Hosts(group) => {
+                let ansible_host = tokens
+                    .filter_map(|token| token.split_once('='))
+                    .find(|(key, _)| *key == "ansible_host")
+                    .map(|(_, value)| unquote(value));
+                for host in expand_hosts(name)? {
+                    inventory.add_host(group, host, ansible_host.clone());
+                }
+            }
+            Section::Children(group) => inventory.add_child(group, name.to_string()),
+            Section::Vars => {}
+        }
+    }
+
+    Ok(inventory)
+}
+
+fn parse_yaml(content: &str) -> Result<Inventory, DiscoverError> {
+    fn walk(
+        inventory: &mut Inventory,
+        name: &str,
+        group: &serde_yaml::Value,
+    ) -> Result<(), DiscoverError> {
+        inventory.groups.entry(name.to_string()).or_default();
+
+        if let Some(hosts) = group.get("hosts").and_then(|hosts| hosts.as_mapping()) {
+            for (host, vars) in hosts {
+                let host = host
+                    .as_str()
+                    .ok_or_else(|| malformed(format!("host names in {} must be strings", name)))?;
+                let ansible_host = vars.get("ansible_host").and_then(|value| match value {
+                    serde_yaml::Value::String(value) => Some(value.clone()),
+                    serde_yaml::Value::Number(value) => Some(value.to_string()),
+                    _ => None,
+                });
+                for host in expand_hosts(host)? {
+                    inventory.add_host(name, host, ansible_host.clone());
+                }
+            }
+        }
+
+        if let Some(children) = group
+            .get("children")
+            .and_then(|children| children.as_mapping())
+        {
+            for (child, child_group) in children {
+                let child = child
+                    .as_str()
+                    .ok_or_else(|| malformed(format!("group names in {} must be strings", name)))?;
+                inventory.add_child(name, child.to_string());
+                walk(inventory, child, child_group)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    let root =
+        serde_yaml::from_str::<serde_yaml::Value>(content).map_err(|e| malformed(e.to_string()))?;
+    let groups = root
+        .as_mapping()
+        .ok_or_else(|| malformed("expected a mapping of groups".to_string()))?;
+
+    let mut inventory = Inventory::default();
+    for (name, group) in groups {
+        let name = name
+            .as_str()
+            .ok_or_else(|| malformed("group names must be strings".to_string()))?;
+        walk(&mut inventory, name, group)?;
+    }
+
+    Ok(inventory)
+}
+
+#[derive(Debug, Clone)]
+pub struct AnsibleProvider {
+    inventory: PathBuf,
+    group: String,
+}
+
+impl TryFrom<ParsedArgs> for AnsibleProvider {
+    type Error = DiscoverError;
+
+    fn try_from(args: ParsedArgs) -> Result<Self, Self::Error> {
+        let mut inventory = None;
+        let mut group = "all".to_string();
+
+        for (key, value) in args {
+            match &key[..] {
+                "inventory" => inventory = Some(PathBuf::from(value)),
+                "group" => group = value,
+                _ => return Err(DiscoverError::UnexpectedArgument(key)),
+            }
+        }
+
+        let inventory =
+            inventory.ok_or_else(|| DiscoverError::MissingArgument("inventory".into()))?;
+
+        Ok(AnsibleProvider { inventory, group })
+    }
+}
+
+impl TryFrom<Vec<String>> for AnsibleProvider {
+    type Error = DiscoverError;
+
+    fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
+        let args = ParsedArgs::try_from(value)?;
+        match *args.provider() {
+            SupportedProvider::Ansible => AnsibleProvider::try_from(args),
+            _ => Err(DiscoverError::MalformedArgument(
+                format!("provider={}", args.provider()),
+                "you should not see this ...".to_string(),
+            )),
+        }
+    }
+}
+
+impl AnsibleProvider {
+    pub fn inventory(&self) -> &PathBuf {
+        &self.inventory
+    }
+
+    pub fn group(&self) -> &str {
+        &self.group
+    }
+
+    fn read_inventory(&self) -> Result<Inventory, DiscoverError> {
+        let content = fs::read_to_string(&self.inventory).map_err(|e| {
+            DiscoverError::ProviderRequestFailed(format!(
+                "Unable to read {}: {}",
+                self.inventory.display(),
+                e
+            ))
+        })?;
+
+        match self.inventory.extension().and_then(|ext| ext.to_str()) {
+            Some("yml") | Some("yaml") => parse_yaml(&content),
+            _ => parse_ini(&content),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Provider for AnsibleProvider {
+    async fn addrs(&self) -> Result<Vec<String>, DiscoverError> {
+        debug!(
+            "Using inventory={} group={}",
+            self.inventory.display(),
+            self.group
+        );
+
+        let inventory = self.read_inventory()?;
+        let hosts = inventory.resolve(&self.group).ok_or_else(|| {
+            DiscoverError::ProviderRequestFailed(format!(
+                "Group {} not found in {}",
+                self.group,
+                self.inventory.display()
+            ))
+        })?;
+        debug!("Found {} hosts in group {}", hosts.len(), self.group);
+
+        let mut addrs = Vec::new();
+        for host in hosts {
+            let addr = inventory.ansible_hosts.get(&host).unwrap_or(&host).clone();
+            info!("Found host {} with address {}", host, addr);
+            if !addrs.contains(&addr) {
+                addrs.push(addr);
+            }
+        }
+
+        debug!("Found addresses: {:?}", addrs);
+        Ok(addrs)
+    }
+
+    fn help() -> &'static str {
+        "Ansible inventory:
+
+	provider:  \"ansible\"
+	inventory: Path of a static INI or YAML inventory. Files ending in .yml or .yaml are
+	           parsed as YAML, anything else as INI.
+	group:     The group to return the hosts of, including the hosts of its children.
+	           Defaults to \"all\".
+
+	Host ranges like \"web[01:10].example.com\" are expanded and the ansible_host of a
+	host is returned instead of its name when it is set.
+"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+
+    const INI_INVENTORY: &str = r#"
+# Hosts without a group
+jump.example.com ansible_host=203.0.113.10
+
+[consul_servers]
+consul[1:3].example.com
+consul-legacy.example.com ansible_host="10.0.0.99" ansible_user=admin
+
+[consul_clients]
+web[08:10:2].example.com
+db-[a:b].example.com ansible_host=10.0.1.5
+
+[consul:children]
+consul_servers
+consul_clients
+
+[consul:vars]
+datacenter=dc1
+"#;
+
+    const YAML_INVENTORY: &str = r#"
+all:
+  hosts:
+    jump.example.com:
+      ansible_host: 203.0.113.10
+  children:
+    consul:
+      children:
+        consul_servers:
+          hosts:
+            consul[1:3].example.com:
+            consul-legacy.example.com:
+              ansible_host: 10.0.0.99
+              ansible_user: admin
+        consul_clients:
+          hosts:
+            web[08:10:2].example.com:
+            db-[a:b].example.com:
+              ansible_host: 10.0.1.5
+          vars:
+            datacenter: dc1
+"#;
+
+    fn inventory_file(content: &str, suffix: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::Builder::new().suffix(suffix).tempfile().unwrap();
+        file.write_all(content.as_bytes()).unwrap();
+        file
+    }
+
+    async fn addrs(
+        file: &tempfile::NamedTempFile,
+        group: &str,
+    ) -> Result<Vec<String>, DiscoverError> {
+        let args = vec![
+            "provider=ansible".to_string(),
+            format!("inventory={}", file.path().display()),
+            format!("group={}", group),
+        ];
+        AnsibleProvider::try_from(args).unwrap().addrs().await
+    }
+
+    #[test]
+    fn ansible_provider_from_string() {
+        let args = "provider=ansible inventory=/etc/ansible/hosts group=consul_servers";
+
+        let res = ParsedArgs::try_from(args.to_string());
+        assert!(res.is_ok());
+        let res = AnsibleProvider::try_from(res.unwrap());
+        assert!(res.is_ok());
+        let provider = res.unwrap();
+        assert_eq!(provider.inventory(), &PathBuf::from("/etc/ansible/hosts"));
+        assert_eq!(provider.group(), "consul_servers");
+
+        let res = AnsibleProvider::try_from(
+            ParsedArgs::try_from("provider=ansible".to_string()).unwrap(),
+        );
+        assert_eq!(
+            res.unwrap_err(),
+            DiscoverError::MissingArgument("inventory".to_string())
+        );
+    }
+
+    #[test]
+    fn expand_host_ranges() {
+        assert_eq!(expand_hosts("web1"), Ok(vec!["web1".to_string()]));
+        assert_eq!(
+            expand_hosts("web[08:10].example.com"),
+            Ok(vec![
+                "web08.example.com".to_string(),
+                "web09.example.com".to_string(),
+                "web10.example.com".to_string(),
+            ])
+        );
+        assert_eq!(
+            expand_hosts("web[1:5:2]"),
+            Ok(vec![
+                "web1".to_string(),
+                "web3".to_string(),
+                "web5".to_string()
+            ])
+        );
+        assert_eq!(
+            expand_hosts("db-[a:b]-[1:2]"),
+            Ok(vec![
+                "db-a-1".to_string(),
+                "db-a-2".to_string(),
+                "db-b-1".to_string(),
+                "db-b-2".to_string(),
+            ])
+        );
+        for malformed in &[
+            "web[1:",
+            "web[1]",
+            "web[1:a]",
+            "web[1:3:0]",
+            "web[5:1]",
+            "db-[c:a]",
+        ] {
+            assert!(expand_hosts(malformed).is_err(), "{}", malformed);
+        }
+    }
+
+    #[tokio::test]
+    async fn resolve_groups_in_ini_inventory() {
+        let file = inventory_file(INI_INVENTORY, "");
+        assert_eq!(
+            addrs(&file, "consul").await,
+            Ok(vec![
+                "consul1.example.com".to_string(),
+                "consul2.example.com".to_string(),
+                "consul3.example.com".to_string(),
+                "10.0.0.99".to_string(),
+                "web08.example.com".to_string(),
+                "web10.example.com".to_string(),
+                "10.0.1.5".to_string(),
+            ])
+        );
+        assert_eq!(
+            addrs(&file, "ungrouped").await,
+            Ok(vec!["203.0.113.10".to_string()])
+        );
+        assert_eq!(addrs(&file, "all").await.unwrap().len(), 8);
+    }
+
+    #[tokio::test]
+    async fn resolve_groups_in_yaml_inventory() {
+        let file = inventory_file(YAML_INVENTORY, ".yml");
+        assert_eq!(
+            addrs(&file, "consul").await,
+            Ok(vec![
+                "consul1.example.com".to_string(),
+                "consul2.example.com".to_string(),
+                "consul3.example.com".to_string(),
+                "10.0.0.99".to_string(),
+                "web08.example.com".to_string(),
+                "web10.example.com".to_string(),
+                "10.0.1.5".to_string(),
+            ])
+        );
+        assert_eq!(
+            addrs(&file, "consul_servers").await,
+            Ok(vec![
+                "consul1.example.com".to_string(),
+                "consul2.example.com".to_string(),
+                "consul3.example.com".to_string(),
+                "10.0.0.99".to_string(),
+            ])
+        );
+        assert_eq!(addrs(&file, "all").await.unwrap().len(), 8);
+    }
+
+    #[tokio::test]
+    async fn fail_on_unknown_group() {
+        let file = inventory_file(INI_INVENTORY, ".ini");
+        assert!(matches!(
+            addrs(&file, "nomad").await,
+            Err(DiscoverError::ProviderRequestFailed(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn nested_group_cycles_terminate() {
+        let file = inventory_file("[a]\nhost-a\n[a:children]\nb\n[b:children]\na\n", "");
+        assert_eq!(addrs(&file, "b").await, Ok(vec!["host-a".to_string()]));
+    }
+}
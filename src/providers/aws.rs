@@ -1,50 +1,209 @@
-use log::{debug, info};
-use rusoto_core::{credential::ChainProvider, HttpClient, Region};
-use rusoto_ec2::{DescribeInstancesRequest, DescribeInstancesResult, Ec2, Ec2Client, Filter};
+use log::{debug, info, warn};
+use rusoto_autoscaling::{
+    AutoScalingGroup, AutoScalingGroupNamesType, Autoscaling, AutoscalingClient,
+};
+use rusoto_core::Region;
+use rusoto_ec2::{
+    DescribeInstancesRequest, DescribeRegionsRequest, Ec2, Ec2Client, Filter, Instance,
+    InstanceNetworkInterface, Reservation,
+};
+use tokio::sync::Semaphore;
 
-use std::{convert::TryFrom, str::FromStr};
+use std::{collections::HashSet, convert::TryFrom, str::FromStr, sync::Arc};
 
 use crate::{args::ParsedArgs, SupportedProvider};
 
-use super::{DiscoverError, Provider};
+use super::{aws_config::AwsConfig, DiscoverError, Provider};
 use serde::Deserialize;
 
-#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
 pub enum AddrType {
+    #[default]
     #[serde(rename = "private_v4")]
     PrivateV4,
     #[serde(rename = "public_v4")]
     PublicV4,
     #[serde(rename = "public_v6")]
     PublicV6,
+    /// Every private IPv4 address of every network interface
+    #[serde(rename = "all_private_v4")]
+    AllPrivateV4,
+    #[serde(rename = "private_v6")]
+    PrivateV6,
+    #[serde(rename = "private_dns")]
+    PrivateDns,
+    #[serde(rename = "public_dns")]
+    PublicDns,
 }
 
-impl Default for AddrType {
-    fn default() -> Self {
-        Self::PrivateV4
+impl TryFrom<String> for AddrType {
+    type Error = DiscoverError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let json_value = format!("\"{}\"", value);
+        serde_json::from_str(&json_value).map_err(|_| {
+            DiscoverError::MalformedArgument(
+                format!("addr_type={}", value),
+                format!("{} is not a valid addr_type. Valid addr_types are: private_v4, all_private_v4, public_v4, private_v6, public_v6, private_dns and public_dns.", value)
+            )
+        })
     }
 }
 
-impl TryFrom<String> for AddrType {
+/// The regions of a multi-region lookup.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Regions {
+    List(Vec<Region>),
+    /// Every region enabled for the account, from DescribeRegions
+    All,
+}
+
+impl TryFrom<String> for Regions {
+    type Error = DiscoverError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        if value == "all" {
+            return Ok(Regions::All);
+        }
+
+        let regions = value
+            .split(',')
+            .filter(|region| !region.is_empty())
+            .map(|region| {
+                Region::from_str(region).map_err(|_| {
+                    DiscoverError::MalformedArgument(
+                        format!("regions={}", value),
+                        format!("{} is not a valid AWS Region", region),
+                    )
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if regions.is_empty() {
+            return Err(DiscoverError::MalformedArgument(
+                format!("regions={}", value),
+                "Expected a comma separated list of regions or \"all\"".to_string(),
+            ));
+        }
+
+        Ok(Regions::List(regions))
+    }
+}
+
+/// What a multi-region lookup does when some of the regions fail.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+pub enum OnError {
+    /// Fail the lookup
+    #[default]
+    #[serde(rename = "fail")]
+    Fail,
+    /// Return the nodes of the other regions and log the errors
+    #[serde(rename = "partial")]
+    Partial,
+}
+
+impl TryFrom<String> for OnError {
     type Error = DiscoverError;
 
     fn try_from(value: String) -> Result<Self, Self::Error> {
         let json_value = format!("\"{}\"", value);
         serde_json::from_str(&json_value).map_err(|_| {
             DiscoverError::MalformedArgument(
-                format!("addr_type={}", value),
-                format!("{} is not a valid addr_type. Valid addr_types are: private_v4, public_v4 and public_v6.", value)
+                format!("on_error={}", value),
+                format!(
+                    "{} is not a valid on_error. Valid values are: fail and partial.",
+                    value
+                ),
             )
         })
     }
 }
 
+/// At most this many regions are queried at the same time, unless
+/// `max_concurrency` is set.
+const DEFAULT_MAX_CONCURRENCY: usize = 4;
+
+/// The services the provider sends requests to, besides sts.
+const SERVICES: &[&str] = &["ec2", "autoscaling"];
+
+/// An address found by the provider, with the region of its instance.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Node {
+    pub addr: String,
+    pub region: String,
+}
+
+/// How the instances to discover are selected.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Selector {
+    /// `tag_key` and `tag_value`
+    Tag { key: String, value: String },
+    /// The InService instances of `asg_name`
+    AutoScalingGroup(String),
+    /// Only the `filter.<name>` arguments
+    Filters,
+}
+
+/// The EC2 filters that can be set with `filter.<name>=value,...`, besides
+/// `tag:<key>`.
+const SUPPORTED_FILTERS: [&str; 11] = [
+    "availability-zone",
+    "image-id",
+    "instance-id",
+    "instance-state-name",
+    "instance-type",
+    "instance.group-id",
+    "instance.group-name",
+    "private-ip-address",
+    "subnet-id",
+    "tag-key",
+    "vpc-id",
+];
+
+/// Parses `filter.<name>=value,...` into an EC2 filter.
+fn parse_filter(name: &str, value: &str) -> Result<Filter, DiscoverError> {
+    let malformed = |reason: String| {
+        DiscoverError::MalformedArgument(format!("filter.{}={}", name, value), reason)
+    };
+
+    let is_tag = matches!(name.strip_prefix("tag:"), Some(key) if !key.is_empty());
+    if !is_tag && !SUPPORTED_FILTERS.contains(&name) {
+        return Err(malformed(format!(
+            "{} is not a supported EC2 filter. Supported filters are: tag:<key>, {}.",
+            name,
+            SUPPORTED_FILTERS.join(", ")
+        )));
+    }
+
+    let values = value
+        .split(',')
+        .filter(|value| !value.is_empty())
+        .map(String::from)
+        .collect::<Vec<_>>();
+    if values.is_empty() {
+        return Err(malformed("Expected at least one value".to_string()));
+    }
+
+    Ok(Filter {
+        name: Some(name.to_string()),
+        values: Some(values),
+    })
+}
+
 #[derive(Debug, Clone)]
 pub struct AWSProvider {
-    tag_key: String,
-    tag_value: String,
-    region: Region,
+    selector: Selector,
+    filters: Vec<Filter>,
+    config: AwsConfig,
+    /// One configuration per role of `role_arns`, empty when a single account is queried
+    accounts: Vec<AwsConfig>,
+    regions: Option<Regions>,
+    on_error: OnError,
     addr_type: AddrType,
+    /// Only the addresses of the network interface attached at this index
+    device_index: Option<i64>,
+    max_results: Option<i64>,
+    /// The regions queried at the same time, over all accounts
+    max_concurrency: usize,
 }
 
 impl TryFrom<ParsedArgs> for AWSProvider {
@@ -53,38 +212,137 @@ impl TryFrom<ParsedArgs> for AWSProvider {
     fn try_from(args: ParsedArgs) -> Result<Self, Self::Error> {
         let mut tag_key = None;
         let mut tag_value = None;
-        let mut region = None;
+        let mut asg_name = None;
+        let mut filters = Vec::new();
+        let mut config = AwsConfig::default();
         let mut addr_type = AddrType::default();
+        let mut device_index = None;
+        let mut max_results = None;
+        let mut max_concurrency = DEFAULT_MAX_CONCURRENCY;
+        let mut role_arns = None;
+        let mut regions = None;
+        let mut on_error = OnError::default();
 
         for (key, value) in args {
             match &key[..] {
                 "tag_key" => tag_key = Some(value),
                 "tag_value" => tag_value = Some(value),
-                "region" => {
-                    region = Some(Region::from_str(&value).map_err(|_| {
-                        DiscoverError::MalformedArgument(
-                            format!("region={}", value),
-                            format!("{} is not a valid AWS Region", value),
-                        )
-                    })?)
-                }
+                "asg_name" => asg_name = Some(value),
                 "addr_type" => addr_type = AddrType::try_from(value)?,
-                _ => return Err(DiscoverError::UnexpectedArgument(key)),
+                "device_index" => {
+                    device_index = Some(
+                        value
+                            .parse::<i64>()
+                            .ok()
+                            .filter(|device_index| *device_index >= 0)
+                            .ok_or_else(|| {
+                                DiscoverError::MalformedArgument(
+                                    format!("device_index={}", value),
+                                    "Expected a non-negative number".to_string(),
+                                )
+                            })?,
+                    )
+                }
+                "role_arns" => role_arns = Some(value),
+                "regions" => regions = Some(Regions::try_from(value)?),
+                "on_error" => on_error = OnError::try_from(value)?,
+                "max_results" => {
+                    max_results = Some(
+                        value
+                            .parse::<i64>()
+                            .ok()
+                            .filter(|max_results| (5..=1000).contains(max_results))
+                            .ok_or_else(|| {
+                                DiscoverError::MalformedArgument(
+                                    format!("max_results={}", value),
+                                    "Expected a number between 5 and 1000".to_string(),
+                                )
+                            })?,
+                    )
+                }
+                "max_concurrency" => {
+                    max_concurrency = value
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|max_concurrency| *max_concurrency > 0)
+                        .ok_or_else(|| {
+                            DiscoverError::MalformedArgument(
+                                format!("max_concurrency={}", value),
+                                "Expected a positive number".to_string(),
+                            )
+                        })?
+                }
+                _ if key.starts_with("filter.") => {
+                    filters.push(parse_filter(&key["filter.".len()..], &value)?)
+                }
+                _ => {
+                    if !config.parse_arg(&key, &value)? {
+                        return Err(DiscoverError::UnexpectedArgument(key));
+                    }
+                }
             }
         }
+        let accounts = match role_arns {
+            Some(role_arns) => {
+                if config.role_arn().is_some() {
+                    return Err(DiscoverError::MalformedArgument(
+                        format!("role_arns={}", role_arns),
+                        "role_arns can not be combined with role_arn".to_string(),
+                    ));
+                }
+                let accounts = role_arns
+                    .split(',')
+                    .filter(|role_arn| !role_arn.is_empty())
+                    .map(|role_arn| config.with_role_arn(role_arn))
+                    .collect::<Vec<_>>();
+                if accounts.is_empty() {
+                    return Err(DiscoverError::MalformedArgument(
+                        format!("role_arns={}", role_arns),
+                        "Expected a comma separated list of role ARNs".to_string(),
+                    ));
+                }
+                for account in &accounts {
+                    account.validate(SERVICES)?;
+                }
+                accounts
+            }
+            None => {
+                config.validate(SERVICES)?;
+                Vec::new()
+            }
+        };
+        // The arguments are not ordered, sort the filters for stable requests
+        filters.sort_by(|a, b| a.name.cmp(&b.name));
 
-        let tag_key = tag_key.ok_or_else(|| DiscoverError::MissingArgument("tag_key".into()))?;
-        let tag_value =
-            tag_value.ok_or_else(|| DiscoverError::MissingArgument("tag_value".into()))?;
-
-        // https://rusoto.github.io/rusoto/rusoto_core/region/enum.Region.html
-        let region = region.unwrap_or_default();
+        let selector = match asg_name {
+            Some(asg_name) if tag_key.is_some() || tag_value.is_some() => {
+                return Err(DiscoverError::MalformedArgument(
+                    format!("asg_name={}", asg_name),
+                    "asg_name can not be combined with tag_key and tag_value".to_string(),
+                ))
+            }
+            Some(asg_name) => Selector::AutoScalingGroup(asg_name),
+            None if tag_key.is_none() && tag_value.is_none() && !filters.is_empty() => {
+                Selector::Filters
+            }
+            None => Selector::Tag {
+                key: tag_key.ok_or_else(|| DiscoverError::MissingArgument("tag_key".into()))?,
+                value: tag_value
+                    .ok_or_else(|| DiscoverError::MissingArgument("tag_value".into()))?,
+            },
+        };
 
         Ok(AWSProvider {
-            tag_key,
-            tag_value,
-            region,
+            selector,
+            filters,
+            config,
+            accounts,
+            regions,
+            on_error,
             addr_type,
+            device_index,
+            max_results,
+            max_concurrency,
         })
     }
 }
@@ -104,147 +362,554 @@ impl TryFrom<Vec<String>> for AWSProvider {
     }
 }
 
+fn interface_device_index(interface: &InstanceNetworkInterface) -> Option<i64> {
+    interface.attachment.as_ref()?.device_index
+}
+
+/// Returns the addresses of `addr_type` of an instance. When `device_index` is
+/// set, only the addresses of the network interface attached at that index are
+/// returned, otherwise those of the instance or of all its interfaces.
+fn instance_addrs(
+    instance: &Instance,
+    addr_type: &AddrType,
+    device_index: Option<i64>,
+) -> Vec<String> {
+    let mut interfaces = instance
+        .network_interfaces
+        .iter()
+        .flatten()
+        .filter(|interface| {
+            device_index.is_none() || interface_device_index(interface) == device_index
+        })
+        .collect::<Vec<_>>();
+    interfaces.sort_by_key(|interface| interface_device_index(interface));
+
+    let addrs: Vec<Option<String>> = match (addr_type, device_index) {
+        (AddrType::PrivateV4, None) => vec![instance.private_ip_address.clone()],
+        (AddrType::PrivateV4, Some(_)) => interfaces
+            .iter()
+            .map(|interface| interface.private_ip_address.clone())
+            .collect(),
+        (AddrType::PublicV4, None) => vec![instance.public_ip_address.clone()],
+        (AddrType::PublicV4, Some(_)) => interfaces
+            .iter()
+            .map(|interface| interface.association.as_ref()?.public_ip.clone())
+            .collect(),
+        (AddrType::PrivateDns, None) => vec![instance.private_dns_name.clone()],
+        (AddrType::PrivateDns, Some(_)) => interfaces
+            .iter()
+            .map(|interface| interface.private_dns_name.clone())
+            .collect(),
+        (AddrType::PublicDns, None) => vec![instance.public_dns_name.clone()],
+        (AddrType::PublicDns, Some(_)) => interfaces
+            .iter()
+            .map(|interface| interface.association.as_ref()?.public_dns_name.clone())
+            .collect(),
+        // An instance without network interfaces only has its primary address
+        (AddrType::AllPrivateV4, None) if interfaces.is_empty() => {
+            vec![instance.private_ip_address.clone()]
+        }
+        (AddrType::AllPrivateV4, _) => interfaces
+            .iter()
+            .flat_map(|interface| interface.private_ip_addresses.iter().flatten())
+            .map(|addr| addr.private_ip_address.clone())
+            .collect(),
+        // EC2 IPv6 addresses are global, both lists are the same
+        (AddrType::PrivateV6, _) | (AddrType::PublicV6, _) => interfaces
+            .iter()
+            .flat_map(|interface| interface.ipv_6_addresses.iter().flatten())
+            .map(|addr| addr.ipv_6_address.clone())
+            .collect(),
+    };
+
+    // Instances without a public DNS name have an empty one
+    addrs
+        .into_iter()
+        .flatten()
+        .filter(|addr| !addr.is_empty())
+        .collect()
+}
+
+/// Returns the ids of the instances that are `InService` in the groups.
+fn in_service_instance_ids(groups: Vec<AutoScalingGroup>) -> Vec<String> {
+    groups
+        .into_iter()
+        .flat_map(|group| {
+            let name = group.auto_scaling_group_name;
+            group
+                .instances
+                .unwrap_or_default()
+                .into_iter()
+                .filter(move |instance| {
+                    if instance.lifecycle_state != "InService" {
+                        debug!(
+                            "Skipping instance {} in {} with lifecycle state {}",
+                            instance.instance_id, name, instance.lifecycle_state
+                        );
+                    }
+                    instance.lifecycle_state == "InService"
+                })
+        })
+        .map(|instance| instance.instance_id)
+        .collect()
+}
+
 impl AWSProvider {
+    /// Empty when the instances are not selected by tag, see `selector`.
     pub fn tag_key(&self) -> &str {
-        &self.tag_key
+        match &self.selector {
+            Selector::Tag { key, .. } => key,
+            _ => "",
+        }
     }
 
+    /// Empty when the instances are not selected by tag, see `selector`.
     pub fn tag_value(&self) -> &str {
-        &self.tag_value
+        match &self.selector {
+            Selector::Tag { value, .. } => value,
+            _ => "",
+        }
+    }
+
+    pub fn selector(&self) -> &Selector {
+        &self.selector
+    }
+
+    pub fn asg_name(&self) -> Option<&str> {
+        match &self.selector {
+            Selector::AutoScalingGroup(name) => Some(name),
+            _ => None,
+        }
     }
 
+    /// The EC2 filters from the `filter.<name>` arguments, sorted by name.
+    pub fn filters(&self) -> &[Filter] {
+        &self.filters
+    }
+
+    /// The `region` argument, or else the region of the environment. Without
+    /// either this is provisional: us-east-1 until the first lookup has read
+    /// the region of the instance.
     pub fn region(&self) -> &Region {
-        &self.region
+        self.config.region()
+    }
+
+    /// The `region` argument, `None` when the region is detected.
+    pub fn region_arg(&self) -> Option<&Region> {
+        self.config.region_arg()
+    }
+
+    pub fn role_arn(&self) -> Option<&str> {
+        self.config.role_arn()
+    }
+
+    /// The roles of the accounts queried with `role_arns`.
+    pub fn role_arns(&self) -> Vec<&str> {
+        self.accounts
+            .iter()
+            .filter_map(|account| account.role_arn())
+            .collect()
+    }
+
+    /// The regions of a multi-region lookup, `None` when only `region` is queried.
+    pub fn regions(&self) -> Option<&Regions> {
+        self.regions.as_ref()
+    }
+
+    pub fn on_error(&self) -> OnError {
+        self.on_error
     }
 
     pub fn addr_type(&self) -> &AddrType {
         &self.addr_type
     }
 
-    async fn get_instances(&self) -> Result<DescribeInstancesResult, DiscoverError> {
-        let provider = ChainProvider::new();
-        let client = Ec2Client::new_with(HttpClient::new().unwrap(), provider, self.region.clone());
+    pub fn device_index(&self) -> Option<i64> {
+        self.device_index
+    }
+
+    pub fn max_results(&self) -> Option<i64> {
+        self.max_results
+    }
+
+    pub fn max_concurrency(&self) -> usize {
+        self.max_concurrency
+    }
+
+    async fn get_asg_instance_ids(
+        &self,
+        config: &AwsConfig,
+        asg_name: &str,
+    ) -> Result<Vec<String>, DiscoverError> {
+        let client = config
+            .client("autoscaling", AutoscalingClient::new_with)
+            .await?;
+
+        let input = AutoScalingGroupNamesType {
+            auto_scaling_group_names: Some(vec![asg_name.to_string()]),
+            ..Default::default()
+        };
+        let res = client
+            .describe_auto_scaling_groups(input)
+            .await
+            .map_err(|e| DiscoverError::ProviderRequestFailed(format!("{:?}", e)))?;
+
+        if res.auto_scaling_groups.is_empty() {
+            return Err(DiscoverError::ProviderRequestFailed(format!(
+                "Auto Scaling group {} not found",
+                asg_name
+            )));
+        }
+
+        Ok(in_service_instance_ids(res.auto_scaling_groups))
+    }
 
+    async fn get_instances(&self, config: &AwsConfig) -> Result<Vec<Reservation>, DiscoverError> {
         let mut input = DescribeInstancesRequest::default();
         let mut filters: Vec<Filter> = Vec::new();
-        filters.push(Filter {
-            name: Some(format!("tag:{}", self.tag_key.clone())),
-            values: Some(vec![self.tag_value.clone()]),
-        });
-        filters.push(Filter {
-            name: Some("instance-state-name".into()),
-            values: Some(vec!["running".into()]),
-        });
+
+        match &self.selector {
+            Selector::Tag { key, value } => {
+                debug!(
+                    "Using region={:?} tag_key={} tag_value={} addr_type={:?}",
+                    config.resolve_region().await,
+                    key,
+                    value,
+                    self.addr_type
+                );
+
+                filters.push(Filter {
+                    name: Some(format!("tag:{}", key)),
+                    values: Some(vec![value.clone()]),
+                });
+            }
+            Selector::AutoScalingGroup(asg_name) => {
+                debug!(
+                    "Using region={:?} asg_name={} addr_type={:?}",
+                    config.resolve_region().await,
+                    asg_name,
+                    self.addr_type
+                );
+
+                let instance_ids = self.get_asg_instance_ids(config, asg_name).await?;
+                debug!(
+                    "Auto Scaling group {} has {} instances in service",
+                    asg_name,
+                    instance_ids.len()
+                );
+                // An empty list of ids would describe every instance in the region
+                if instance_ids.is_empty() {
+                    return Ok(Vec::new());
+                }
+                // Unlike InstanceId.N, a filter ignores ids that no longer exist
+                filters.push(Filter {
+                    name: Some("instance-id".into()),
+                    values: Some(instance_ids),
+                });
+            }
+            Selector::Filters => {
+                debug!(
+                    "Using region={:?} addr_type={:?}",
+                    config.resolve_region().await,
+                    self.addr_type
+                );
+            }
+        }
+
+        debug!("Using filters={:?}", self.filters);
+        filters.extend(self.filters.iter().cloned());
+        let has_state_filter = self
+            .filters
+            .iter()
+            .any(|filter| filter.name.as_deref() == Some("instance-state-name"));
+        if !has_state_filter {
+            filters.push(Filter {
+                name: Some("instance-state-name".into()),
+                values: Some(vec!["running".into()]),
+            });
+        }
 
         input.filters = Some(filters);
+        input.max_results = self.max_results;
 
-        debug!(
-            "Using region={:?} tag_key={} tag_value={} addr_type={:?}",
-            self.region, self.tag_key, self.tag_value, self.addr_type
-        );
+        let client = config.client("ec2", Ec2Client::new_with).await?;
+
+        let mut reservations = Vec::new();
+        let mut page = 1;
+        loop {
+            // A failing page fails the whole lookup, a partial list of nodes
+            // would look like a valid one
+            let res = client
+                .describe_instances(input.clone())
+                .await
+                .map_err(|e| {
+                    DiscoverError::ProviderRequestFailed(format!(
+                        "DescribeInstances page {} failed: {:?}",
+                        page, e
+                    ))
+                })?;
+            reservations.extend(res.reservations.unwrap_or_default());
+
+            match res.next_token {
+                // A token that does not advance would page forever
+                Some(next_token) if input.next_token.as_ref() == Some(&next_token) => {
+                    return Err(DiscoverError::ProviderRequestFailed(format!(
+                        "DescribeInstances page {} returned the NextToken of page {}",
+                        page,
+                        page - 1
+                    )));
+                }
+                Some(next_token) if !next_token.is_empty() => {
+                    debug!("Fetching DescribeInstances page {}", page + 1);
+                    input.next_token = Some(next_token);
+                    page += 1;
+                }
+                _ => break,
+            }
+        }
+
+        Ok(reservations)
+    }
+
+    /// The addresses of the instances, according to `addr_type`. Instances
+    /// without an id are skipped, a partial or malformed response must not
+    /// return addresses of unknown instances.
+    fn reservation_addrs(&self, reservations: Vec<Reservation>) -> Vec<String> {
+        debug!("Found {} reservations", reservations.len());
 
-        client
-            .describe_instances(input)
+        let mut addrs = Vec::new();
+        for reservation in reservations {
+            // The id is only logged, the instances are valid without it
+            let reservation_id = reservation.reservation_id.as_deref().unwrap_or("unknown");
+            let instances = reservation.instances.unwrap_or_default();
+            debug!(
+                "Reservation {:?} has {} instances",
+                reservation_id,
+                instances.len()
+            );
+
+            for instance in instances {
+                let instance_id = match instance.instance_id.clone() {
+                    Some(instance_id) => instance_id,
+                    None => {
+                        warn!(
+                            "Skipping an instance without id in reservation {:?}",
+                            reservation_id
+                        );
+                        continue;
+                    }
+                };
+                debug!("Found instance {:?}", instance_id);
+
+                let instance_addrs = instance_addrs(&instance, &self.addr_type, self.device_index);
+                if instance_addrs.is_empty() {
+                    debug!(
+                        "Instance {:?} has no {:?} address",
+                        instance_id, self.addr_type
+                    );
+                }
+                for addr in instance_addrs {
+                    info!(
+                        "Instance {:?} has {:?} address {:?}",
+                        instance_id, self.addr_type, addr
+                    );
+                    addrs.push(addr);
+                }
+            }
+        }
+
+        addrs
+    }
+
+    /// The enabled regions of the account of `config`.
+    async fn get_all_regions(&self, config: &AwsConfig) -> Result<Vec<Region>, DiscoverError> {
+        let client = config.client("ec2", Ec2Client::new_with).await?;
+        let res = client
+            .describe_regions(DescribeRegionsRequest::default())
             .await
-            .map_err(|e| DiscoverError::ProviderRequestFailed(format!("{:?}", e)))
+            .map_err(|e| {
+                DiscoverError::ProviderRequestFailed(format!("DescribeRegions failed: {:?}", e))
+            })?;
+
+        Ok(res
+            .regions
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|region| region.region_name)
+            .filter_map(|name| match Region::from_str(&name) {
+                Ok(region) => Some(region),
+                Err(_) => {
+                    warn!("Skipping unknown region {}", name);
+                    None
+                }
+            })
+            .collect())
+    }
+
+    async fn get_region_nodes(&self, config: &AwsConfig) -> Result<Vec<Node>, DiscoverError> {
+        let reservations = self.get_instances(config).await?;
+        let region = config.resolve_region().await.name().to_string();
+
+        Ok(self
+            .reservation_addrs(reservations)
+            .into_iter()
+            .map(|addr| Node {
+                addr,
+                region: region.clone(),
+            })
+            .collect())
+    }
+
+    /// Runs the lookup in every region of `regions`, or only in `region`. Each
+    /// region holds a permit of `semaphore` while it is queried.
+    async fn get_account_nodes(
+        &self,
+        config: &AwsConfig,
+        semaphore: &Arc<Semaphore>,
+    ) -> Result<Vec<Node>, DiscoverError> {
+        let regions = match &self.regions {
+            None => {
+                let _permit = semaphore.acquire().await.map_err(|e| {
+                    DiscoverError::ProviderRequestFailed(format!("Lookup failed: {}", e))
+                })?;
+                return self.get_region_nodes(config).await;
+            }
+            Some(Regions::List(regions)) => regions
+                .iter()
+                .map(|region| config.with_region(region.clone()))
+                .collect::<Vec<_>>(),
+            Some(Regions::All) => self
+                .get_all_regions(config)
+                .await?
+                .into_iter()
+                .map(|region| config.with_region(region))
+                .collect(),
+        };
+        debug!("Querying {} regions", regions.len());
+
+        let lookups = regions
+            .iter()
+            .map(|config| {
+                let provider = self.clone();
+                let config = config.clone();
+                let semaphore = semaphore.clone();
+                tokio::spawn(async move {
+                    let _permit = semaphore.acquire_owned().await.map_err(|e| {
+                        DiscoverError::ProviderRequestFailed(format!("Lookup failed: {}", e))
+                    })?;
+                    provider.get_region_nodes(&config).await
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut nodes = Vec::new();
+        let mut failures = Vec::new();
+        for (config, lookup) in regions.iter().zip(lookups) {
+            let region = config.resolve_region().await.name().to_string();
+            match lookup.await {
+                Ok(Ok(region_nodes)) => {
+                    debug!("Found {} addresses in {}", region_nodes.len(), region);
+                    nodes.extend(region_nodes);
+                }
+                Ok(Err(e)) => failures.push((region, e)),
+                Err(e) => failures.push((
+                    region,
+                    DiscoverError::ProviderRequestFailed(format!("Lookup failed: {}", e)),
+                )),
+            }
+        }
+
+        if failures.is_empty() {
+            return Ok(nodes);
+        }
+        // Partial results are only returned when at least one region answered
+        if self.on_error == OnError::Partial && failures.len() < regions.len() {
+            for (region, e) in failures {
+                warn!("Skipping region {}: {}", region, e);
+            }
+            return Ok(nodes);
+        }
+
+        Err(DiscoverError::RegionsFailed(failures))
+    }
+
+    /// Runs the lookup in the account of every role of `role_arns` concurrently,
+    /// and merges their nodes. The accounts share the permits of `semaphore`.
+    async fn get_accounts_nodes(
+        &self,
+        semaphore: &Arc<Semaphore>,
+    ) -> Result<Vec<Node>, DiscoverError> {
+        let lookups = self
+            .accounts
+            .iter()
+            .map(|config| {
+                let provider = self.clone();
+                let config = config.clone();
+                let semaphore = semaphore.clone();
+                tokio::spawn(async move { provider.get_account_nodes(&config, &semaphore).await })
+            })
+            .collect::<Vec<_>>();
+
+        let mut nodes = Vec::new();
+        let mut failures = Vec::new();
+        for (config, lookup) in self.accounts.iter().zip(lookups) {
+            let role_arn = config.role_arn().unwrap_or_default().to_string();
+            match lookup.await {
+                Ok(Ok(account_nodes)) => {
+                    debug!("Found {} addresses with {}", account_nodes.len(), role_arn);
+                    nodes.extend(account_nodes);
+                }
+                Ok(Err(e)) => failures.push((role_arn, e)),
+                Err(e) => failures.push((
+                    role_arn,
+                    DiscoverError::ProviderRequestFailed(format!("Lookup failed: {}", e)),
+                )),
+            }
+        }
+        if failures.is_empty() {
+            return Ok(nodes);
+        }
+        // Unless on_error=partial, one failing account fails the lookup, the
+        // nodes of the other accounts would look like a complete list
+        if self.on_error == OnError::Partial && failures.len() < self.accounts.len() {
+            for (role_arn, e) in failures {
+                warn!("Skipping account of {}: {}", role_arn, e);
+            }
+            return Ok(nodes);
+        }
+
+        Err(DiscoverError::AccountsFailed(failures))
+    }
+
+    /// The addresses found by the provider with the region of their instance,
+    /// e.g. to tell the nodes of a multi-region lookup apart.
+    pub async fn nodes(&self) -> Result<Vec<Node>, DiscoverError> {
+        let semaphore = Arc::new(Semaphore::new(self.max_concurrency));
+        let mut nodes = if self.accounts.is_empty() {
+            self.get_account_nodes(&self.config, &semaphore).await?
+        } else {
+            self.get_accounts_nodes(&semaphore).await?
+        };
+
+        // An instance shared with several of the accounts is returned once
+        let mut seen = HashSet::new();
+        nodes.retain(|node| seen.insert(node.clone()));
+
+        Ok(nodes)
     }
 }
 
 #[async_trait::async_trait]
 impl Provider for AWSProvider {
     async fn addrs(&self) -> Result<Vec<String>, DiscoverError> {
-        let res = self.get_instances().await?;
-        let reservations = res.reservations.unwrap_or_default();
-        debug!("Found {} reservations", reservations.len());
+        let nodes = self.nodes().await?;
 
-        let addrs = reservations
+        let mut seen = HashSet::new();
+        let addrs = nodes
             .into_iter()
-            .map(|reservation| {
-                let reservation_id = reservation.reservation_id.clone()
-			.expect("Reservation to have a reservation id");
-                let instances = reservation.instances.unwrap_or_default();
-                debug!(
-                    "Reservation {:?} has {} instances",
-                    reservation_id,
-                    instances.len()
-                );
-                instances
-                    .into_iter()
-                    .filter_map(|instance| {
-                        let instance_id = instance.instance_id.clone()
-				.expect("Instance to have an instance id");
-                        debug!("Found instance {:?}", instance_id);
-
-                        match self.addr_type {
-                            AddrType::PrivateV4 => match instance.private_ip_address {
-                                Some(addr) => {
-                                    info!("Instance {:?} has private ip {:?}", instance_id, addr);
-
-                                    Some(vec![addr])
-                                }
-                                None => {
-                                    debug!("Instance {:?} has no private ip", instance_id);
-                                    None
-                                }
-                            },
-                            AddrType::PublicV4 => match instance.public_ip_address {
-                                Some(addr) => {
-                                    info!("Instance {:?} has public ip {:?}", instance_id, addr);
-
-                                    Some(vec![addr])
-                                }
-                                None => {
-                                    debug!("Instance {:?} has no public ip", instance_id);
-                                    None
-                                }
-                            },
-                            AddrType::PublicV6 => {
-                                let network_interfaces =
-                                    instance.network_interfaces.unwrap_or_default();
-                                debug!(
-                                    "Instance {:?} has {} network interfaces",
-                                    instance_id,
-                                    network_interfaces.len()
-                                );
-
-                                Some(
-                                    network_interfaces
-                                        .into_iter()
-                                        .filter_map(|i| {
-						let network_interface_id = i.network_interface_id.clone();
-                                            debug!(
-                                                "Checking NetworInterfaceId {:?} on Instance {:?}",
-                                                network_interface_id, instance_id
-                                            );
-
-                                            if i.ipv_6_addresses.is_none() {
-                                                debug!(
-                                            "Instance {:?} has no IPv6 on NetworkInterfaceId {:?}",
-                                            instance_id, network_interface_id
-                                        );
-                                            }
-
-                                            i.ipv_6_addresses.map(|ipv6| {
-                                                ipv6.into_iter()
-                                                    .filter_map(|ipv6| {
-                                                        info!("Instance {:?} has IPv6 {:?} on NetworkInterfaceId {:?}", instance_id, ipv6.ipv_6_address, network_interface_id);
-
-                                                        ipv6.ipv_6_address
-                                                    })
-                                                    .collect::<Vec<_>>()
-                                            })
-                                        })
-					.flatten()
-                                        .collect::<Vec<_>>(),
-                                )
-                            }
-                        }
-                    })
-                    .flatten()
-                    .collect::<Vec<_>>()
-            })
-            .flatten()
-            .collect::<Vec<_>>();
+            .map(|node| node.addr)
+            .filter(|addr| seen.insert(addr.clone()))
+            .collect();
 
         Ok(addrs)
     }
@@ -253,16 +918,64 @@ impl Provider for AWSProvider {
         "Amazon AWS:
 
 	provider:          \"aws\"
-	region:            The AWS region. Default to region of instance.
+	region:            The AWS region. Defaults to AWS_DEFAULT_REGION or AWS_REGION, or else to
+	                   the region of the instance from the instance metadata (IMDSv2).
 	tag_key:           The tag key to filter on
 	tag_value:         The tag value to filter on
-	addr_type:         \"private_v4\", \"public_v4\" or \"public_v6\". Defaults to \"private_v4\".
+	asg_name:          The Auto Scaling group to return the InService instances of, instead
+	                   of filtering on tag_key and tag_value
+	addr_type:         \"private_v4\", \"all_private_v4\", \"public_v4\", \"private_v6\",
+	                   \"public_v6\", \"private_dns\" or \"public_dns\". Defaults to \"private_v4\".
+	                   all_private_v4 returns the secondary addresses of every network
+	                   interface too. private_v6 and public_v6 both return the IPv6
+	                   addresses of the network interfaces.
+	device_index:      Only return the addresses of the network interface attached at this
+	                   device index, e.g. 1 for the second interface
+	filter.<name>:     Comma separated values of an EC2 filter, e.g. filter.tag:env=prod,staging
+	                   or filter.vpc-id=vpc-0a1b2c. Supported filters are tag:<key>, tag-key,
+	                   availability-zone, image-id, instance-id, instance-state-name,
+	                   instance-type, instance.group-id, instance.group-name,
+	                   private-ip-address, subnet-id and vpc-id. Only running instances are
+	                   returned unless filter.instance-state-name is set.
+	max_results:       Page size of DescribeInstances, between 5 and 1000. Every page is
+	                   fetched. Defaults to the EC2 default.
 	access_key_id:     The AWS access key to use
 	secret_access_key: The AWS secret access key to use
+	session_token:     The session token of temporary credentials
+	regions:           Comma separated regions to query concurrently instead of region, or
+	                   \"all\" for every region enabled for the account. region is then
+	                   only used to call DescribeRegions.
+	on_error:          \"fail\" or \"partial\". Whether a failing region or account fails the
+	                   lookup, or the addresses of the other regions and accounts are
+	                   returned. Defaults to \"fail\".
+	max_concurrency:   How many regions are queried at the same time, over all accounts of
+	                   role_arns. Defaults to 4.
+	endpoint:          The URL the EC2 requests are sent to instead of the AWS endpoint,
+	                   e.g. a VPC interface endpoint or a local mock
+	sts_endpoint:      The URL the STS requests of role_arn and role_arns are sent to
+	autoscaling_endpoint:
+	                   The URL the Auto Scaling requests of asg_name are sent to
+	fips:              \"true\" to use the FIPS endpoints. Defaults to \"false\". The China
+	                   regions have none.
+	dualstack:         \"true\" to use the IPv4 and IPv6 endpoints. Defaults to \"false\".
+	role_arn:          The IAM role to assume with the credentials, e.g. of another account
+	role_arns:         Comma separated IAM roles, e.g. one per account. Each role is assumed
+	                   and queried concurrently, and the addresses are merged. The lookup
+	                   fails with the error of every failing role, see on_error.
+	external_id:       The external ID the role's trust policy requires
+	role_session_name: The session name of the assumed role. Defaults to \"node-discover\".
+	duration:          How long the assumed role credentials are valid, between 900s and
+	                   12h. Defaults to 1h. They are refreshed before they expire.
 
-	The only required IAM permission is 'ec2:DescribeInstances'. If the Consul agent is
+	The only required IAM permission is 'ec2:DescribeInstances', plus
+	'autoscaling:DescribeAutoScalingGroups' when asg_name is used and 'ec2:DescribeRegions'
+	with regions=all. If the Consul agent is
 	running on AWS instance it is recommended you use an IAM role, otherwise it is
 	recommended you make a dedicated IAM user and access key used only for auto-joining.
+	Without access_key_id and secret_access_key the credentials are read from the
+	environment, a web identity token (AWS_WEB_IDENTITY_TOKEN_FILE and AWS_ROLE_ARN),
+	the shared credentials file, the ECS container or the instance profile. With role_arn
+	the 'sts:AssumeRole' permission on the role is required as well.
 	"
     }
 }
@@ -270,9 +983,49 @@ impl Provider for AWSProvider {
 #[cfg(test)]
 mod test {
     use crate::aws::{AWSProvider, AddrType};
+    use crate::providers::stub::{self, StubResponse};
+    use rusoto_core::credential::StaticProvider;
+    use rusoto_ec2::DescribeInstancesResult;
 
     use super::*;
 
+    const DESCRIBE_INSTANCES: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<DescribeInstancesResponse xmlns="http://ec2.amazonaws.com/doc/2016-11-15/">
+    <requestId>8f7724cf-496f-496e-8fe3-example</requestId>
+    <reservationSet>
+        <item>
+            <reservationId>r-1234567890abcdef0</reservationId>
+            <instancesSet>
+                <item>
+                    <instanceId>i-1234567890abcdef0</instanceId>
+                    <privateIpAddress>10.0.0.12</privateIpAddress>
+                </item>
+                <item>
+                    <instanceId>i-1234567890abcdef1</instanceId>
+                    <privateIpAddress>10.0.0.13</privateIpAddress>
+                </item>
+            </instancesSet>
+        </item>
+    </reservationSet>
+</DescribeInstancesResponse>"#;
+
+    async fn ec2_stub() -> stub::Stub {
+        stub::serve_tcp(|_| StubResponse::new(200, "text/xml", DESCRIBE_INSTANCES)).await
+    }
+
+    fn provider_for(stub: &stub::Stub, args: &str) -> AWSProvider {
+        let mut args = format!(
+            "{} endpoint={url} sts_endpoint={url} autoscaling_endpoint={url}",
+            args,
+            url = stub.url()
+        );
+        // Skips the lookup of the region in the instance metadata
+        if !args.contains(" region=") {
+            args.push_str(" region=us-east-1");
+        }
+        AWSProvider::try_from(ParsedArgs::try_from(args).unwrap()).unwrap()
+    }
+
     #[test]
     fn aws_provider_from_string() {
         let tag_key = "Name";
@@ -334,5 +1087,1076 @@ mod test {
         );
     }
 
+    #[test]
+    fn aws_provider_with_asg_name() {
+        let args = "provider=aws region=eu-west-1 asg_name=consul-servers";
+
+        let res = AWSProvider::try_from(ParsedArgs::try_from(args.to_string()).unwrap());
+        assert!(res.is_ok());
+        let provider = res.unwrap();
+        assert_eq!(provider.asg_name(), Some("consul-servers"));
+        assert_eq!(
+            provider.selector(),
+            &Selector::AutoScalingGroup("consul-servers".to_string())
+        );
+        assert_eq!(provider.tag_key(), "");
+        assert_eq!(provider.tag_value(), "");
+
+        let args = "provider=aws asg_name=consul-servers tag_key=consul tag_value=server";
+        let res = AWSProvider::try_from(ParsedArgs::try_from(args.to_string()).unwrap());
+        assert!(matches!(
+            res.unwrap_err(),
+            DiscoverError::MalformedArgument(arg, _) if arg == "asg_name=consul-servers"
+        ));
+
+        let res = AWSProvider::try_from(ParsedArgs::try_from("provider=aws".to_string()).unwrap());
+        assert_eq!(
+            res.unwrap_err(),
+            DiscoverError::MissingArgument("tag_key".to_string())
+        );
+    }
+
+    #[test]
+    fn keep_in_service_asg_instances() {
+        let instance = |id: &str, state: &str| rusoto_autoscaling::Instance {
+            instance_id: id.to_string(),
+            lifecycle_state: state.to_string(),
+            ..Default::default()
+        };
+        let groups = vec![AutoScalingGroup {
+            auto_scaling_group_name: "consul-servers".to_string(),
+            instances: Some(vec![
+                instance("i-1", "InService"),
+                instance("i-2", "Pending"),
+                instance("i-3", "Terminating"),
+                instance("i-4", "InService"),
+            ]),
+            ..Default::default()
+        }];
+
+        assert_eq!(
+            in_service_instance_ids(groups),
+            vec!["i-1".to_string(), "i-4".to_string()]
+        );
+        assert!(in_service_instance_ids(vec![AutoScalingGroup::default()]).is_empty());
+    }
+
+    const DESCRIBE_AUTO_SCALING_GROUPS: &str = r#"<DescribeAutoScalingGroupsResponse xmlns="http://autoscaling.amazonaws.com/doc/2011-01-01/">
+    <DescribeAutoScalingGroupsResult>
+        <AutoScalingGroups>
+            <member>
+                <AutoScalingGroupName>consul-servers</AutoScalingGroupName>
+                <Instances>
+                    <member>
+                        <InstanceId>i-0a1b2c3d4e5f60001</InstanceId>
+                        <LifecycleState>InService</LifecycleState>
+                    </member>
+                    <member>
+                        <InstanceId>i-0a1b2c3d4e5f60002</InstanceId>
+                        <LifecycleState>Pending</LifecycleState>
+                    </member>
+                    <member>
+                        <InstanceId>i-0a1b2c3d4e5f60003</InstanceId>
+                        <LifecycleState>InService</LifecycleState>
+                    </member>
+                </Instances>
+            </member>
+        </AutoScalingGroups>
+    </DescribeAutoScalingGroupsResult>
+    <ResponseMetadata>
+        <RequestId>1549581b-12b7-11e3-895e-1334aEXAMPLE</RequestId>
+    </ResponseMetadata>
+</DescribeAutoScalingGroupsResponse>"#;
+
+    #[tokio::test]
+    async fn filter_on_asg_instance_ids() {
+        let stub = stub::serve_tcp(|req| {
+            let body = if req.body.contains("Action=DescribeAutoScalingGroups") {
+                DESCRIBE_AUTO_SCALING_GROUPS
+            } else {
+                DESCRIBE_INSTANCES
+            };
+            StubResponse::new(200, "text/xml", body)
+        })
+        .await;
+        let provider = provider_for(
+            &stub,
+            "provider=aws asg_name=consul-servers max_results=5 access_key_id=AKIDSTATIC secret_access_key=secret",
+        );
+        assert_eq!(
+            provider.addrs().await,
+            Ok(vec!["10.0.0.12".to_string(), "10.0.0.13".to_string()])
+        );
+
+        let requests = stub.requests();
+        let describe_instances = requests
+            .iter()
+            .find(|req| req.body.contains("Action=DescribeInstances"))
+            .unwrap();
+        assert!(describe_instances
+            .body
+            .contains("Filter.1.Name=instance-id&Filter.1.Value.1=i-0a1b2c3d4e5f60001&Filter.1.Value.2=i-0a1b2c3d4e5f60003"));
+        assert!(!describe_instances.body.contains("InstanceId."));
+        assert!(describe_instances.body.contains("MaxResults=5"));
+    }
+
+    #[tokio::test]
+    async fn sign_requests_with_static_credentials() {
+        let stub = ec2_stub().await;
+        let provider = provider_for(
+            &stub,
+            "provider=aws tag_key=consul tag_value=server access_key_id=AKIDSTATIC secret_access_key=secret session_token=token",
+        );
+
+        assert_eq!(
+            provider.addrs().await,
+            Ok(vec!["10.0.0.12".to_string(), "10.0.0.13".to_string()])
+        );
+
+        let requests = stub.requests();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].body.contains("Action=DescribeInstances"));
+        assert!(requests[0].body.contains("Filter.1.Name=tag%3Aconsul"));
+        let authorization = requests[0].header("authorization").unwrap();
+        assert!(
+            authorization.contains("Credential=AKIDSTATIC/"),
+            "{}",
+            authorization
+        );
+        assert_eq!(requests[0].header("x-amz-security-token"), Some("token"));
+    }
+
+    #[tokio::test]
+    async fn sign_requests_with_the_default_credentials() {
+        let stub = ec2_stub().await;
+        let mut provider = provider_for(&stub, "provider=aws tag_key=consul tag_value=server");
+        // Stands in for the default chain, which reads the environment
+        provider.config = provider
+            .config
+            .with_default_credentials(StaticProvider::new_minimal(
+                "AKIDCHAIN".to_string(),
+                "secret".to_string(),
+            ));
+        assert_eq!(
+            provider.addrs().await,
+            Ok(vec!["10.0.0.12".to_string(), "10.0.0.13".to_string()])
+        );
+
+        let requests = stub.requests();
+        let authorization = requests[0].header("authorization").unwrap();
+        assert!(
+            authorization.contains("Credential=AKIDCHAIN/"),
+            "{}",
+            authorization
+        );
+        assert_eq!(requests[0].header("x-amz-security-token"), None);
+    }
+
+    #[test]
+    fn fail_on_incomplete_static_credentials() {
+        let args = "provider=aws tag_key=consul tag_value=server access_key_id=AKIDSTATIC";
+
+        let res = AWSProvider::try_from(ParsedArgs::try_from(args.to_string()).unwrap());
+        assert_eq!(
+            res.unwrap_err(),
+            DiscoverError::MissingArgument("secret_access_key".to_string())
+        );
+    }
+
+    fn assume_role_response(access_key_id: &str, expiration: &str) -> String {
+        format!(
+            r#"<AssumeRoleResponse xmlns="https://sts.amazonaws.com/doc/2011-06-15/">
+    <AssumeRoleResult>
+        <Credentials>
+            <AccessKeyId>{}</AccessKeyId>
+            <SecretAccessKey>secret</SecretAccessKey>
+            <SessionToken>assumed-token</SessionToken>
+            <Expiration>{}</Expiration>
+        </Credentials>
+        <AssumedRoleUser>
+            <AssumedRoleId>AROAEXAMPLE:node-discover</AssumedRoleId>
+            <Arn>arn:aws:sts::123456789012:assumed-role/consul/node-discover</Arn>
+        </AssumedRoleUser>
+    </AssumeRoleResult>
+    <ResponseMetadata>
+        <RequestId>c6104cbe-af31-11e0-8154-example</RequestId>
+    </ResponseMetadata>
+</AssumeRoleResponse>"#,
+            access_key_id, expiration
+        )
+    }
+
+    async fn sts_and_ec2_stub(expiration: &'static str) -> stub::Stub {
+        stub::serve_tcp(move |req| {
+            if req.body.contains("Action=AssumeRole") {
+                StubResponse::new(
+                    200,
+                    "text/xml",
+                    &assume_role_response("ASIAASSUMED", expiration),
+                )
+            } else {
+                StubResponse::new(200, "text/xml", DESCRIBE_INSTANCES)
+            }
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn sign_requests_with_the_assumed_role() {
+        let stub = sts_and_ec2_stub("2099-01-01T00:00:00Z").await;
+        let provider = provider_for(
+            &stub,
+            "provider=aws tag_key=consul tag_value=server access_key_id=AKIDBASE secret_access_key=secret role_arn=arn:aws:iam::123456789012:role/consul external_id=shared-services duration=2h",
+        );
+        assert_eq!(
+            provider.role_arn(),
+            Some("arn:aws:iam::123456789012:role/consul")
+        );
+
+        for _ in 0..2 {
+            assert_eq!(
+                provider.addrs().await,
+                Ok(vec!["10.0.0.12".to_string(), "10.0.0.13".to_string()])
+            );
+        }
+
+        // The role is assumed once, its credentials are reused by the second call.
+        let requests = stub.requests();
+        assert_eq!(requests.len(), 3);
+        assert!(requests[0].body.contains("Action=AssumeRole"));
+        assert!(requests[0]
+            .body
+            .contains("RoleArn=arn%3Aaws%3Aiam%3A%3A123456789012%3Arole%2Fconsul"));
+        assert!(requests[0].body.contains("ExternalId=shared-services"));
+        assert!(requests[0].body.contains("RoleSessionName=node-discover"));
+        assert!(requests[0].body.contains("DurationSeconds=7200"));
+        assert!(requests[0]
+            .header("authorization")
+            .unwrap()
+            .contains("Credential=AKIDBASE/"));
+
+        for request in &requests[1..] {
+            assert!(request.body.contains("Action=DescribeInstances"));
+            assert!(request
+                .header("authorization")
+                .unwrap()
+                .contains("Credential=ASIAASSUMED/"));
+            assert_eq!(
+                request.header("x-amz-security-token"),
+                Some("assumed-token")
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn refresh_expired_role_credentials() {
+        let stub = sts_and_ec2_stub("2000-01-01T00:00:00Z").await;
+        let provider = provider_for(
+            &stub,
+            "provider=aws tag_key=consul tag_value=server access_key_id=AKIDBASE secret_access_key=secret role_arn=arn:aws:iam::123456789012:role/consul",
+        );
+
+        for _ in 0..2 {
+            assert!(provider.addrs().await.is_ok());
+        }
+
+        let actions = stub
+            .requests()
+            .iter()
+            .map(|req| req.body.split('&').next().unwrap().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            actions,
+            vec![
+                "Action=AssumeRole",
+                "Action=DescribeInstances",
+                "Action=AssumeRole",
+                "Action=DescribeInstances"
+            ]
+        );
+    }
+
+    #[test]
+    fn fail_on_role_options_without_role_arn() {
+        let args = "provider=aws tag_key=consul tag_value=server external_id=shared-services";
+
+        let res = AWSProvider::try_from(ParsedArgs::try_from(args.to_string()).unwrap());
+        assert_eq!(
+            res.unwrap_err(),
+            DiscoverError::MissingArgument("role_arn".to_string())
+        );
+    }
+
+    #[test]
+    fn aws_provider_with_role_arns() {
+        let args = "provider=aws tag_key=consul tag_value=server role_arns=arn:aws:iam::111111111111:role/consul,arn:aws:iam::222222222222:role/consul external_id=shared-services";
+
+        let provider =
+            AWSProvider::try_from(ParsedArgs::try_from(args.to_string()).unwrap()).unwrap();
+        assert_eq!(
+            provider.role_arns(),
+            vec![
+                "arn:aws:iam::111111111111:role/consul",
+                "arn:aws:iam::222222222222:role/consul"
+            ]
+        );
+        assert_eq!(provider.role_arn(), None);
+
+        let args = "provider=aws tag_key=consul tag_value=server role_arns=arn:aws:iam::111111111111:role/consul role_arn=arn:aws:iam::222222222222:role/consul";
+        let res = AWSProvider::try_from(ParsedArgs::try_from(args.to_string()).unwrap());
+        assert!(matches!(
+            res.unwrap_err(),
+            DiscoverError::MalformedArgument(arg, _) if arg.starts_with("role_arns=")
+        ));
+    }
+
+    /// Account 1 has 10.0.0.12 and 10.0.0.13, account 2 has 10.0.0.14 and shares
+    /// the instances of account 1, account 3 can not be assumed.
+    async fn accounts_stub() -> stub::Stub {
+        stub::serve_tcp(|req| {
+            if req.body.contains("Action=AssumeRole") {
+                for account in &["111111111111", "222222222222"] {
+                    if req.body.contains(account) {
+                        let access_key_id = format!("ASIA{}", account);
+                        return StubResponse::new(
+                            200,
+                            "text/xml",
+                            &assume_role_response(&access_key_id, "2099-01-01T00:00:00Z"),
+                        );
+                    }
+                }
+                return StubResponse::new(
+                    403,
+                    "text/xml",
+                    r#"<ErrorResponse xmlns="https://sts.amazonaws.com/doc/2011-06-15/">
+    <Error>
+        <Type>Sender</Type>
+        <Code>AccessDenied</Code>
+        <Message>Not authorized to perform sts:AssumeRole</Message>
+    </Error>
+    <RequestId>c6104cbe-af31-11e0-8154-example</RequestId>
+</ErrorResponse>"#,
+                );
+            }
+
+            let authorization = req.header("authorization").unwrap_or_default();
+            let body = if authorization.contains("Credential=ASIA222222222222/")
+                && !req.body.contains("NextToken=page-2")
+            {
+                DESCRIBE_INSTANCES_PAGE
+            } else {
+                DESCRIBE_INSTANCES
+            };
+            StubResponse::new(200, "text/xml", body)
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn merge_the_addresses_of_every_account() {
+        let stub = accounts_stub().await;
+        let provider = provider_for(
+            &stub,
+            "provider=aws tag_key=consul tag_value=server access_key_id=AKIDBASE secret_access_key=secret role_arns=arn:aws:iam::111111111111:role/consul,arn:aws:iam::222222222222:role/consul",
+        );
+
+        assert_eq!(
+            provider.addrs().await,
+            Ok(vec![
+                "10.0.0.12".to_string(),
+                "10.0.0.13".to_string(),
+                "10.0.0.14".to_string()
+            ])
+        );
+        let assume_role_requests = stub
+            .requests()
+            .iter()
+            .filter(|req| req.body.contains("Action=AssumeRole"))
+            .count();
+        assert_eq!(assume_role_requests, 2);
+    }
+
+    #[tokio::test]
+    async fn report_the_failing_accounts() {
+        let stub = accounts_stub().await;
+        let provider = provider_for(
+            &stub,
+            "provider=aws tag_key=consul tag_value=server access_key_id=AKIDBASE secret_access_key=secret role_arns=arn:aws:iam::111111111111:role/consul,arn:aws:iam::333333333333:role/consul",
+        );
+
+        match provider.addrs().await {
+            Err(DiscoverError::AccountsFailed(failures)) => {
+                assert_eq!(failures.len(), 1);
+                assert_eq!(failures[0].0, "arn:aws:iam::333333333333:role/consul");
+                assert!(
+                    matches!(&failures[0].1, DiscoverError::ProviderRequestFailed(e) if e.contains("AccessDenied")),
+                    "{:?}",
+                    failures[0].1
+                );
+            }
+            res => panic!("Expected AccountsFailed, got {:?}", res),
+        }
+    }
+
+    #[tokio::test]
+    async fn skip_the_failing_accounts_with_partial() {
+        let stub = accounts_stub().await;
+        let provider = provider_for(
+            &stub,
+            "provider=aws tag_key=consul tag_value=server on_error=partial access_key_id=AKIDBASE secret_access_key=secret role_arns=arn:aws:iam::111111111111:role/consul,arn:aws:iam::333333333333:role/consul",
+        );
+        assert_eq!(
+            provider.addrs().await,
+            Ok(vec!["10.0.0.12".to_string(), "10.0.0.13".to_string()])
+        );
+
+        // Partial results are only returned when at least one account answered
+        let provider = provider_for(
+            &stub,
+            "provider=aws tag_key=consul tag_value=server on_error=partial access_key_id=AKIDBASE secret_access_key=secret role_arns=arn:aws:iam::333333333333:role/consul,arn:aws:iam::444444444444:role/consul",
+        );
+        match provider.addrs().await {
+            Err(DiscoverError::AccountsFailed(failures)) => assert_eq!(failures.len(), 2),
+            res => panic!("Expected AccountsFailed, got {:?}", res),
+        }
+    }
+
+    const DESCRIBE_INSTANCES_PAGE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<DescribeInstancesResponse xmlns="http://ec2.amazonaws.com/doc/2016-11-15/">
+    <requestId>8f7724cf-496f-496e-8fe3-example</requestId>
+    <reservationSet>
+        <item>
+            <reservationId>r-1234567890abcdef1</reservationId>
+            <instancesSet>
+                <item>
+                    <instanceId>i-1234567890abcdef2</instanceId>
+                    <privateIpAddress>10.0.0.14</privateIpAddress>
+                </item>
+            </instancesSet>
+        </item>
+    </reservationSet>
+    <nextToken>page-2</nextToken>
+</DescribeInstancesResponse>"#;
+
+    #[tokio::test]
+    async fn follow_next_token() {
+        let stub = stub::serve_tcp(|req| {
+            let body = if req.body.contains("NextToken=page-2") {
+                DESCRIBE_INSTANCES
+            } else {
+                DESCRIBE_INSTANCES_PAGE
+            };
+            StubResponse::new(200, "text/xml", body)
+        })
+        .await;
+        let provider = provider_for(
+            &stub,
+            "provider=aws tag_key=consul tag_value=server max_results=5 access_key_id=AKIDSTATIC secret_access_key=secret",
+        );
+
+        assert_eq!(
+            provider.addrs().await,
+            Ok(vec![
+                "10.0.0.14".to_string(),
+                "10.0.0.12".to_string(),
+                "10.0.0.13".to_string()
+            ])
+        );
+
+        let requests = stub.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests.iter().all(|req| req.body.contains("MaxResults=5")));
+        assert!(!requests[0].body.contains("NextToken"));
+    }
+
+    #[tokio::test]
+    async fn fail_when_a_later_page_fails() {
+        let stub = stub::serve_tcp(|req| {
+            if req.body.contains("NextToken=page-2") {
+                StubResponse::new(
+                    503,
+                    "text/xml",
+                    "<Response><Errors><Error><Code>Unavailable</Code><Message>Service unavailable</Message></Error></Errors></Response>",
+                )
+            } else {
+                StubResponse::new(200, "text/xml", DESCRIBE_INSTANCES_PAGE)
+            }
+        })
+        .await;
+        let provider = provider_for(
+            &stub,
+            "provider=aws tag_key=consul tag_value=server access_key_id=AKIDSTATIC secret_access_key=secret",
+        );
+
+        let res = provider.addrs().await;
+        assert!(
+            matches!(&res, Err(DiscoverError::ProviderRequestFailed(e)) if e.contains("page 2")),
+            "{:?}",
+            res
+        );
+    }
+
+    #[tokio::test]
+    async fn fail_when_the_next_token_repeats() {
+        let stub =
+            stub::serve_tcp(|_| StubResponse::new(200, "text/xml", DESCRIBE_INSTANCES_PAGE)).await;
+        let provider = provider_for(
+            &stub,
+            "provider=aws tag_key=consul tag_value=server access_key_id=AKIDSTATIC secret_access_key=secret",
+        );
+
+        let res = provider.addrs().await;
+        assert!(
+            matches!(&res, Err(DiscoverError::ProviderRequestFailed(e)) if e.contains("NextToken")),
+            "{:?}",
+            res
+        );
+        assert_eq!(stub.requests().len(), 2);
+    }
+
+    #[test]
+    fn fail_on_invalid_max_results() {
+        for max_results in &["4", "1001", "many"] {
+            let args = format!(
+                "provider=aws tag_key=consul tag_value=server max_results={}",
+                max_results
+            );
+            let res = AWSProvider::try_from(ParsedArgs::try_from(args).unwrap());
+            assert_eq!(
+                res.unwrap_err(),
+                DiscoverError::MalformedArgument(
+                    format!("max_results={}", max_results),
+                    "Expected a number between 5 and 1000".to_string()
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn parse_ec2_filters() {
+        let args = "provider=aws filter.tag:role=server filter.tag:env=prod,staging filter.vpc-id=vpc-0a1b2c";
+
+        let res = AWSProvider::try_from(ParsedArgs::try_from(args.to_string()).unwrap());
+        assert!(res.is_ok());
+        let provider = res.unwrap();
+        assert_eq!(provider.selector(), &Selector::Filters);
+        assert_eq!(
+            provider.filters(),
+            &[
+                Filter {
+                    name: Some("tag:env".to_string()),
+                    values: Some(vec!["prod".to_string(), "staging".to_string()]),
+                },
+                Filter {
+                    name: Some("tag:role".to_string()),
+                    values: Some(vec!["server".to_string()]),
+                },
+                Filter {
+                    name: Some("vpc-id".to_string()),
+                    values: Some(vec!["vpc-0a1b2c".to_string()]),
+                },
+            ]
+        );
+
+        let cases = vec![
+            ("filter.owner-id=123", "filter.owner-id=123"),
+            ("filter.tag:=server", "filter.tag:=server"),
+            ("filter.subnet-id=,", "filter.subnet-id=,"),
+        ];
+        for (filter, arg) in cases {
+            let args = format!("provider=aws {}", filter);
+            let res = AWSProvider::try_from(ParsedArgs::try_from(args).unwrap());
+            assert!(
+                matches!(res.as_ref().unwrap_err(), DiscoverError::MalformedArgument(a, _) if a == arg),
+                "{:?}",
+                res
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn send_ec2_filters() {
+        let stub = ec2_stub().await;
+        let provider = provider_for(
+            &stub,
+            "provider=aws tag_key=consul tag_value=server filter.availability-zone=eu-west-1a,eu-west-1b access_key_id=AKIDSTATIC secret_access_key=secret",
+        );
+        assert!(provider.addrs().await.is_ok());
+
+        let body = &stub.requests()[0].body;
+        for param in &[
+            "Filter.1.Name=tag%3Aconsul",
+            "Filter.1.Value.1=server",
+            "Filter.2.Name=availability-zone",
+            "Filter.2.Value.1=eu-west-1a",
+            "Filter.2.Value.2=eu-west-1b",
+            "Filter.3.Name=instance-state-name",
+            "Filter.3.Value.1=running",
+        ] {
+            assert!(body.contains(param), "{} not in {}", param, body);
+        }
+
+        let provider = provider_for(
+            &stub,
+            "provider=aws filter.instance-state-name=running,stopped access_key_id=AKIDSTATIC secret_access_key=secret",
+        );
+        assert!(provider.addrs().await.is_ok());
+
+        let body = &stub.requests()[1].body;
+        assert!(body.contains("Filter.1.Value.2=stopped"), "{}", body);
+        assert!(!body.contains("Filter.2."), "{}", body);
+    }
+
+    #[test]
+    fn aws_provider_with_regions() {
+        let args = "provider=aws tag_key=consul tag_value=server regions=eu-west-1,us-east-1 on_error=partial max_concurrency=2";
+        let provider =
+            AWSProvider::try_from(ParsedArgs::try_from(args.to_string()).unwrap()).unwrap();
+        assert_eq!(
+            provider.regions(),
+            Some(&Regions::List(vec![Region::EuWest1, Region::UsEast1]))
+        );
+        assert_eq!(provider.on_error(), OnError::Partial);
+        assert_eq!(provider.max_concurrency(), 2);
+
+        let args = "provider=aws tag_key=consul tag_value=server regions=all";
+        let provider =
+            AWSProvider::try_from(ParsedArgs::try_from(args.to_string()).unwrap()).unwrap();
+        assert_eq!(provider.regions(), Some(&Regions::All));
+        assert_eq!(provider.on_error(), OnError::Fail);
+        assert_eq!(provider.max_concurrency(), 4);
+
+        for (args, arg) in &[
+            ("regions=eu-west-1,mars-1", "regions=eu-west-1,mars-1"),
+            ("regions=,", "regions=,"),
+            ("on_error=ignore", "on_error=ignore"),
+            ("max_concurrency=0", "max_concurrency=0"),
+            ("max_concurrency=many", "max_concurrency=many"),
+        ] {
+            let args = format!("provider=aws tag_key=consul tag_value=server {}", args);
+            let res = AWSProvider::try_from(ParsedArgs::try_from(args).unwrap());
+            assert!(matches!(
+                res.unwrap_err(),
+                DiscoverError::MalformedArgument(malformed, _) if malformed == *arg
+            ));
+        }
+    }
+
+    fn describe_instances_with(private_ip: &str) -> String {
+        format!(
+            r#"<DescribeInstancesResponse xmlns="http://ec2.amazonaws.com/doc/2016-11-15/">
+    <reservationSet>
+        <item>
+            <reservationId>r-1234567890abcdef0</reservationId>
+            <instancesSet>
+                <item>
+                    <instanceId>i-1234567890abcdef0</instanceId>
+                    <privateIpAddress>{}</privateIpAddress>
+                </item>
+            </instancesSet>
+        </item>
+    </reservationSet>
+</DescribeInstancesResponse>"#,
+            private_ip
+        )
+    }
+
+    const DESCRIBE_REGIONS: &str = r#"<DescribeRegionsResponse xmlns="http://ec2.amazonaws.com/doc/2016-11-15/">
+    <requestId>59dbff89-35bd-4eac-99ed-be587example</requestId>
+    <regionInfo>
+        <item>
+            <regionName>eu-west-1</regionName>
+            <regionEndpoint>ec2.eu-west-1.amazonaws.com</regionEndpoint>
+        </item>
+        <item>
+            <regionName>us-east-1</regionName>
+            <regionEndpoint>ec2.us-east-1.amazonaws.com</regionEndpoint>
+        </item>
+        <item>
+            <regionName>ap-south-1</regionName>
+            <regionEndpoint>ec2.ap-south-1.amazonaws.com</regionEndpoint>
+        </item>
+    </regionInfo>
+</DescribeRegionsResponse>"#;
+
+    /// Every region has one instance, and ap-south-1 fails.
+    async fn regions_stub() -> stub::Stub {
+        stub::serve_tcp(|req| {
+            if req.body.contains("Action=DescribeRegions") {
+                return StubResponse::new(200, "text/xml", DESCRIBE_REGIONS);
+            }
+
+            let authorization = req.header("authorization").unwrap_or_default();
+            let region_ips = [("/eu-west-1/", "10.1.0.12"), ("/us-east-1/", "10.2.0.12")];
+            match region_ips
+                .iter()
+                .find(|(region, _)| authorization.contains(region))
+            {
+                Some((_, ip)) => {
+                    StubResponse::new(200, "text/xml", &describe_instances_with(ip))
+                }
+                None => StubResponse::new(
+                    503,
+                    "text/xml",
+                    "<Response><Errors><Error><Code>Unavailable</Code><Message>Service unavailable</Message></Error></Errors></Response>",
+                ),
+            }
+        })
+        .await
+    }
 
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn share_max_concurrency_between_accounts() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+        let (counter, max) = (in_flight.clone(), max_in_flight.clone());
+        let stub = stub::serve_tcp(move |req| {
+            if req.body.contains("Action=AssumeRole") {
+                return StubResponse::new(
+                    200,
+                    "text/xml",
+                    &assume_role_response("ASIA111111111111", "2099-01-01T00:00:00Z"),
+                );
+            }
+            let current = counter.fetch_add(1, Ordering::SeqCst) + 1;
+            max.fetch_max(current, Ordering::SeqCst);
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            counter.fetch_sub(1, Ordering::SeqCst);
+            StubResponse::new(200, "text/xml", DESCRIBE_INSTANCES)
+        })
+        .await;
+        let provider = provider_for(
+            &stub,
+            "provider=aws tag_key=consul tag_value=server regions=eu-west-1,us-east-1 max_concurrency=1 access_key_id=AKIDBASE secret_access_key=secret role_arns=arn:aws:iam::111111111111:role/consul,arn:aws:iam::222222222222:role/consul",
+        );
+
+        assert!(provider.addrs().await.is_ok());
+        let describe_instances = stub
+            .requests()
+            .iter()
+            .filter(|req| req.body.contains("Action=DescribeInstances"))
+            .count();
+        assert_eq!(describe_instances, 4);
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn tag_the_nodes_with_their_region() {
+        let stub = regions_stub().await;
+        let provider = provider_for(
+            &stub,
+            "provider=aws tag_key=consul tag_value=server regions=eu-west-1,us-east-1 access_key_id=AKIDSTATIC secret_access_key=secret",
+        );
+
+        assert_eq!(
+            provider.nodes().await,
+            Ok(vec![
+                Node {
+                    addr: "10.1.0.12".to_string(),
+                    region: "eu-west-1".to_string()
+                },
+                Node {
+                    addr: "10.2.0.12".to_string(),
+                    region: "us-east-1".to_string()
+                },
+            ])
+        );
+        assert_eq!(
+            provider.addrs().await,
+            Ok(vec!["10.1.0.12".to_string(), "10.2.0.12".to_string()])
+        );
+    }
+
+    #[tokio::test]
+    async fn fail_on_a_failing_region() {
+        let stub = regions_stub().await;
+        let provider = provider_for(
+            &stub,
+            "provider=aws tag_key=consul tag_value=server regions=all access_key_id=AKIDSTATIC secret_access_key=secret",
+        );
+
+        match provider.addrs().await {
+            Err(DiscoverError::RegionsFailed(failures)) => {
+                assert_eq!(failures.len(), 1);
+                assert_eq!(failures[0].0, "ap-south-1");
+            }
+            res => panic!("Expected RegionsFailed, got {:?}", res),
+        }
+        assert!(stub.requests()[0].body.contains("Action=DescribeRegions"));
+    }
+
+    #[tokio::test]
+    async fn return_partial_results_of_the_other_regions() {
+        let stub = regions_stub().await;
+        let provider = provider_for(
+            &stub,
+            "provider=aws tag_key=consul tag_value=server regions=all on_error=partial access_key_id=AKIDSTATIC secret_access_key=secret",
+        );
+        assert_eq!(
+            provider.addrs().await,
+            Ok(vec!["10.1.0.12".to_string(), "10.2.0.12".to_string()])
+        );
+
+        // Nothing is returned when every region fails
+        let provider = provider_for(
+            &stub,
+            "provider=aws tag_key=consul tag_value=server regions=ap-south-1 on_error=partial access_key_id=AKIDSTATIC secret_access_key=secret",
+        );
+        assert!(matches!(
+            provider.addrs().await,
+            Err(DiscoverError::RegionsFailed(_))
+        ));
+    }
+
+    /// An instance with a primary interface with a secondary IPv4 and an
+    /// IPv6 address, and a second private interface.
+    fn multi_eni_result() -> DescribeInstancesResult {
+        use rusoto_ec2::{
+            InstanceIpv6Address, InstanceNetworkInterfaceAssociation,
+            InstanceNetworkInterfaceAttachment, InstancePrivateIpAddress,
+        };
+
+        let interface = |index: i64, private_ips: &[&str], association| InstanceNetworkInterface {
+            attachment: Some(InstanceNetworkInterfaceAttachment {
+                device_index: Some(index),
+                ..Default::default()
+            }),
+            association,
+            private_ip_address: Some(private_ips[0].to_string()),
+            private_dns_name: Some(format!(
+                "ip-{}.ec2.internal",
+                private_ips[0].replace('.', "-")
+            )),
+            private_ip_addresses: Some(
+                private_ips
+                    .iter()
+                    .map(|ip| InstancePrivateIpAddress {
+                        private_ip_address: Some(ip.to_string()),
+                        ..Default::default()
+                    })
+                    .collect(),
+            ),
+            ..Default::default()
+        };
+        let primary = InstanceNetworkInterface {
+            ipv_6_addresses: Some(vec![InstanceIpv6Address {
+                ipv_6_address: Some("2001:db8::12".to_string()),
+            }]),
+            ..interface(
+                0,
+                &["10.0.0.12", "10.0.0.112"],
+                Some(InstanceNetworkInterfaceAssociation {
+                    public_ip: Some("54.0.0.12".to_string()),
+                    public_dns_name: Some("ec2-54-0-0-12.compute-1.amazonaws.com".to_string()),
+                    ..Default::default()
+                }),
+            )
+        };
+        // Listed before the primary interface
+        let secondary = interface(1, &["10.0.1.12"], None);
+
+        DescribeInstancesResult {
+            reservations: Some(vec![Reservation {
+                reservation_id: Some("r-1234567890abcdef0".to_string()),
+                instances: Some(vec![Instance {
+                    instance_id: Some("i-1234567890abcdef0".to_string()),
+                    private_ip_address: Some("10.0.0.12".to_string()),
+                    private_dns_name: Some("ip-10-0-0-12.ec2.internal".to_string()),
+                    public_ip_address: Some("54.0.0.12".to_string()),
+                    public_dns_name: Some("ec2-54-0-0-12.compute-1.amazonaws.com".to_string()),
+                    network_interfaces: Some(vec![secondary, primary]),
+                    ..Default::default()
+                }]),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        }
+    }
+
+    fn multi_eni_addrs(args: &str) -> Vec<String> {
+        let args = format!("provider=aws tag_key=consul tag_value=server {}", args);
+        let provider = AWSProvider::try_from(ParsedArgs::try_from(args).unwrap()).unwrap();
+        provider.reservation_addrs(multi_eni_result().reservations.unwrap())
+    }
+
+    #[test]
+    fn extract_the_addresses_of_every_addr_type() {
+        assert_eq!(multi_eni_addrs("addr_type=private_v4"), vec!["10.0.0.12"]);
+        assert_eq!(
+            multi_eni_addrs("addr_type=all_private_v4"),
+            vec!["10.0.0.12", "10.0.0.112", "10.0.1.12"]
+        );
+        assert_eq!(multi_eni_addrs("addr_type=public_v4"), vec!["54.0.0.12"]);
+        assert_eq!(
+            multi_eni_addrs("addr_type=private_v6"),
+            vec!["2001:db8::12"]
+        );
+        assert_eq!(multi_eni_addrs("addr_type=public_v6"), vec!["2001:db8::12"]);
+        assert_eq!(
+            multi_eni_addrs("addr_type=private_dns"),
+            vec!["ip-10-0-0-12.ec2.internal"]
+        );
+        assert_eq!(
+            multi_eni_addrs("addr_type=public_dns"),
+            vec!["ec2-54-0-0-12.compute-1.amazonaws.com"]
+        );
+    }
+
+    #[test]
+    fn extract_the_addresses_of_one_network_interface() {
+        assert_eq!(
+            multi_eni_addrs("addr_type=private_v4 device_index=1"),
+            vec!["10.0.1.12"]
+        );
+        assert_eq!(
+            multi_eni_addrs("addr_type=all_private_v4 device_index=0"),
+            vec!["10.0.0.12", "10.0.0.112"]
+        );
+        assert_eq!(
+            multi_eni_addrs("addr_type=private_dns device_index=1"),
+            vec!["ip-10-0-1-12.ec2.internal"]
+        );
+        assert!(multi_eni_addrs("addr_type=public_v4 device_index=1").is_empty());
+        assert!(multi_eni_addrs("addr_type=public_dns device_index=1").is_empty());
+        assert!(multi_eni_addrs("addr_type=private_v6 device_index=1").is_empty());
+        assert!(multi_eni_addrs("device_index=2").is_empty());
+
+        let args = "provider=aws tag_key=consul tag_value=server device_index=-1";
+        let res = AWSProvider::try_from(ParsedArgs::try_from(args.to_string()).unwrap());
+        assert!(matches!(
+            res.unwrap_err(),
+            DiscoverError::MalformedArgument(arg, _) if arg == "device_index=-1"
+        ));
+    }
+
+    #[test]
+    fn skip_missing_and_empty_addresses() {
+        let instance = Instance {
+            instance_id: Some("i-1234567890abcdef0".to_string()),
+            private_ip_address: Some("10.0.0.12".to_string()),
+            public_dns_name: Some("".to_string()),
+            ..Default::default()
+        };
+
+        assert!(instance_addrs(&instance, &AddrType::PublicDns, None).is_empty());
+        assert!(instance_addrs(&instance, &AddrType::PublicV4, None).is_empty());
+        assert!(instance_addrs(&instance, &AddrType::PublicV6, None).is_empty());
+        // Without network interfaces only the primary address is known
+        assert_eq!(
+            instance_addrs(&instance, &AddrType::AllPrivateV4, None),
+            vec!["10.0.0.12"]
+        );
+        assert!(instance_addrs(&instance, &AddrType::AllPrivateV4, Some(0)).is_empty());
+    }
+
+    #[test]
+    fn skip_malformed_reservations_and_instances() {
+        let provider = AWSProvider::try_from(
+            ParsedArgs::try_from("provider=aws tag_key=consul tag_value=server".to_string())
+                .unwrap(),
+        )
+        .unwrap();
+        let instance = |id: Option<&str>, ip: Option<&str>| Instance {
+            instance_id: id.map(str::to_string),
+            private_ip_address: ip.map(str::to_string),
+            ..Default::default()
+        };
+
+        assert!(provider.reservation_addrs(Vec::new()).is_empty());
+        assert!(provider
+            .reservation_addrs(
+                DescribeInstancesResult::default()
+                    .reservations
+                    .unwrap_or_default()
+            )
+            .is_empty());
+
+        let reservations = vec![
+            // No instances at all
+            Reservation::default(),
+            Reservation {
+                reservation_id: Some("r-1234567890abcdef0".to_string()),
+                instances: Some(Vec::new()),
+                ..Default::default()
+            },
+            // No reservation id
+            Reservation {
+                instances: Some(vec![instance(Some("i-1"), Some("10.0.0.1"))]),
+                ..Default::default()
+            },
+            Reservation {
+                reservation_id: Some("r-1234567890abcdef1".to_string()),
+                instances: Some(vec![
+                    instance(None, Some("10.0.0.2")),
+                    instance(Some("i-3"), None),
+                    Instance::default(),
+                    instance(Some("i-4"), Some("10.0.0.4")),
+                ]),
+                ..Default::default()
+            },
+        ];
+        assert_eq!(
+            provider.reservation_addrs(reservations),
+            vec!["10.0.0.1".to_string(), "10.0.0.4".to_string()]
+        );
+    }
+
+    #[test]
+    fn skip_network_interfaces_without_attachment() {
+        let instance = Instance {
+            instance_id: Some("i-1234567890abcdef0".to_string()),
+            network_interfaces: Some(vec![
+                InstanceNetworkInterface::default(),
+                InstanceNetworkInterface {
+                    private_ip_address: Some("10.0.0.12".to_string()),
+                    private_ip_addresses: Some(vec![Default::default()]),
+                    ipv_6_addresses: Some(vec![Default::default()]),
+                    association: Some(Default::default()),
+                    ..Default::default()
+                },
+            ]),
+            ..Default::default()
+        };
+
+        assert!(instance_addrs(&instance, &AddrType::PrivateV4, Some(0)).is_empty());
+        assert!(instance_addrs(&instance, &AddrType::PrivateV4, None).is_empty());
+        assert!(instance_addrs(&instance, &AddrType::AllPrivateV4, None).is_empty());
+        assert!(instance_addrs(&instance, &AddrType::PublicV6, None).is_empty());
+        assert!(instance_addrs(&instance, &AddrType::PublicDns, Some(0)).is_empty());
+    }
+
+    #[tokio::test]
+    async fn survive_a_partial_response() {
+        let stub = stub::serve_tcp(|_| {
+            StubResponse::new(
+                200,
+                "text/xml",
+                r#"<DescribeInstancesResponse xmlns="http://ec2.amazonaws.com/doc/2016-11-15/">
+    <reservationSet>
+        <item>
+            <instancesSet>
+                <item>
+                    <privateIpAddress>10.0.0.11</privateIpAddress>
+                </item>
+                <item>
+                    <instanceId>i-1234567890abcdef2</instanceId>
+                    <privateIpAddress>10.0.0.12</privateIpAddress>
+                </item>
+            </instancesSet>
+        </item>
+        <item></item>
+    </reservationSet>
+</DescribeInstancesResponse>"#,
+            )
+        })
+        .await;
+        let provider = provider_for(
+            &stub,
+            "provider=aws tag_key=consul tag_value=server access_key_id=AKIDSTATIC secret_access_key=secret",
+        );
+
+        assert_eq!(provider.addrs().await, Ok(vec!["10.0.0.12".to_string()]));
+    }
 }
@@ -0,0 +1,340 @@
+use log::{debug, info};
+use rusoto_core::Region;
+use rusoto_servicediscovery::{
+    DiscoverInstancesRequest, HttpInstanceSummary, ServiceDiscovery, ServiceDiscoveryClient,
+};
+
+use std::convert::TryFrom;
+
+use crate::{args::ParsedArgs, SupportedProvider};
+
+use super::{aws_config::AwsConfig, DiscoverError, Provider};
+
+/// The most instances DiscoverInstances returns, it defaults to 100.
+const MAX_RESULTS: i64 = 1000;
+
+const HEALTH_STATUSES: [&str; 4] = ["HEALTHY", "UNHEALTHY", "ALL", "HEALTHY_OR_ELSE_ALL"];
+
+#[derive(Debug, Clone)]
+pub struct CloudMapProvider {
+    namespace: String,
+    service: String,
+    health_status: String,
+    config: AwsConfig,
+}
+
+impl TryFrom<ParsedArgs> for CloudMapProvider {
+    type Error = DiscoverError;
+
+    fn try_from(args: ParsedArgs) -> Result<Self, Self::Error> {
+        let mut namespace = None;
+        let mut service = None;
+        let mut health_status = "HEALTHY".to_string();
+        let mut config = AwsConfig::default();
+
+        for (key, value) in args {
+            match &key[..] {
+                "namespace" => namespace = Some(value),
+                "service" => service = Some(value),
+                "health_status" => {
+                    if !HEALTH_STATUSES.contains(&value.as_str()) {
+                        return Err(DiscoverError::MalformedArgument(
+                            format!("health_status={}", value),
+                            format!(
+                                "{} is not a valid health_status. Valid health_statuses are: {}.",
+                                value,
+                                HEALTH_STATUSES.join(", ")
+                            ),
+                        ));
+                    }
+                    health_status = value
+                }
+                _ => {
+                    if !config.parse_arg(&key, &value)? {
+                        return Err(DiscoverError::UnexpectedArgument(key));
+                    }
+                }
+            }
+        }
+        config.validate(&["servicediscovery"])?;
+
+        let namespace =
+            namespace.ok_or_else(|| DiscoverError::MissingArgument("namespace".into()))?;
+        let service = service.ok_or_else(|| DiscoverError::MissingArgument("service".into()))?;
+
+        Ok(CloudMapProvider {
+            namespace,
+            service,
+            health_status,
+            config,
+        })
+    }
+}
+
+impl TryFrom<Vec<String>> for CloudMapProvider {
+    type Error = DiscoverError;
+
+    fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
+        let args = ParsedArgs::try_from(value)?;
+        match *args.provider() {
+            SupportedProvider::CloudMap => CloudMapProvider::try_from(args),
+            _ => Err(DiscoverError::MalformedArgument(
+                format!("provider={}", args.provider()),
+                "you should not see this ...".to_string(),
+            )),
+        }
+    }
+}
+
+/// Returns "ip:port" of an instance, or just "ip" if it has no port.
+fn instance_addr(instance: &HttpInstanceSummary) -> Option<String> {
+    let attributes = instance.attributes.as_ref()?;
+    let ip = attributes.get("AWS_INSTANCE_IPV4")?;
+    match attributes.get("AWS_INSTANCE_PORT") {
+        Some(port) => Some(format!("{}:{}", ip, port)),
+        None => Some(ip.clone()),
+    }
+}
+
+impl CloudMapProvider {
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    pub fn service(&self) -> &str {
+        &self.service
+    }
+
+    pub fn health_status(&self) -> &str {
+        &self.health_status
+    }
+
+    /// The `region` argument, or else the region of the environment. Without
+    /// either this is provisional: us-east-1 until the first lookup has read
+    /// the region of the instance.
+    pub fn region(&self) -> &Region {
+        self.config.region()
+    }
+
+    /// The `region` argument, `None` when the region is detected.
+    pub fn region_arg(&self) -> Option<&Region> {
+        self.config.region_arg()
+    }
+
+    async fn get_instances(&self) -> Result<Vec<HttpInstanceSummary>, DiscoverError> {
+        let client = self
+            .config
+            .client("servicediscovery", ServiceDiscoveryClient::new_with)
+            .await?;
+
+        debug!(
+            "Using region={:?} namespace={} service={} health_status={}",
+            self.region(),
+            self.namespace,
+            self.service,
+            self.health_status
+        );
+
+        let input = DiscoverInstancesRequest {
+            namespace_name: self.namespace.clone(),
+            service_name: self.service.clone(),
+            health_status: Some(self.health_status.clone()),
+            max_results: Some(MAX_RESULTS),
+            ..Default::default()
+        };
+
+        client
+            .discover_instances(input)
+            .await
+            .map(|res| res.instances.unwrap_or_default())
+            .map_err(|e| DiscoverError::ProviderRequestFailed(format!("{:?}", e)))
+    }
+}
+
+#[async_trait::async_trait]
+impl Provider for CloudMapProvider {
+    async fn addrs(&self) -> Result<Vec<String>, DiscoverError> {
+        let instances = self.get_instances().await?;
+        debug!("Found {} instances", instances.len());
+
+        let addrs = instances
+            .iter()
+            .filter_map(|instance| match instance_addr(instance) {
+                Some(addr) => {
+                    info!("Instance {:?} has address {:?}", instance.instance_id, addr);
+                    Some(addr)
+                }
+                None => {
+                    debug!("Instance {:?} has no IPv4 address", instance.instance_id);
+                    None
+                }
+            })
+            .collect();
+
+        Ok(addrs)
+    }
+
+    fn help() -> &'static str {
+        "AWS Cloud Map:
+
+	provider:                  \"aws-cloudmap\"
+	region:                    The AWS region. Defaults to AWS_DEFAULT_REGION or AWS_REGION, or
+	                           else to the region of the instance from the instance metadata
+	                           (IMDSv2).
+	namespace:                 The name of the Cloud Map namespace
+	service:                   The name of the service in the namespace
+	health_status:             \"HEALTHY\", \"UNHEALTHY\", \"ALL\" or \"HEALTHY_OR_ELSE_ALL\".
+	                           Defaults to \"HEALTHY\".
+	access_key_id:             The AWS access key to use
+	secret_access_key:         The AWS secret access key to use
+	session_token:             The session token of temporary credentials
+	role_arn:                  The IAM role to assume with the credentials, e.g. of another
+	                           account
+	external_id:               The external ID the role's trust policy requires
+	role_session_name:         The session name of the assumed role. Defaults to
+	                           \"node-discover\".
+	duration:                  How long the assumed role credentials are valid, between 900s
+	                           and 12h. Defaults to 1h.
+	servicediscovery_endpoint: The URL the Cloud Map requests are sent to instead of the AWS
+	                           endpoint
+	sts_endpoint:              The URL the STS requests of role_arn are sent to
+	fips:                      \"true\" to use the FIPS endpoints. Defaults to \"false\".
+	dualstack:                 \"true\" to use the IPv4 and IPv6 endpoints. Defaults to \"false\".
+
+	Returns the AWS_INSTANCE_IPV4 attribute of the registered instances, followed by
+	AWS_INSTANCE_PORT when it is set. The required IAM permission is
+	'servicediscovery:DiscoverInstances'. Without access_key_id and secret_access_key the
+	credentials are read the same way as by the \"aws\" provider.
+	"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::providers::stub::{self, StubResponse};
+    use std::collections::HashMap;
+
+    #[test]
+    fn cloudmap_provider_from_string() {
+        let args = "provider=aws-cloudmap region=eu-west-1 namespace=consul.local service=server";
+
+        let res = ParsedArgs::try_from(args.to_string());
+        assert!(res.is_ok());
+        let res = CloudMapProvider::try_from(res.unwrap());
+        assert!(res.is_ok());
+        let provider = res.unwrap();
+        assert_eq!(provider.namespace(), "consul.local");
+        assert_eq!(provider.service(), "server");
+        assert_eq!(provider.health_status(), "HEALTHY");
+        assert_eq!(provider.region(), &Region::EuWest1);
+        assert_eq!(provider.region_arg(), Some(&Region::EuWest1));
+    }
+
+    #[test]
+    fn fail_on_invalid_health_status() {
+        let args = "provider=aws-cloudmap namespace=consul.local service=server health_status=OK";
+
+        let res = CloudMapProvider::try_from(ParsedArgs::try_from(args.to_string()).unwrap());
+        assert!(matches!(
+            res.unwrap_err(),
+            DiscoverError::MalformedArgument(arg, _) if arg == "health_status=OK"
+        ));
+
+        let args = "provider=aws-cloudmap service=server";
+        let res = CloudMapProvider::try_from(ParsedArgs::try_from(args.to_string()).unwrap());
+        assert_eq!(
+            res.unwrap_err(),
+            DiscoverError::MissingArgument("namespace".to_string())
+        );
+    }
+
+    #[test]
+    fn extract_instance_addrs() {
+        let instance = |attributes: &[(&str, &str)]| HttpInstanceSummary {
+            attributes: Some(
+                attributes
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect::<HashMap<_, _>>(),
+            ),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            instance_addr(&instance(&[
+                ("AWS_INSTANCE_IPV4", "10.0.1.17"),
+                ("AWS_INSTANCE_PORT", "8301")
+            ])),
+            Some("10.0.1.17:8301".to_string())
+        );
+        assert_eq!(
+            instance_addr(&instance(&[("AWS_INSTANCE_IPV4", "10.0.1.17")])),
+            Some("10.0.1.17".to_string())
+        );
+        assert_eq!(
+            instance_addr(&instance(&[("AWS_INSTANCE_CNAME", "consul.example.com")])),
+            None
+        );
+        assert_eq!(instance_addr(&HttpInstanceSummary::default()), None);
+    }
+
+    #[tokio::test]
+    async fn discover_the_instances_of_the_service() {
+        let stub = stub::serve_tcp(|_| {
+            StubResponse::new(
+                200,
+                "application/x-amz-json-1.1",
+                r#"{
+                    "Instances": [
+                        {
+                            "InstanceId": "consul-1",
+                            "NamespaceName": "consul.local",
+                            "ServiceName": "server",
+                            "HealthStatus": "HEALTHY",
+                            "Attributes": {
+                                "AWS_INSTANCE_IPV4": "10.0.1.17",
+                                "AWS_INSTANCE_PORT": "8301"
+                            }
+                        },
+                        {
+                            "InstanceId": "consul-2",
+                            "Attributes": {"AWS_INSTANCE_IPV4": "10.0.1.18"}
+                        },
+                        {
+                            "InstanceId": "consul-3",
+                            "Attributes": {"AWS_INSTANCE_CNAME": "consul.example.com"}
+                        }
+                    ]
+                }"#,
+            )
+        })
+        .await;
+        let args = format!(
+            "provider=aws-cloudmap region=us-east-1 namespace=consul.local service=server health_status=ALL access_key_id=AKIDSTATIC secret_access_key=secret servicediscovery_endpoint={}",
+            stub.url()
+        );
+        let provider = CloudMapProvider::try_from(ParsedArgs::try_from(args).unwrap()).unwrap();
+
+        assert_eq!(
+            provider.addrs().await,
+            Ok(vec!["10.0.1.17:8301".to_string(), "10.0.1.18".to_string()])
+        );
+
+        let requests = stub.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(
+            requests[0].header("x-amz-target"),
+            Some("Route53AutoNaming_v20170314.DiscoverInstances")
+        );
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&requests[0].body).unwrap(),
+            serde_json::json!({
+                "NamespaceName": "consul.local",
+                "ServiceName": "server",
+                "HealthStatus": "ALL",
+                "MaxResults": 1000,
+            })
+        );
+    }
+}
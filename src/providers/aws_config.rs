@@ -0,0 +1,779 @@
+use chrono::Utc;
+use log::{debug, info, warn};
+use rusoto_core::{
+    credential::{
+        AwsCredentials, ChainProvider, CredentialsError, ProvideAwsCredentials, StaticProvider,
+    },
+    HttpClient, Region,
+};
+use rusoto_sts::{StsAssumeRoleSessionCredentialsProvider, StsClient, WebIdentityProvider};
+
+use std::{
+    collections::BTreeMap,
+    str::FromStr,
+    sync::{Arc, Mutex, OnceLock},
+    time::Duration,
+};
+
+use super::{aws_metadata, parse_bool, DiscoverError};
+
+const DEFAULT_ROLE_SESSION_NAME: &str = "node-discover";
+
+/// Assumed role credentials are refreshed when they expire within this margin.
+const CREDENTIALS_EXPIRY_MARGIN: i64 = 5 * 60;
+
+/// The session duration STS accepts, in seconds.
+const MIN_ROLE_DURATION: u64 = 900;
+const MAX_ROLE_DURATION: u64 = 43200;
+
+/// The services whose endpoint can be replaced with `<service>_endpoint`.
+/// `endpoint` replaces the one of ec2.
+const ENDPOINT_SERVICES: &[&str] = &[
+    "ec2",
+    "sts",
+    "autoscaling",
+    "ecs",
+    "servicediscovery",
+    "elasticloadbalancing",
+];
+
+/// The name of the argument that replaces the endpoint of `service`.
+fn endpoint_arg(service: &str) -> String {
+    match service {
+        "ec2" => "endpoint".to_string(),
+        _ => format!("{}_endpoint", service),
+    }
+}
+
+/// The credentials the AWS providers sign their requests with.
+#[derive(Debug, Clone)]
+pub(crate) enum Credentials {
+    /// Keys given in the configuration
+    Static(StaticProvider),
+    /// The default chain: environment, profile file, container and instance metadata
+    Chain(Box<ChainProvider>),
+    /// The web identity token of `AWS_WEB_IDENTITY_TOKEN_FILE`, e.g. IAM roles for
+    /// EKS service accounts
+    WebIdentity(Cached<WebIdentityProvider>),
+    /// A role assumed with any of the above
+    AssumeRole(Cached<AssumeRole>),
+}
+
+#[async_trait::async_trait]
+impl ProvideAwsCredentials for Credentials {
+    async fn credentials(&self) -> Result<AwsCredentials, CredentialsError> {
+        match self {
+            Credentials::Static(provider) => provider.credentials().await,
+            Credentials::Chain(provider) => provider.credentials().await,
+            Credentials::WebIdentity(provider) => provider.credentials().await,
+            Credentials::AssumeRole(provider) => provider.credentials().await,
+        }
+    }
+}
+
+/// `StsAssumeRoleSessionCredentialsProvider` is neither `Debug` nor `Clone`.
+#[derive(Clone)]
+pub(crate) struct AssumeRole(Arc<StsAssumeRoleSessionCredentialsProvider>);
+
+impl std::fmt::Debug for AssumeRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("AssumeRole")
+    }
+}
+
+#[async_trait::async_trait]
+impl ProvideAwsCredentials for AssumeRole {
+    async fn credentials(&self) -> Result<AwsCredentials, CredentialsError> {
+        self.0.credentials().await
+    }
+}
+
+type CredentialsCache = Arc<Mutex<Option<AwsCredentials>>>;
+
+/// Reuses the temporary credentials of a provider until shortly before they
+/// expire. Unlike rusoto's `AutoRefreshingProvider`, failures are not cached,
+/// so a later discovery retries them.
+#[derive(Debug, Clone)]
+pub(crate) struct Cached<P> {
+    provider: P,
+    cache: CredentialsCache,
+}
+
+#[async_trait::async_trait]
+impl<P: ProvideAwsCredentials + Send + Sync> ProvideAwsCredentials for Cached<P> {
+    async fn credentials(&self) -> Result<AwsCredentials, CredentialsError> {
+        if let Some(credentials) = self.cache.lock().unwrap().as_ref() {
+            let fresh = match credentials.expires_at() {
+                Some(expires_at) => {
+                    *expires_at > Utc::now() + chrono::Duration::seconds(CREDENTIALS_EXPIRY_MARGIN)
+                }
+                None => true,
+            };
+            if fresh {
+                return Ok(credentials.clone());
+            }
+        }
+
+        debug!("Requesting temporary AWS credentials");
+        let credentials = self.provider.credentials().await?;
+        *self.cache.lock().unwrap() = Some(credentials.clone());
+
+        Ok(credentials)
+    }
+}
+
+fn parse_duration(value: &str) -> Result<Duration, DiscoverError> {
+    let malformed = || {
+        DiscoverError::MalformedArgument(
+            format!("duration={}", value),
+            format!(
+                "{} is not a valid duration. Expected between 900s and 12h, as seconds or a number followed by a unit: s, m or h.",
+                value
+            ),
+        )
+    };
+
+    let (amount, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(idx) => value.split_at(idx),
+        None => (value, "s"),
+    };
+    let amount = amount.parse::<u64>().map_err(|_| malformed())?;
+
+    let seconds = match unit {
+        "s" => Some(amount),
+        "m" => amount.checked_mul(60),
+        "h" => amount.checked_mul(60 * 60),
+        _ => None,
+    }
+    .ok_or_else(malformed)?;
+    if !(MIN_ROLE_DURATION..=MAX_ROLE_DURATION).contains(&seconds) {
+        return Err(malformed());
+    }
+
+    Ok(Duration::from_secs(seconds))
+}
+
+/// The region of the environment, `AWS_DEFAULT_REGION` or `AWS_REGION`. `env`
+/// looks up an environment variable.
+fn env_region(env: impl Fn(&str) -> Option<String>) -> Option<Region> {
+    for var in &["AWS_DEFAULT_REGION", "AWS_REGION"] {
+        if let Some(name) = env(var) {
+            match Region::from_str(&name) {
+                Ok(region) => return Some(region),
+                Err(_) => warn!("Ignoring {}={}, it is not a valid AWS Region", var, name),
+            }
+        }
+    }
+    None
+}
+
+/// The region of the EC2 instance from the metadata service at
+/// `metadata_endpoint`, unless `AWS_EC2_METADATA_DISABLED` is set. Falls back
+/// to us-east-1. `env` looks up an environment variable.
+async fn detect_region(env: impl Fn(&str) -> Option<String>, metadata_endpoint: &str) -> Region {
+    if env("AWS_EC2_METADATA_DISABLED").as_deref() != Some("true") {
+        match aws_metadata::instance_region(metadata_endpoint).await {
+            Ok(name) => match Region::from_str(&name) {
+                Ok(region) => {
+                    info!("Using region {} of the instance", name);
+                    return region;
+                }
+                Err(_) => warn!("The instance is in the unknown region {}", name),
+            },
+            Err(e) => debug!("Unable to read the region of the instance: {}", e),
+        }
+    }
+
+    warn!(
+        "Unable to detect the AWS region, using us-east-1. Set the region argument or AWS_REGION."
+    );
+    Region::UsEast1
+}
+
+fn http_client() -> Result<HttpClient, DiscoverError> {
+    HttpClient::new().map_err(|e| {
+        DiscoverError::ProviderRequestFailed(format!("Unable to create HTTP client: {}", e))
+    })
+}
+
+/// Region and credential settings shared by all the AWS providers.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct AwsConfig {
+    // https://rusoto.github.io/rusoto/rusoto_core/region/enum.Region.html
+    region: Option<Region>,
+    /// The region of the environment or the instance when `region` is not
+    /// set, shared by the clones
+    detected_region: Arc<OnceLock<Region>>,
+    access_key_id: Option<String>,
+    secret_access_key: Option<String>,
+    session_token: Option<String>,
+    role_arn: Option<String>,
+    external_id: Option<String>,
+    role_session_name: Option<String>,
+    duration: Option<Duration>,
+    /// Custom endpoints by service, e.g. a VPC interface endpoint
+    endpoints: BTreeMap<String, String>,
+    fips: bool,
+    dualstack: bool,
+    /// Temporary credentials, shared by the clones of the configuration
+    cache: CredentialsCache,
+    /// Replaces the default chain, only set by the tests
+    default_credentials: Option<StaticProvider>,
+}
+
+impl AwsConfig {
+    /// Applies `key=value` if it is one of the arguments shared by the AWS
+    /// providers. Returns `false` if the argument is not known here.
+    pub fn parse_arg(&mut self, key: &str, value: &str) -> Result<bool, DiscoverError> {
+        match key {
+            "region" => {
+                self.region = Some(Region::from_str(value).map_err(|_| {
+                    DiscoverError::MalformedArgument(
+                        format!("region={}", value),
+                        format!("{} is not a valid AWS Region", value),
+                    )
+                })?)
+            }
+            "access_key_id" => self.access_key_id = Some(value.to_string()),
+            "secret_access_key" => self.secret_access_key = Some(value.to_string()),
+            "session_token" => self.session_token = Some(value.to_string()),
+            "role_arn" => self.role_arn = Some(value.to_string()),
+            "external_id" => self.external_id = Some(value.to_string()),
+            "role_session_name" => self.role_session_name = Some(value.to_string()),
+            "duration" => self.duration = Some(parse_duration(value)?),
+            "fips" => self.fips = parse_bool(key, value)?,
+            "dualstack" => self.dualstack = parse_bool(key, value)?,
+            _ => match ENDPOINT_SERVICES
+                .iter()
+                .find(|service| endpoint_arg(service) == key)
+            {
+                Some(service) => {
+                    self.endpoints
+                        .insert(service.to_string(), value.to_string());
+                }
+                None => return Ok(false),
+            },
+        }
+
+        Ok(true)
+    }
+
+    /// Checks that the shared arguments are complete, once all of them are parsed,
+    /// and reads the region of the environment when there is no region argument.
+    /// `services` are the services the provider sends requests to, besides sts.
+    pub fn validate(&self, services: &[&str]) -> Result<(), DiscoverError> {
+        match (&self.access_key_id, &self.secret_access_key) {
+            (Some(_), None) => Err(DiscoverError::MissingArgument("secret_access_key".into())),
+            (None, Some(_)) => Err(DiscoverError::MissingArgument("access_key_id".into())),
+            (None, None) if self.session_token.is_some() => {
+                Err(DiscoverError::MissingArgument("access_key_id".into()))
+            }
+            _ => Ok(()),
+        }?;
+
+        let role_options = [&self.external_id, &self.role_session_name];
+        if self.role_arn.is_none()
+            && (self.duration.is_some() || role_options.iter().any(|option| option.is_some()))
+        {
+            return Err(DiscoverError::MissingArgument("role_arn".into()));
+        }
+
+        for (service, endpoint) in &self.endpoints {
+            let arg = endpoint_arg(service);
+            if service != "sts" && !services.contains(&&service[..]) {
+                return Err(DiscoverError::UnexpectedArgument(arg));
+            }
+            if self.fips || self.dualstack {
+                return Err(DiscoverError::MalformedArgument(
+                    format!("{}={}", arg, endpoint),
+                    format!("{} can not be combined with fips and dualstack", arg),
+                ));
+            }
+        }
+
+        if let Some(region) = &self.region {
+            self.service_region(region, "sts")?;
+        }
+        self.read_env_region(|var| std::env::var(var).ok());
+
+        Ok(())
+    }
+
+    /// Takes the region of the environment unless the region argument is set,
+    /// so only the lookup in the instance metadata is left to `resolve_region`.
+    fn read_env_region(&self, env: impl Fn(&str) -> Option<String>) {
+        if self.region.is_some() || self.detected_region.get().is_some() {
+            return;
+        }
+        if let Some(region) = env_region(env) {
+            let _ = self.detected_region.set(region);
+        }
+    }
+
+    /// The `region` argument, or else the region of the environment, or else
+    /// the region of the instance once a lookup has detected it. Without a
+    /// region argument or environment this is provisional: us-east-1 until
+    /// the first lookup, see `resolve_region`.
+    pub fn region(&self) -> &Region {
+        static DEFAULT_REGION: Region = Region::UsEast1;
+
+        self.region
+            .as_ref()
+            .or_else(|| self.detected_region.get())
+            .unwrap_or(&DEFAULT_REGION)
+    }
+
+    /// The `region` argument, `None` when the region is detected.
+    pub fn region_arg(&self) -> Option<&Region> {
+        self.region.as_ref()
+    }
+
+    /// The `region` argument, or else the region of the environment
+    /// (`AWS_DEFAULT_REGION` or `AWS_REGION`), or else the region of the EC2
+    /// instance from its metadata. Falls back to us-east-1.
+    pub async fn resolve_region(&self) -> Region {
+        self.read_env_region(|var| std::env::var(var).ok());
+        if let Some(region) = self.region.as_ref().or_else(|| self.detected_region.get()) {
+            return region.clone();
+        }
+
+        let region = detect_region(|var| std::env::var(var).ok(), &aws_metadata::endpoint()).await;
+        self.detected_region.get_or_init(|| region).clone()
+    }
+
+    pub fn role_arn(&self) -> Option<&str> {
+        self.role_arn.as_deref()
+    }
+
+    /// The region the requests to `service`, e.g. "ec2", are sent to. It has
+    /// a custom endpoint with `<service>_endpoint`, `fips` or `dualstack`.
+    pub fn service_region(&self, region: &Region, service: &str) -> Result<Region, DiscoverError> {
+        let name = region.name().to_string();
+        if let Some(endpoint) = self.endpoints.get(service) {
+            return Ok(Region::Custom {
+                name,
+                endpoint: endpoint.clone(),
+            });
+        }
+        if !self.fips && !self.dualstack {
+            return Ok(region.clone());
+        }
+
+        let china = name.starts_with("cn-");
+        if china && self.fips {
+            return Err(DiscoverError::MalformedArgument(
+                "fips=true".to_string(),
+                format!("There are no FIPS endpoints in the China region {}", name),
+            ));
+        }
+        let domain = match (china, self.dualstack) {
+            (false, false) => "amazonaws.com",
+            (false, true) => "api.aws",
+            (true, false) => "amazonaws.com.cn",
+            (true, true) => "api.amazonwebservices.com.cn",
+        };
+
+        let endpoint = format!(
+            "https://{}{}.{}.{}",
+            service,
+            if self.fips { "-fips" } else { "" },
+            name,
+            domain
+        );
+        Ok(Region::Custom { name, endpoint })
+    }
+
+    /// A copy of the configuration for `region`, sharing the credentials.
+    pub fn with_region(&self, region: Region) -> AwsConfig {
+        AwsConfig {
+            region: Some(region),
+            ..self.clone()
+        }
+    }
+
+    /// A copy of the configuration that assumes `role_arn`, with its own
+    /// credentials cache.
+    pub fn with_role_arn(&self, role_arn: &str) -> AwsConfig {
+        AwsConfig {
+            role_arn: Some(role_arn.to_string()),
+            cache: CredentialsCache::default(),
+            ..self.clone()
+        }
+    }
+
+    /// Static credentials when they are configured, a web identity token when
+    /// `AWS_WEB_IDENTITY_TOKEN_FILE` is set, the default chain otherwise.
+    fn base_credentials(&self) -> Credentials {
+        match (&self.access_key_id, &self.secret_access_key) {
+            (Some(access_key_id), Some(secret_access_key)) => {
+                Credentials::Static(StaticProvider::new(
+                    access_key_id.clone(),
+                    secret_access_key.clone(),
+                    self.session_token.clone(),
+                    None,
+                ))
+            }
+            _ if std::env::var_os("AWS_WEB_IDENTITY_TOKEN_FILE").is_some() => {
+                Credentials::WebIdentity(Cached {
+                    provider: WebIdentityProvider::from_k8s_env(),
+                    cache: self.cache.clone(),
+                })
+            }
+            _ => match &self.default_credentials {
+                Some(provider) => Credentials::Static(provider.clone()),
+                None => Credentials::Chain(Box::new(ChainProvider::new())),
+            },
+        }
+    }
+
+    /// A copy of the configuration that uses `provider` instead of the default
+    /// chain, which reads the environment of the process.
+    #[cfg(test)]
+    pub fn with_default_credentials(&self, provider: StaticProvider) -> AwsConfig {
+        AwsConfig {
+            default_credentials: Some(provider),
+            ..self.clone()
+        }
+    }
+
+    /// The base credentials, or the role assumed with them in `region` when
+    /// `role_arn` is set.
+    pub fn credentials(&self, region: &Region) -> Result<Credentials, DiscoverError> {
+        let role_arn = match &self.role_arn {
+            Some(role_arn) => role_arn,
+            None => return Ok(self.base_credentials()),
+        };
+
+        // The base credentials are only used to refresh the assumed role, they
+        // are not cached on their own.
+        let base = match self.base_credentials() {
+            Credentials::WebIdentity(Cached { provider, .. }) => Credentials::WebIdentity(Cached {
+                provider,
+                cache: CredentialsCache::default(),
+            }),
+            base => base,
+        };
+        let sts = StsClient::new_with(http_client()?, base, self.service_region(region, "sts")?);
+        let provider = StsAssumeRoleSessionCredentialsProvider::new(
+            sts,
+            role_arn.clone(),
+            self.role_session_name
+                .clone()
+                .unwrap_or_else(|| DEFAULT_ROLE_SESSION_NAME.to_string()),
+            self.external_id.clone(),
+            self.duration
+                .map(|duration| chrono::Duration::seconds(duration.as_secs() as i64)),
+            None,
+            None,
+        );
+
+        Ok(Credentials::AssumeRole(Cached {
+            provider: AssumeRole(Arc::new(provider)),
+            cache: self.cache.clone(),
+        }))
+    }
+
+    /// Builds a rusoto client of `service` with the configured region and
+    /// credentials, e.g. `config.client("ec2", Ec2Client::new_with).await`.
+    pub async fn client<C>(
+        &self,
+        service: &str,
+        new_with: impl FnOnce(HttpClient, Credentials, Region) -> C,
+    ) -> Result<C, DiscoverError> {
+        let region = self.resolve_region().await;
+
+        Ok(new_with(
+            http_client()?,
+            self.credentials(&region)?,
+            self.service_region(&region, service)?,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::providers::stub::{self, StubResponse};
+
+    #[test]
+    fn parse_shared_arguments() {
+        let mut config = AwsConfig::default();
+        assert_eq!(config.parse_arg("region", "eu-west-1"), Ok(true));
+        assert_eq!(config.region(), &Region::EuWest1);
+        assert_eq!(config.region_arg(), Some(&Region::EuWest1));
+        assert_eq!(config.parse_arg("tag_key", "consul"), Ok(false));
+        assert_eq!(
+            config.parse_arg("region", "mars-1"),
+            Err(DiscoverError::MalformedArgument(
+                "region=mars-1".to_string(),
+                "mars-1 is not a valid AWS Region".to_string()
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn use_static_credentials_when_configured() {
+        let mut config = AwsConfig::default();
+        assert!(matches!(
+            config.credentials(&Region::UsEast1),
+            Ok(Credentials::Chain(_))
+        ));
+
+        config.parse_arg("access_key_id", "AKIDEXAMPLE").unwrap();
+        assert_eq!(
+            config.validate(&["ec2"]),
+            Err(DiscoverError::MissingArgument(
+                "secret_access_key".to_string()
+            ))
+        );
+        config.parse_arg("secret_access_key", "secret").unwrap();
+        config.parse_arg("session_token", "token").unwrap();
+        assert_eq!(config.validate(&["ec2"]), Ok(()));
+
+        let credentials = config
+            .credentials(&Region::UsEast1)
+            .unwrap()
+            .credentials()
+            .await
+            .unwrap();
+        assert_eq!(credentials.aws_access_key_id(), "AKIDEXAMPLE");
+        assert_eq!(credentials.aws_secret_access_key(), "secret");
+        assert_eq!(credentials.token().as_deref(), Some("token"));
+
+        let mut config = AwsConfig::default();
+        config.parse_arg("session_token", "token").unwrap();
+        assert_eq!(
+            config.validate(&["ec2"]),
+            Err(DiscoverError::MissingArgument("access_key_id".to_string()))
+        );
+    }
+
+    #[test]
+    fn parse_role_arguments() {
+        let mut config = AwsConfig::default();
+        assert_eq!(config.parse_arg("duration", "2h"), Ok(true));
+        assert_eq!(config.duration, Some(Duration::from_secs(7200)));
+        assert_eq!(config.parse_arg("duration", "900"), Ok(true));
+        assert_eq!(config.duration, Some(Duration::from_secs(900)));
+        for invalid in &[
+            "10m",
+            "13h",
+            "1d",
+            "h",
+            "18446744073709551615m",
+            "5124095576030432h",
+        ] {
+            assert!(matches!(
+                config.parse_arg("duration", invalid),
+                Err(DiscoverError::MalformedArgument(arg, _)) if arg == format!("duration={}", invalid)
+            ));
+        }
+
+        assert_eq!(
+            config.validate(&["ec2"]),
+            Err(DiscoverError::MissingArgument("role_arn".to_string()))
+        );
+        config
+            .parse_arg("role_arn", "arn:aws:iam::123456789012:role/consul")
+            .unwrap();
+        config.parse_arg("external_id", "consul").unwrap();
+        assert_eq!(config.validate(&["ec2"]), Ok(()));
+        assert_eq!(
+            config.role_arn(),
+            Some("arn:aws:iam::123456789012:role/consul")
+        );
+        assert!(matches!(
+            config.credentials(&Region::UsEast1),
+            Ok(Credentials::AssumeRole(_))
+        ));
+    }
+
+    #[test]
+    fn build_custom_endpoints() {
+        let mut config = AwsConfig::default();
+        let region = Region::UsGovWest1;
+        assert_eq!(
+            config.service_region(&region, "ec2"),
+            Ok(Region::UsGovWest1)
+        );
+
+        config.parse_arg("fips", "true").unwrap();
+        assert_eq!(
+            config.service_region(&region, "ec2"),
+            Ok(Region::Custom {
+                name: "us-gov-west-1".to_string(),
+                endpoint: "https://ec2-fips.us-gov-west-1.amazonaws.com".to_string()
+            })
+        );
+        config.parse_arg("dualstack", "true").unwrap();
+        assert_eq!(
+            config.service_region(&region, "sts"),
+            Ok(Region::Custom {
+                name: "us-gov-west-1".to_string(),
+                endpoint: "https://sts-fips.us-gov-west-1.api.aws".to_string()
+            })
+        );
+        assert!(matches!(
+            config.parse_arg("dualstack", "yes"),
+            Err(DiscoverError::MalformedArgument(arg, _)) if arg == "dualstack=yes"
+        ));
+
+        config
+            .parse_arg(
+                "endpoint",
+                "https://vpce-0a1b2c.ec2.us-gov-west-1.vpce.amazonaws.com",
+            )
+            .unwrap();
+        assert!(matches!(
+            config.validate(&["ec2"]),
+            Err(DiscoverError::MalformedArgument(arg, _)) if arg.starts_with("endpoint=")
+        ));
+        config.parse_arg("fips", "false").unwrap();
+        config.parse_arg("dualstack", "false").unwrap();
+        assert_eq!(config.validate(&["ec2"]), Ok(()));
+        assert_eq!(
+            config.service_region(&region, "ec2"),
+            Ok(Region::Custom {
+                name: "us-gov-west-1".to_string(),
+                endpoint: "https://vpce-0a1b2c.ec2.us-gov-west-1.vpce.amazonaws.com".to_string()
+            })
+        );
+        // endpoint only replaces the endpoint of ec2
+        assert_eq!(
+            config.service_region(&region, "sts"),
+            Ok(Region::UsGovWest1)
+        );
+
+        config
+            .parse_arg("sts_endpoint", "http://127.0.0.1:4566")
+            .unwrap();
+        assert_eq!(
+            config.service_region(&region, "sts"),
+            Ok(Region::Custom {
+                name: "us-gov-west-1".to_string(),
+                endpoint: "http://127.0.0.1:4566".to_string()
+            })
+        );
+        assert_eq!(
+            config.parse_arg("ecs_endpoint", "http://127.0.0.1:4566"),
+            Ok(true)
+        );
+        assert_eq!(
+            config.validate(&["ec2"]),
+            Err(DiscoverError::UnexpectedArgument(
+                "ecs_endpoint".to_string()
+            ))
+        );
+        assert_eq!(config.validate(&["ec2", "ecs"]), Ok(()));
+        assert_eq!(
+            config.parse_arg("s3_endpoint", "http://127.0.0.1:4566"),
+            Ok(false)
+        );
+    }
+
+    #[test]
+    fn build_endpoints_of_the_china_regions() {
+        let mut config = AwsConfig::default();
+        let region = Region::CnNorth1;
+        config.parse_arg("dualstack", "true").unwrap();
+        assert_eq!(
+            config.service_region(&region, "ec2"),
+            Ok(Region::Custom {
+                name: "cn-north-1".to_string(),
+                endpoint: "https://ec2.cn-north-1.api.amazonwebservices.com.cn".to_string()
+            })
+        );
+
+        config.parse_arg("fips", "true").unwrap();
+        assert!(matches!(
+            config.service_region(&region, "ec2"),
+            Err(DiscoverError::MalformedArgument(arg, _)) if arg == "fips=true"
+        ));
+        config.parse_arg("region", "cn-northwest-1").unwrap();
+        assert!(matches!(
+            config.validate(&["ec2"]),
+            Err(DiscoverError::MalformedArgument(arg, _)) if arg == "fips=true"
+        ));
+    }
+
+    fn env(vars: &'static [(&'static str, &'static str)]) -> impl Fn(&str) -> Option<String> {
+        move |var: &str| {
+            vars.iter()
+                .find(|(name, _)| *name == var)
+                .map(|(_, value)| value.to_string())
+        }
+    }
+
+    #[test]
+    fn read_the_region_of_the_environment() {
+        assert_eq!(
+            env_region(env(&[("AWS_REGION", "eu-west-1")])),
+            Some(Region::EuWest1)
+        );
+        assert_eq!(
+            env_region(env(&[
+                ("AWS_DEFAULT_REGION", "eu-north-1"),
+                ("AWS_REGION", "eu-west-1")
+            ])),
+            Some(Region::EuNorth1)
+        );
+        // An invalid region in the environment is ignored
+        assert_eq!(env_region(env(&[("AWS_DEFAULT_REGION", "mars-1")])), None);
+
+        // Read before the first lookup, the region is not provisional
+        let config = AwsConfig::default();
+        config.read_env_region(env(&[("AWS_REGION", "eu-west-1")]));
+        assert_eq!(config.region(), &Region::EuWest1);
+        assert_eq!(config.region_arg(), None);
+
+        // The region argument wins over the environment
+        let mut config = AwsConfig::default();
+        config.parse_arg("region", "us-west-2").unwrap();
+        config.read_env_region(env(&[("AWS_REGION", "eu-west-1")]));
+        assert_eq!(config.region(), &Region::UsWest2);
+        assert!(config.detected_region.get().is_none());
+    }
+
+    #[tokio::test]
+    async fn detect_the_region_of_the_instance() {
+        let stub = stub::serve_tcp(|req| match &req.path[..] {
+            "/latest/api/token" => StubResponse::new(200, "text/plain", "token-123"),
+            _ => StubResponse::json(r#"{"region": "eu-central-1"}"#),
+        })
+        .await;
+
+        assert_eq!(
+            detect_region(env(&[]), stub.url()).await,
+            Region::EuCentral1
+        );
+        let requests = stub.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].method, "PUT");
+        assert_eq!(
+            requests[1].header("x-aws-ec2-metadata-token"),
+            Some("token-123")
+        );
+
+        assert_eq!(
+            detect_region(env(&[("AWS_EC2_METADATA_DISABLED", "true")]), stub.url()).await,
+            Region::UsEast1
+        );
+        assert_eq!(stub.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn share_the_detected_region() {
+        let config = AwsConfig::default();
+        assert_eq!(config.region_arg(), None);
+        assert_eq!(config.region(), &Region::UsEast1);
+
+        config.detected_region.set(Region::EuCentral1).unwrap();
+        assert_eq!(config.region(), &Region::EuCentral1);
+        // The clones share the detected region
+        assert_eq!(config.clone().resolve_region().await, Region::EuCentral1);
+
+        let config = config.with_region(Region::EuWest1);
+        assert_eq!(config.region(), &Region::EuWest1);
+        assert_eq!(config.resolve_region().await, Region::EuWest1);
+    }
+}
@@ -0,0 +1,396 @@
+use log::{debug, info, warn};
+use rusoto_core::Region;
+use rusoto_ecs::{DescribeTasksRequest, Ecs, EcsClient, ListTasksRequest, Task};
+
+use std::convert::TryFrom;
+
+use crate::{args::ParsedArgs, SupportedProvider};
+
+use super::{aws_config::AwsConfig, DiscoverError, Provider};
+
+/// DescribeTasks accepts at most 100 tasks per call.
+const DESCRIBE_TASKS_BATCH: usize = 100;
+
+#[derive(Debug, Clone)]
+pub struct ECSProvider {
+    cluster: Option<String>,
+    service: Option<String>,
+    family: Option<String>,
+    config: AwsConfig,
+}
+
+impl TryFrom<ParsedArgs> for ECSProvider {
+    type Error = DiscoverError;
+
+    fn try_from(args: ParsedArgs) -> Result<Self, Self::Error> {
+        let mut cluster = None;
+        let mut service = None;
+        let mut family = None;
+        let mut config = AwsConfig::default();
+
+        for (key, value) in args {
+            match &key[..] {
+                "cluster" => cluster = Some(value),
+                "service" => service = Some(value),
+                "family" => family = Some(value),
+                _ => {
+                    if !config.parse_arg(&key, &value)? {
+                        return Err(DiscoverError::UnexpectedArgument(key));
+                    }
+                }
+            }
+        }
+        config.validate(&["ecs"])?;
+
+        Ok(ECSProvider {
+            cluster,
+            service,
+            family,
+            config,
+        })
+    }
+}
+
+impl TryFrom<Vec<String>> for ECSProvider {
+    type Error = DiscoverError;
+
+    fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
+        let args = ParsedArgs::try_from(value)?;
+        match *args.provider() {
+            SupportedProvider::ECS => ECSProvider::try_from(args),
+            _ => Err(DiscoverError::MalformedArgument(
+                format!("provider={}", args.provider()),
+                "you should not see this ...".to_string(),
+            )),
+        }
+    }
+}
+
+/// Returns the private IPs of the awsvpc network interfaces of a task.
+fn task_addrs(task: &Task) -> Vec<String> {
+    task.attachments
+        .iter()
+        .flatten()
+        .filter(|attachment| attachment.type_.as_deref() == Some("ElasticNetworkInterface"))
+        .flat_map(|attachment| attachment.details.iter().flatten())
+        .filter(|detail| detail.name.as_deref() == Some("privateIPv4Address"))
+        .filter_map(|detail| detail.value.clone())
+        .collect()
+}
+
+impl ECSProvider {
+    pub fn cluster(&self) -> Option<&str> {
+        self.cluster.as_deref()
+    }
+
+    pub fn service(&self) -> Option<&str> {
+        self.service.as_deref()
+    }
+
+    pub fn family(&self) -> Option<&str> {
+        self.family.as_deref()
+    }
+
+    /// The `region` argument, or else the region of the environment. Without
+    /// either this is provisional: us-east-1 until the first lookup has read
+    /// the region of the instance.
+    pub fn region(&self) -> &Region {
+        self.config.region()
+    }
+
+    /// The `region` argument, `None` when the region is detected.
+    pub fn region_arg(&self) -> Option<&Region> {
+        self.config.region_arg()
+    }
+
+    async fn get_tasks(&self) -> Result<Vec<Task>, DiscoverError> {
+        let client = self.config.client("ecs", EcsClient::new_with).await?;
+
+        debug!(
+            "Using region={:?} cluster={:?} service={:?} family={:?}",
+            self.region(),
+            self.cluster,
+            self.service,
+            self.family
+        );
+
+        let mut task_arns = Vec::new();
+        let mut next_token = None;
+        loop {
+            let input = ListTasksRequest {
+                cluster: self.cluster.clone(),
+                service_name: self.service.clone(),
+                family: self.family.clone(),
+                desired_status: Some("RUNNING".into()),
+                next_token,
+                ..Default::default()
+            };
+            let res = client
+                .list_tasks(input)
+                .await
+                .map_err(|e| DiscoverError::ProviderRequestFailed(format!("{:?}", e)))?;
+            task_arns.extend(res.task_arns.unwrap_or_default());
+
+            next_token = res.next_token;
+            if next_token.is_none() {
+                break;
+            }
+        }
+        debug!("Found {} tasks", task_arns.len());
+
+        let mut tasks = Vec::new();
+        for batch in task_arns.chunks(DESCRIBE_TASKS_BATCH) {
+            let input = DescribeTasksRequest {
+                cluster: self.cluster.clone(),
+                tasks: batch.to_vec(),
+                ..Default::default()
+            };
+            let res = client
+                .describe_tasks(input)
+                .await
+                .map_err(|e| DiscoverError::ProviderRequestFailed(format!("{:?}", e)))?;
+
+            for failure in res.failures.unwrap_or_default() {
+                warn!(
+                    "Unable to describe task {:?}: {:?}",
+                    failure.arn, failure.reason
+                );
+            }
+            tasks.extend(res.tasks.unwrap_or_default());
+        }
+
+        Ok(tasks)
+    }
+}
+
+#[async_trait::async_trait]
+impl Provider for ECSProvider {
+    async fn addrs(&self) -> Result<Vec<String>, DiscoverError> {
+        let tasks = self.get_tasks().await?;
+
+        let mut addrs = Vec::new();
+        for task in tasks {
+            if task.last_status.as_deref() != Some("RUNNING") {
+                debug!(
+                    "Skipping task {:?} with status {:?}",
+                    task.task_arn, task.last_status
+                );
+                continue;
+            }
+
+            let task_addrs = task_addrs(&task);
+            if task_addrs.is_empty() {
+                debug!("Task {:?} has no awsvpc network interface", task.task_arn);
+            }
+            for addr in task_addrs {
+                info!("Task {:?} has private ip {:?}", task.task_arn, addr);
+                addrs.push(addr);
+            }
+        }
+
+        Ok(addrs)
+    }
+
+    fn help() -> &'static str {
+        "Amazon ECS:
+
+	provider:          \"aws-ecs\"
+	region:            The AWS region. Defaults to AWS_DEFAULT_REGION or AWS_REGION, or else to
+	                   the region of the instance from the instance metadata (IMDSv2).
+	cluster:           The cluster the tasks run in. Defaults to the default cluster.
+	service:           Only return the tasks of this service
+	family:            Only return the tasks of this task definition family
+	access_key_id:     The AWS access key to use
+	secret_access_key: The AWS secret access key to use
+	session_token:     The session token of temporary credentials
+	role_arn:          The IAM role to assume with the credentials, e.g. of another account
+	external_id:       The external ID the role's trust policy requires
+	role_session_name: The session name of the assumed role. Defaults to \"node-discover\".
+	duration:          How long the assumed role credentials are valid, between 900s and
+	                   12h. Defaults to 1h.
+	ecs_endpoint:      The URL the ECS requests are sent to instead of the AWS endpoint
+	sts_endpoint:      The URL the STS requests of role_arn are sent to
+	fips:              \"true\" to use the FIPS endpoints. Defaults to \"false\".
+	dualstack:         \"true\" to use the IPv4 and IPv6 endpoints. Defaults to \"false\".
+
+	Only RUNNING tasks using the awsvpc network mode, e.g. Fargate tasks, are returned
+	with the private IPv4 address of their network interface. The required IAM
+	permissions are 'ecs:ListTasks' and 'ecs:DescribeTasks'. Without access_key_id and
+	secret_access_key the credentials are read the same way as by the \"aws\" provider.
+	"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::providers::stub::{self, StubResponse};
+    use rusoto_ecs::{Attachment, KeyValuePair};
+
+    fn detail(name: &str, value: &str) -> KeyValuePair {
+        KeyValuePair {
+            name: Some(name.to_string()),
+            value: Some(value.to_string()),
+        }
+    }
+
+    #[test]
+    fn ecs_provider_from_string() {
+        let args = "provider=aws-ecs region=eu-west-1 cluster=consul service=consul-server";
+
+        let res = ParsedArgs::try_from(args.to_string());
+        assert!(res.is_ok());
+        let res = ECSProvider::try_from(res.unwrap());
+        assert!(res.is_ok());
+        let provider = res.unwrap();
+        assert_eq!(provider.cluster(), Some("consul"));
+        assert_eq!(provider.service(), Some("consul-server"));
+        assert_eq!(provider.family(), None);
+        assert_eq!(provider.region(), &Region::EuWest1);
+        assert_eq!(provider.region_arg(), Some(&Region::EuWest1));
+    }
+
+    #[test]
+    fn fail_on_unexpected_argument() {
+        let args = "provider=aws-ecs cluster=consul tag_key=consul";
+
+        let res = ECSProvider::try_from(ParsedArgs::try_from(args.to_string()).unwrap());
+        assert_eq!(
+            res.unwrap_err(),
+            DiscoverError::UnexpectedArgument("tag_key".to_string())
+        );
+    }
+
+    #[test]
+    fn extract_eni_private_ips() {
+        let task = Task {
+            attachments: Some(vec![
+                Attachment {
+                    type_: Some("ElasticNetworkInterface".to_string()),
+                    details: Some(vec![
+                        detail("subnetId", "subnet-12345678"),
+                        detail("networkInterfaceId", "eni-12345678"),
+                        detail("privateIPv4Address", "10.0.1.17"),
+                    ]),
+                    ..Default::default()
+                },
+                Attachment {
+                    type_: Some("ServiceConnect".to_string()),
+                    details: Some(vec![detail("privateIPv4Address", "10.0.9.9")]),
+                    ..Default::default()
+                },
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(task_addrs(&task), vec!["10.0.1.17".to_string()]);
+
+        assert!(task_addrs(&Task::default()).is_empty());
+    }
+
+    fn task_arn(i: usize) -> String {
+        format!("arn:aws:ecs:us-east-1:123456789012:task/consul/{:032}", i)
+    }
+
+    fn task_ip(i: usize) -> String {
+        format!("10.0.{}.{}", i / 100, i % 100)
+    }
+
+    /// 150 running tasks, listed in pages of 120.
+    async fn ecs_stub() -> stub::Stub {
+        stub::serve_tcp(|req| {
+            let body = serde_json::from_str::<serde_json::Value>(&req.body).unwrap();
+            match req.header("x-amz-target") {
+                Some("AmazonEC2ContainerServiceV20141113.ListTasks") => {
+                    let res = match body["nextToken"].as_str() {
+                        None => serde_json::json!({
+                            "taskArns": (0..120).map(task_arn).collect::<Vec<_>>(),
+                            "nextToken": "page-2",
+                        }),
+                        Some(_) => serde_json::json!({
+                            "taskArns": (120..150).map(task_arn).collect::<Vec<_>>(),
+                        }),
+                    };
+                    StubResponse::json(&res.to_string())
+                }
+                Some("AmazonEC2ContainerServiceV20141113.DescribeTasks") => {
+                    let tasks = body["tasks"]
+                        .as_array()
+                        .unwrap()
+                        .iter()
+                        .map(|arn| {
+                            let arn = arn.as_str().unwrap();
+                            let i = arn.rsplit('/').next().unwrap().parse::<usize>().unwrap();
+                            serde_json::json!({
+                                "taskArn": arn,
+                                "lastStatus": "RUNNING",
+                                "attachments": [{
+                                    "type": "ElasticNetworkInterface",
+                                    "details": [
+                                        {"name": "privateIPv4Address", "value": task_ip(i)},
+                                    ],
+                                }],
+                            })
+                        })
+                        .collect::<Vec<_>>();
+                    StubResponse::json(&serde_json::json!({ "tasks": tasks }).to_string())
+                }
+                target => panic!("Unexpected request {:?}", target),
+            }
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn list_and_describe_every_task() {
+        let stub = ecs_stub().await;
+        let args = format!(
+            "provider=aws-ecs region=us-east-1 cluster=consul service=consul-server access_key_id=AKIDSTATIC secret_access_key=secret ecs_endpoint={}",
+            stub.url()
+        );
+        let provider = ECSProvider::try_from(ParsedArgs::try_from(args).unwrap()).unwrap();
+
+        let mut addrs = provider.addrs().await.unwrap();
+        addrs.sort();
+        let mut expected = (0..150).map(task_ip).collect::<Vec<_>>();
+        expected.sort();
+        assert_eq!(addrs, expected);
+
+        let requests = stub
+            .requests()
+            .iter()
+            .map(|req| {
+                (
+                    req.header("x-amz-target").unwrap().to_string(),
+                    serde_json::from_str::<serde_json::Value>(&req.body).unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        let targets = requests
+            .iter()
+            .map(|(target, _)| target.rsplit('.').next().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            targets,
+            vec!["ListTasks", "ListTasks", "DescribeTasks", "DescribeTasks"]
+        );
+
+        assert_eq!(
+            requests[0].1,
+            serde_json::json!({
+                "cluster": "consul",
+                "serviceName": "consul-server",
+                "desiredStatus": "RUNNING",
+            })
+        );
+        assert_eq!(requests[1].1["nextToken"], "page-2");
+
+        let batch = |i: usize| requests[i].1["tasks"].as_array().unwrap().clone();
+        assert_eq!(batch(2).len(), 100);
+        assert_eq!(batch(3).len(), 50);
+        assert_eq!(batch(2)[0], task_arn(0));
+        assert_eq!(batch(3)[49], task_arn(149));
+        for (_, body) in &requests[2..] {
+            assert_eq!(body["cluster"], "consul");
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use hyper::{body, Body, Client, Method, Request};
+use serde::Deserialize;
+
+use std::time::Duration;
+
+use super::DiscoverError;
+
+pub(crate) const DEFAULT_ENDPOINT: &str = "http://169.254.169.254";
+
+/// Each request to the metadata service gives up after this long, so the
+/// lookup fails fast outside of EC2.
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The token is only used for the identity document.
+const TOKEN_TTL_SECONDS: &str = "60";
+
+#[derive(Debug, Deserialize)]
+struct IdentityDocument {
+    pub region: String,
+}
+
+/// The metadata service of `AWS_EC2_METADATA_SERVICE_ENDPOINT`, or the
+/// default one.
+pub(crate) fn endpoint() -> String {
+    std::env::var("AWS_EC2_METADATA_SERVICE_ENDPOINT")
+        .map(|endpoint| endpoint.trim_end_matches('/').to_string())
+        .unwrap_or_else(|_| DEFAULT_ENDPOINT.to_string())
+}
+
+async fn send(req: Request<Body>) -> Result<Vec<u8>, DiscoverError> {
+    let uri = req.uri().clone();
+    let failed = |e: String| {
+        DiscoverError::ProviderRequestFailed(format!(
+            "Instance metadata request {} failed: {}",
+            uri, e
+        ))
+    };
+
+    let res = tokio::time::timeout(TIMEOUT, Client::new().request(req))
+        .await
+        .map_err(|_| failed(format!("no response within {:?}", TIMEOUT)))?
+        .map_err(|e| failed(e.to_string()))?;
+    if !res.status().is_success() {
+        return Err(failed(format!("status {}", res.status())));
+    }
+
+    let body = tokio::time::timeout(TIMEOUT, body::to_bytes(res.into_body()))
+        .await
+        .map_err(|_| failed(format!("no response within {:?}", TIMEOUT)))?
+        .map_err(|e| failed(e.to_string()))?;
+
+    Ok(body.to_vec())
+}
+
+/// The region of the instance, from the identity document of the metadata
+/// service at `endpoint`. Uses IMDSv2, i.e. a session token is requested first.
+pub(crate) async fn instance_region(endpoint: &str) -> Result<String, DiscoverError> {
+    let req = Request::builder()
+        .method(Method::PUT)
+        .uri(format!("{}/latest/api/token", endpoint))
+        .header("X-aws-ec2-metadata-token-ttl-seconds", TOKEN_TTL_SECONDS)
+        .body(Body::empty())
+        .map_err(|e| DiscoverError::ProviderRequestFailed(e.to_string()))?;
+    let token = String::from_utf8(send(req).await?).map_err(|e| {
+        DiscoverError::ProviderRequestFailed(format!("Invalid metadata token: {}", e))
+    })?;
+
+    let req = Request::builder()
+        .method(Method::GET)
+        .uri(format!(
+            "{}/latest/dynamic/instance-identity/document",
+            endpoint
+        ))
+        .header("X-aws-ec2-metadata-token", token)
+        .body(Body::empty())
+        .map_err(|e| DiscoverError::ProviderRequestFailed(e.to_string()))?;
+    let document: IdentityDocument = serde_json::from_slice(&send(req).await?).map_err(|e| {
+        DiscoverError::ProviderRequestFailed(format!("Invalid identity document: {}", e))
+    })?;
+
+    Ok(document.region)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::providers::stub::{self, StubResponse};
+
+    async fn metadata_stub() -> stub::Stub {
+        stub::serve_tcp(|req| match (&req.method[..], &req.path[..]) {
+            ("PUT", "/latest/api/token") => StubResponse::new(200, "text/plain", "token-123"),
+            ("GET", "/latest/dynamic/instance-identity/document")
+                if req.header("x-aws-ec2-metadata-token") == Some("token-123") =>
+            {
+                StubResponse::json(
+                    r#"{"accountId": "123456789012", "instanceId": "i-1234567890abcdef0", "region": "eu-central-1"}"#,
+                )
+            }
+            _ => StubResponse::new(401, "text/plain", ""),
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn read_the_region_of_the_identity_document() {
+        let stub = metadata_stub().await;
+
+        assert_eq!(
+            instance_region(stub.url()).await,
+            Ok("eu-central-1".to_string())
+        );
+        let requests = stub.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(
+            requests[0].header("x-aws-ec2-metadata-token-ttl-seconds"),
+            Some(TOKEN_TTL_SECONDS)
+        );
+    }
+
+    #[tokio::test]
+    async fn fail_without_a_token() {
+        let stub = stub::serve_tcp(|_| StubResponse::new(403, "text/plain", "")).await;
+
+        let res = instance_region(stub.url()).await;
+        assert!(
+            matches!(&res, Err(DiscoverError::ProviderRequestFailed(e)) if e.contains("status 403")),
+            "{:?}",
+            res
+        );
+    }
+
+    #[tokio::test]
+    async fn fail_fast_without_a_response() {
+        // Connections are accepted by the backlog but never answered
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+
+        let start = std::time::Instant::now();
+        let res = instance_region(&endpoint).await;
+        assert!(
+            matches!(&res, Err(DiscoverError::ProviderRequestFailed(e)) if e.contains("no response")),
+            "{:?}",
+            res
+        );
+        assert!(start.elapsed() < TIMEOUT * 2);
+    }
+}
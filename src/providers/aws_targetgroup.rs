@@ -0,0 +1,444 @@
+use log::{debug, info};
+use rusoto_core::Region;
+use rusoto_ec2::{DescribeInstancesRequest, Ec2, Ec2Client};
+use rusoto_elbv2::{DescribeTargetHealthInput, Elb, ElbClient, TargetHealthDescription};
+
+use std::{collections::HashMap, convert::TryFrom};
+
+use crate::{args::ParsedArgs, SupportedProvider};
+
+use super::{aws_config::AwsConfig, parse_bool, DiscoverError, Provider};
+
+#[derive(Debug, Clone)]
+pub struct TargetGroupProvider {
+    target_group_arn: String,
+    healthy_only: bool,
+    config: AwsConfig,
+}
+
+impl TryFrom<ParsedArgs> for TargetGroupProvider {
+    type Error = DiscoverError;
+
+    fn try_from(args: ParsedArgs) -> Result<Self, Self::Error> {
+        let mut target_group_arn = None;
+        let mut healthy_only = true;
+        let mut config = AwsConfig::default();
+
+        for (key, value) in args {
+            match &key[..] {
+                "target_group_arn" => target_group_arn = Some(value),
+                "healthy_only" => healthy_only = parse_bool(&key, &value)?,
+                _ => {
+                    if !config.parse_arg(&key, &value)? {
+                        return Err(DiscoverError::UnexpectedArgument(key));
+                    }
+                }
+            }
+        }
+        config.validate(&["elasticloadbalancing", "ec2"])?;
+
+        let target_group_arn = target_group_arn
+            .ok_or_else(|| DiscoverError::MissingArgument("target_group_arn".into()))?;
+
+        Ok(TargetGroupProvider {
+            target_group_arn,
+            healthy_only,
+            config,
+        })
+    }
+}
+
+impl TryFrom<Vec<String>> for TargetGroupProvider {
+    type Error = DiscoverError;
+
+    fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
+        let args = ParsedArgs::try_from(value)?;
+        match *args.provider() {
+            SupportedProvider::TargetGroup => TargetGroupProvider::try_from(args),
+            _ => Err(DiscoverError::MalformedArgument(
+                format!("provider={}", args.provider()),
+                "you should not see this ...".to_string(),
+            )),
+        }
+    }
+}
+
+/// The targets of a target group, split by how they are resolved to addresses.
+#[derive(Debug, Default, PartialEq)]
+struct Targets {
+    /// Id and port of instance targets, they still need to be resolved to an IP.
+    /// An instance is listed once per port it is registered with.
+    instances: Vec<(String, Option<i64>)>,
+    /// "ip:port" of IP targets
+    ip_addrs: Vec<String>,
+}
+
+fn target_addr(ip: &str, port: Option<i64>) -> String {
+    match port {
+        Some(port) => format!("{}:{}", ip, port),
+        None => ip.to_string(),
+    }
+}
+
+fn split_targets(descriptions: Vec<TargetHealthDescription>, healthy_only: bool) -> Targets {
+    let mut targets = Targets::default();
+
+    for description in descriptions {
+        let target = match description.target {
+            Some(target) => target,
+            None => continue,
+        };
+        let state = description
+            .target_health
+            .and_then(|health| health.state)
+            .unwrap_or_default();
+        if healthy_only && state != "healthy" {
+            debug!("Skipping target {} with state {:?}", target.id, state);
+            continue;
+        }
+
+        if target.id.starts_with("i-") {
+            let instance = (target.id, target.port);
+            if !targets.instances.contains(&instance) {
+                targets.instances.push(instance);
+            }
+        } else if target.id.parse::<std::net::IpAddr>().is_ok() {
+            let addr = target_addr(&target.id, target.port);
+            info!("Found IP target {}", addr);
+            targets.ip_addrs.push(addr);
+        } else {
+            // Lambda functions and ALBs can be targets as well
+            debug!(
+                "Skipping target {} which is not an instance or IP",
+                target.id
+            );
+        }
+    }
+
+    targets
+}
+
+impl TargetGroupProvider {
+    pub fn target_group_arn(&self) -> &str {
+        &self.target_group_arn
+    }
+
+    pub fn healthy_only(&self) -> bool {
+        self.healthy_only
+    }
+
+    /// The `region` argument, or else the region of the environment. Without
+    /// either this is provisional: us-east-1 until the first lookup has read
+    /// the region of the instance.
+    pub fn region(&self) -> &Region {
+        self.config.region()
+    }
+
+    /// The `region` argument, `None` when the region is detected.
+    pub fn region_arg(&self) -> Option<&Region> {
+        self.config.region_arg()
+    }
+
+    async fn get_targets(&self) -> Result<Vec<TargetHealthDescription>, DiscoverError> {
+        let client = self
+            .config
+            .client("elasticloadbalancing", ElbClient::new_with)
+            .await?;
+
+        debug!(
+            "Using region={:?} target_group_arn={} healthy_only={}",
+            self.region(),
+            self.target_group_arn,
+            self.healthy_only
+        );
+
+        let input = DescribeTargetHealthInput {
+            target_group_arn: self.target_group_arn.clone(),
+            ..Default::default()
+        };
+
+        client
+            .describe_target_health(input)
+            .await
+            .map(|res| res.target_health_descriptions.unwrap_or_default())
+            .map_err(|e| DiscoverError::ProviderRequestFailed(format!("{:?}", e)))
+    }
+
+    /// Returns "ip:port" of the instance targets, with the private IP of the
+    /// instance and the port it is registered with.
+    async fn get_instance_addrs(
+        &self,
+        instances: &[(String, Option<i64>)],
+    ) -> Result<Vec<String>, DiscoverError> {
+        let client = self.config.client("ec2", Ec2Client::new_with).await?;
+
+        let mut instance_ids = Vec::new();
+        for (id, _) in instances {
+            if !instance_ids.contains(id) {
+                instance_ids.push(id.clone());
+            }
+        }
+        let input = DescribeInstancesRequest {
+            instance_ids: Some(instance_ids),
+            ..Default::default()
+        };
+        let res = client
+            .describe_instances(input)
+            .await
+            .map_err(|e| DiscoverError::ProviderRequestFailed(format!("{:?}", e)))?;
+
+        let described = res
+            .reservations
+            .unwrap_or_default()
+            .into_iter()
+            .flat_map(|reservation| reservation.instances.unwrap_or_default());
+
+        let mut ips = HashMap::new();
+        for instance in described {
+            match (instance.instance_id, instance.private_ip_address) {
+                (Some(id), Some(ip)) => {
+                    info!("Instance {} has private ip {}", id, ip);
+                    ips.insert(id, ip);
+                }
+                (id, _) => debug!("Instance {:?} has no private ip", id),
+            }
+        }
+
+        Ok(instances
+            .iter()
+            .filter_map(|(id, port)| ips.get(id).map(|ip| target_addr(ip, *port)))
+            .collect())
+    }
+}
+
+#[async_trait::async_trait]
+impl Provider for TargetGroupProvider {
+    async fn addrs(&self) -> Result<Vec<String>, DiscoverError> {
+        let descriptions = self.get_targets().await?;
+        debug!("Found {} targets", descriptions.len());
+
+        let targets = split_targets(descriptions, self.healthy_only);
+        let mut addrs = targets.ip_addrs;
+        // An empty list of ids would describe every instance in the region
+        if !targets.instances.is_empty() {
+            addrs.extend(self.get_instance_addrs(&targets.instances).await?);
+        }
+
+        Ok(addrs)
+    }
+
+    fn help() -> &'static str {
+        "AWS ELB target group:
+
+	provider:                      \"aws-targetgroup\"
+	region:                        The AWS region. Defaults to AWS_DEFAULT_REGION or AWS_REGION,
+	                               or else to the region of the instance from the instance
+	                               metadata (IMDSv2).
+	target_group_arn:              The ARN of the target group of an ALB or NLB
+	healthy_only:                  Only return targets that pass their health checks, \"true\"
+	                               or \"false\". Defaults to \"true\".
+	access_key_id:                 The AWS access key to use
+	secret_access_key:             The AWS secret access key to use
+	session_token:                 The session token of temporary credentials
+	role_arn:                      The IAM role to assume with the credentials, e.g. of another
+	                               account
+	external_id:                   The external ID the role's trust policy requires
+	role_session_name:             The session name of the assumed role. Defaults to
+	                               \"node-discover\".
+	duration:                      How long the assumed role credentials are valid, between
+	                               900s and 12h. Defaults to 1h.
+	elasticloadbalancing_endpoint: The URL the ELB requests are sent to instead of the AWS
+	                               endpoint
+	endpoint:                      The URL the EC2 requests are sent to instead of the AWS
+	                               endpoint
+	sts_endpoint:                  The URL the STS requests of role_arn are sent to
+	fips:                          \"true\" to use the FIPS endpoints. Defaults to \"false\".
+	dualstack:                     \"true\" to use the IPv4 and IPv6 endpoints. Defaults to
+	                               \"false\".
+
+	Targets are returned as \"ip:port\", instance targets with the private IPv4 address of
+	the instance and once per port they are registered with. The required IAM permissions
+	are 'elasticloadbalancing:DescribeTargetHealth' and 'ec2:DescribeInstances'. Without
+	access_key_id and secret_access_key the credentials are read the same way as by the
+	\"aws\" provider.
+	"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::providers::stub::{self, StubResponse};
+    use rusoto_elbv2::{TargetDescription, TargetHealth};
+
+    const DESCRIBE_TARGET_HEALTH: &str = r#"<DescribeTargetHealthResponse xmlns="http://elasticloadbalancing.amazonaws.com/doc/2015-12-01/">
+    <DescribeTargetHealthResult>
+        <TargetHealthDescriptions>
+            <member>
+                <Target><Id>i-0123456789abcdef0</Id><Port>8301</Port></Target>
+                <TargetHealth><State>healthy</State></TargetHealth>
+            </member>
+            <member>
+                <Target><Id>i-0123456789abcdef0</Id><Port>8302</Port></Target>
+                <TargetHealth><State>healthy</State></TargetHealth>
+            </member>
+            <member>
+                <Target><Id>i-0fedcba9876543210</Id><Port>8301</Port></Target>
+                <TargetHealth><State>healthy</State></TargetHealth>
+            </member>
+            <member>
+                <Target><Id>i-0aaaaaaaaaaaaaaaa</Id><Port>8301</Port></Target>
+                <TargetHealth><State>unhealthy</State></TargetHealth>
+            </member>
+            <member>
+                <Target><Id>10.0.1.30</Id><Port>8301</Port></Target>
+                <TargetHealth><State>healthy</State></TargetHealth>
+            </member>
+        </TargetHealthDescriptions>
+    </DescribeTargetHealthResult>
+    <ResponseMetadata>
+        <RequestId>6b1f4f6a-7d0e-11e6-8b1a-example</RequestId>
+    </ResponseMetadata>
+</DescribeTargetHealthResponse>"#;
+
+    const DESCRIBE_INSTANCES: &str = r#"<DescribeInstancesResponse xmlns="http://ec2.amazonaws.com/doc/2016-11-15/">
+    <requestId>8f7724cf-496f-496e-8fe3-example</requestId>
+    <reservationSet>
+        <item>
+            <reservationId>r-1234567890abcdef0</reservationId>
+            <instancesSet>
+                <item>
+                    <instanceId>i-0fedcba9876543210</instanceId>
+                    <privateIpAddress>10.0.1.18</privateIpAddress>
+                </item>
+                <item>
+                    <instanceId>i-0123456789abcdef0</instanceId>
+                    <privateIpAddress>10.0.1.17</privateIpAddress>
+                </item>
+            </instancesSet>
+        </item>
+    </reservationSet>
+</DescribeInstancesResponse>"#;
+
+    fn description(id: &str, port: i64, state: &str) -> TargetHealthDescription {
+        TargetHealthDescription {
+            target: Some(TargetDescription {
+                id: id.to_string(),
+                port: Some(port),
+                ..Default::default()
+            }),
+            target_health: Some(TargetHealth {
+                state: Some(state.to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn targetgroup_provider_from_string() {
+        let arn = "arn:aws:elasticloadbalancing:eu-west-1:123456789012:targetgroup/consul/6d0ecf831eec9f09";
+        let args = format!(
+            "provider=aws-targetgroup region=eu-west-1 target_group_arn={}",
+            arn
+        );
+
+        let res = ParsedArgs::try_from(args);
+        assert!(res.is_ok());
+        let res = TargetGroupProvider::try_from(res.unwrap());
+        assert!(res.is_ok());
+        let provider = res.unwrap();
+        assert_eq!(provider.target_group_arn(), arn);
+        assert!(provider.healthy_only());
+
+        let args = format!(
+            "provider=aws-targetgroup target_group_arn={} healthy_only=yes",
+            arn
+        );
+        let res = TargetGroupProvider::try_from(ParsedArgs::try_from(args).unwrap());
+        assert_eq!(
+            res.unwrap_err(),
+            DiscoverError::MalformedArgument(
+                "healthy_only=yes".to_string(),
+                "Expected either true or false".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn split_instance_and_ip_targets() {
+        let descriptions = vec![
+            description("i-0123456789abcdef0", 8301, "healthy"),
+            description("i-0123456789abcdef0", 8302, "healthy"),
+            description("i-0fedcba9876543210", 8301, "unhealthy"),
+            description("10.0.1.17", 8301, "healthy"),
+            description("10.0.1.18", 8301, "draining"),
+            description(
+                "arn:aws:lambda:eu-west-1:123456789012:function:consul",
+                0,
+                "healthy",
+            ),
+        ];
+
+        assert_eq!(
+            split_targets(descriptions.clone(), true),
+            Targets {
+                instances: vec![
+                    ("i-0123456789abcdef0".to_string(), Some(8301)),
+                    ("i-0123456789abcdef0".to_string(), Some(8302))
+                ],
+                ip_addrs: vec!["10.0.1.17:8301".to_string()],
+            }
+        );
+        assert_eq!(
+            split_targets(descriptions, false),
+            Targets {
+                instances: vec![
+                    ("i-0123456789abcdef0".to_string(), Some(8301)),
+                    ("i-0123456789abcdef0".to_string(), Some(8302)),
+                    ("i-0fedcba9876543210".to_string(), Some(8301))
+                ],
+                ip_addrs: vec!["10.0.1.17:8301".to_string(), "10.0.1.18:8301".to_string()],
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn resolve_instance_targets_with_their_ports() {
+        let stub = stub::serve_tcp(|req| {
+            if req.body.contains("Action=DescribeTargetHealth") {
+                StubResponse::new(200, "text/xml", DESCRIBE_TARGET_HEALTH)
+            } else {
+                StubResponse::new(200, "text/xml", DESCRIBE_INSTANCES)
+            }
+        })
+        .await;
+        let args = format!(
+            "provider=aws-targetgroup region=us-east-1 target_group_arn=arn:aws:elasticloadbalancing:us-east-1:123456789012:targetgroup/consul/6d0ecf831eec9f09 access_key_id=AKIDSTATIC secret_access_key=secret elasticloadbalancing_endpoint={url} endpoint={url}",
+            url = stub.url()
+        );
+        let provider = TargetGroupProvider::try_from(ParsedArgs::try_from(args).unwrap()).unwrap();
+
+        assert_eq!(
+            provider.addrs().await,
+            Ok(vec![
+                "10.0.1.30:8301".to_string(),
+                "10.0.1.17:8301".to_string(),
+                "10.0.1.17:8302".to_string(),
+                "10.0.1.18:8301".to_string(),
+            ])
+        );
+
+        let requests = stub.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests[0].body.contains("Action=DescribeTargetHealth"));
+        assert!(requests[0].body.contains(
+            "TargetGroupArn=arn%3Aaws%3Aelasticloadbalancing%3Aus-east-1%3A123456789012%3Atargetgroup%2Fconsul%2F6d0ecf831eec9f09"
+        ));
+        assert!(requests[1].body.contains("Action=DescribeInstances"));
+        assert!(requests[1]
+            .body
+            .contains("InstanceId.1=i-0123456789abcdef0&InstanceId.2=i-0fedcba9876543210"));
+        assert!(!requests[1].body.contains("InstanceId.3"));
+    }
+}
@@ -25,12 +25,14 @@ struct DropletRegion {
     pub slug: String,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Clone, Deserialize)]
 struct Networks {
     pub v4: Vec<Network>,
     pub v6: Vec<Network>,
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Clone, Deserialize)]
 struct Network {
     pub ip_address: String,
@@ -135,7 +137,7 @@ impl DOProvider {
                 .await
                 .map_err(|e| DiscoverError::ProviderRequestFailed(format!("{:?}", e)))?;
             let droplets_count = data.droplets.len();
-            droplets.extend(data.droplets.into_iter());
+            droplets.extend(data.droplets);
 
             page += 1;
 
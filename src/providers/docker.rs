@@ -0,0 +1,391 @@
+use log::{debug, info};
+use serde::Deserialize;
+use std::{collections::HashMap, convert::TryFrom, env, path::PathBuf};
+
+use crate::{args::ParsedArgs, SupportedProvider};
+
+use super::{unix_socket, DiscoverError, Provider};
+
+const DEFAULT_DOCKER_HOST: &str = "unix:///var/run/docker.sock";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Container {
+    pub id: String,
+    #[serde(default)]
+    pub names: Vec<String>,
+    pub network_settings: NetworkSettings,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct NetworkSettings {
+    #[serde(default)]
+    pub networks: HashMap<String, EndpointSettings>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct EndpointSettings {
+    #[serde(rename = "IPAddress", default)]
+    pub ip_address: String,
+}
+
+/// Where the Docker Engine API is listening.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DockerHost {
+    /// Path of the Unix socket
+    Unix(PathBuf),
+    /// Base url of the TCP endpoint, e.g. `http://10.0.0.5:2375`
+    Tcp(String),
+}
+
+impl TryFrom<String> for DockerHost {
+    type Error = DiscoverError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        if let Some(path) = value.strip_prefix("unix://") {
+            return Ok(DockerHost::Unix(PathBuf::from(path)));
+        }
+        if let Some(addr) = value.strip_prefix("tcp://") {
+            return Ok(DockerHost::Tcp(format!(
+                "http://{}",
+                addr.trim_end_matches('/')
+            )));
+        }
+        if value.starts_with("http://") || value.starts_with("https://") {
+            return Ok(DockerHost::Tcp(value.trim_end_matches('/').to_string()));
+        }
+
+        Err(DiscoverError::MalformedArgument(
+            format!("host={}", value),
+            format!(
+                "{} is not a valid Docker host. Expected unix:///path/to/docker.sock or tcp://host:port.",
+                value
+            ),
+        ))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DockerProvider {
+    host: DockerHost,
+    labels: Vec<String>,
+    network: Option<String>,
+}
+
+impl TryFrom<ParsedArgs> for DockerProvider {
+    type Error = DiscoverError;
+
+    fn try_from(args: ParsedArgs) -> Result<Self, Self::Error> {
+        let mut host = None;
+        let mut labels = None;
+        let mut network = None;
+
+        for (key, value) in args {
+            match &key[..] {
+                "host" => host = Some(DockerHost::try_from(value)?),
+                "label" => labels = Some(value.split(',').map(String::from).collect()),
+                "network" => network = Some(value),
+                _ => return Err(DiscoverError::UnexpectedArgument(key)),
+            }
+        }
+
+        let labels = labels.ok_or_else(|| DiscoverError::MissingArgument("label".into()))?;
+        let host = match host {
+            Some(host) => host,
+            None => DockerHost::try_from(
+                env::var("DOCKER_HOST").unwrap_or_else(|_| DEFAULT_DOCKER_HOST.to_string()),
+            )?,
+        };
+
+        Ok(DockerProvider {
+            host,
+            labels,
+            network,
+        })
+    }
+}
+
+impl TryFrom<Vec<String>> for DockerProvider {
+    type Error = DiscoverError;
+
+    fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
+        let args = ParsedArgs::try_from(value)?;
+        match *args.provider() {
+            SupportedProvider::Docker => DockerProvider::try_from(args),
+            _ => Err(DiscoverError::MalformedArgument(
+                format!("provider={}", args.provider()),
+                "you should not see this ...".to_string(),
+            )),
+        }
+    }
+}
+
+impl DockerProvider {
+    pub fn host(&self) -> &DockerHost {
+        &self.host
+    }
+
+    pub fn labels(&self) -> &[String] {
+        &self.labels
+    }
+
+    pub fn network(&self) -> Option<&String> {
+        self.network.as_ref()
+    }
+
+    async fn get_containers(&self) -> Result<Vec<Container>, DiscoverError> {
+        debug!(
+            "Using host={:?} labels={:?} network={:?}",
+            self.host, self.labels, self.network
+        );
+
+        let filters = serde_json::json!({
+            "label": self.labels,
+            "status": ["running"],
+        });
+        let path = format!(
+            "/containers/json?filters={}",
+            url::form_urlencoded::byte_serialize(filters.to_string().as_bytes())
+                .collect::<String>()
+        );
+
+        match &self.host {
+            DockerHost::Unix(socket) => unix_socket::get_json(socket, &path, &[]).await,
+            DockerHost::Tcp(base_url) => {
+                let url = format!("{}{}", base_url, path);
+                let res = reqwest::Client::new()
+                    .get(&url)
+                    .send()
+                    .await
+                    .map_err(|e| DiscoverError::ProviderRequestFailed(format!("{:?}", e)))?;
+
+                // Docker explains errors in the body, e.g. an invalid filter
+                let status = res.status();
+                if !status.is_success() {
+                    let body = res.text().await.unwrap_or_default();
+                    return Err(DiscoverError::ProviderRequestFailed(format!(
+                        "{} returned {}: {}",
+                        url,
+                        status,
+                        body.trim()
+                    )));
+                }
+
+                res.json::<Vec<Container>>()
+                    .await
+                    .map_err(|e| DiscoverError::ProviderRequestFailed(format!("{:?}", e)))
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Provider for DockerProvider {
+    async fn addrs(&self) -> Result<Vec<String>, DiscoverError> {
+        let containers = self.get_containers().await?;
+        debug!("Found {} containers", containers.len());
+
+        let mut addrs = Vec::new();
+        for container in containers {
+            let name = container.names.first().unwrap_or(&container.id);
+            for (network, settings) in container.network_settings.networks {
+                if let Some(wanted) = &self.network {
+                    if network != *wanted {
+                        continue;
+                    }
+                }
+                if settings.ip_address.is_empty() {
+                    debug!("Container {} has no IP on network {}", name, network);
+                    continue;
+                }
+
+                info!(
+                    "Found container {} with IP {} on network {}",
+                    name, settings.ip_address, network
+                );
+                addrs.push(settings.ip_address);
+            }
+        }
+
+        debug!("Found ip addresses: {:?}", addrs);
+        Ok(addrs)
+    }
+
+    fn help() -> &'static str {
+        "Docker:
+
+	provider: \"docker\"
+	host:     The Docker Engine API to use, \"unix:///path/to/docker.sock\" or \"tcp://host:port\".
+	          Defaults to DOCKER_HOST or \"unix:///var/run/docker.sock\".
+	label:    Comma separated list of labels to filter running containers on, e.g.
+	          \"com.example.role=server\". Containers must match all labels.
+	network:  The Docker network to return the container IP of. Defaults to all networks.
+"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::providers::stub::{self, StubResponse};
+
+    const CONTAINERS: &str = r#"[
+        {
+            "Id": "8dfafdbc3a40",
+            "Names": ["/consul-1"],
+            "NetworkSettings": {
+                "Networks": {
+                    "bridge": { "IPAddress": "172.17.0.2" },
+                    "backend": { "IPAddress": "10.10.0.2" }
+                }
+            }
+        },
+        {
+            "Id": "9cd87474be90",
+            "Names": ["/consul-2"],
+            "NetworkSettings": {
+                "Networks": {
+                    "backend": { "IPAddress": "10.10.0.3" },
+                    "host": { "IPAddress": "" }
+                }
+            }
+        }
+    ]"#;
+
+    #[test]
+    fn docker_provider_from_string() {
+        let args = "provider=docker host=tcp://10.0.0.5:2375 label=com.example.role=server,env=dev network=backend";
+
+        let res = ParsedArgs::try_from(args.to_string());
+        assert!(res.is_ok());
+        let res = DockerProvider::try_from(res.unwrap());
+        assert!(res.is_ok());
+        let provider = res.unwrap();
+        assert_eq!(
+            provider.host(),
+            &DockerHost::Tcp("http://10.0.0.5:2375".to_string())
+        );
+        assert_eq!(
+            provider.labels(),
+            &["com.example.role=server".to_string(), "env=dev".to_string()]
+        );
+        assert_eq!(provider.network(), Some(&"backend".to_string()));
+    }
+
+    #[test]
+    fn fail_on_missing_label() {
+        let args = "provider=docker network=backend";
+
+        let res = DockerProvider::try_from(ParsedArgs::try_from(args.to_string()).unwrap());
+        assert_eq!(
+            res.unwrap_err(),
+            DiscoverError::MissingArgument("label".to_string())
+        );
+    }
+
+    #[test]
+    fn fail_on_malformed_host() {
+        let args = "provider=docker host=/var/run/docker.sock label=role=server";
+
+        let res = DockerProvider::try_from(ParsedArgs::try_from(args.to_string()).unwrap());
+        assert!(matches!(
+            res.unwrap_err(),
+            DiscoverError::MalformedArgument(arg, _) if arg == "host=/var/run/docker.sock"
+        ));
+    }
+
+    #[tokio::test]
+    async fn list_containers_over_unix_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("docker.sock");
+        let stub = stub::serve_unix(&socket, |_| StubResponse::json(CONTAINERS)).await;
+
+        let args = vec![
+            "provider=docker".to_string(),
+            format!("host=unix://{}", socket.display()),
+            "label=com.example.role=server".to_string(),
+            "network=backend".to_string(),
+        ];
+        let provider = DockerProvider::try_from(args).unwrap();
+        let mut addrs = provider.addrs().await.unwrap();
+        addrs.sort();
+        assert_eq!(addrs, vec!["10.10.0.2", "10.10.0.3"]);
+
+        let requests = stub.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "GET");
+        let query = requests[0].path.split_once('?').unwrap().1;
+        let (key, filters) = url::form_urlencoded::parse(query.as_bytes())
+            .next()
+            .unwrap();
+        assert_eq!(key, "filters");
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&filters).unwrap(),
+            serde_json::json!({
+                "label": ["com.example.role=server"],
+                "status": ["running"],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn list_containers_over_tcp_on_all_networks() {
+        let stub = stub::serve_tcp(|_| StubResponse::json(CONTAINERS)).await;
+
+        let args = vec![
+            "provider=docker".to_string(),
+            format!("host={}", stub.url()),
+            "label=com.example.role=server".to_string(),
+        ];
+        let provider = DockerProvider::try_from(args).unwrap();
+        let mut addrs = provider.addrs().await.unwrap();
+        addrs.sort();
+        assert_eq!(addrs, vec!["10.10.0.2", "10.10.0.3", "172.17.0.2"]);
+    }
+
+    #[tokio::test]
+    async fn fail_on_error_response() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("docker.sock");
+        let _stub = stub::serve_unix(&socket, |_| {
+            StubResponse::new(500, "application/json", r#"{"message":"boom"}"#)
+        })
+        .await;
+
+        let args = vec![
+            "provider=docker".to_string(),
+            format!("host=unix://{}", socket.display()),
+            "label=role=server".to_string(),
+        ];
+        let provider = DockerProvider::try_from(args).unwrap();
+        match provider.addrs().await {
+            Err(DiscoverError::ProviderRequestFailed(msg)) => {
+                assert!(msg.contains("boom"), "{}", msg)
+            }
+            res => panic!("Expected ProviderRequestFailed, got {:?}", res),
+        }
+    }
+
+    #[tokio::test]
+    async fn fail_on_error_response_over_tcp() {
+        let stub = stub::serve_tcp(|_| {
+            StubResponse::new(400, "application/json", r#"{"message":"invalid filter"}"#)
+        })
+        .await;
+
+        let args = vec![
+            "provider=docker".to_string(),
+            format!("host={}", stub.url().replacen("http://", "tcp://", 1)),
+            "label=role=server".to_string(),
+        ];
+        let provider = DockerProvider::try_from(args).unwrap();
+        match provider.addrs().await {
+            Err(DiscoverError::ProviderRequestFailed(msg)) => {
+                assert!(msg.contains("400"), "{}", msg);
+                assert!(msg.contains("invalid filter"), "{}", msg);
+            }
+            res => panic!("Expected ProviderRequestFailed, got {:?}", res),
+        }
+    }
+}
@@ -0,0 +1,479 @@
+use log::{debug, info, warn};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    convert::TryFrom,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{args::ParsedArgs, SupportedProvider};
+
+use super::{tls, DiscoverError, Provider};
+
+const DEFAULT_ENDPOINT: &str = "http://127.0.0.1:2379";
+
+#[derive(Debug, Clone, Serialize)]
+struct AuthenticateRequest<'a> {
+    pub name: &'a str,
+    pub password: &'a str,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct AuthenticateResponse {
+    pub token: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct RangeRequest {
+    pub key: String,
+    pub range_end: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct RangeResponse {
+    #[serde(default)]
+    pub kvs: Vec<KeyValue>,
+}
+
+/// Keys and values are base64 encoded by the JSON gateway.
+#[derive(Debug, Clone, Deserialize)]
+struct KeyValue {
+    pub key: String,
+    #[serde(default)]
+    pub value: String,
+}
+
+/// Returns the smallest key that is larger than every key starting with `prefix`.
+fn prefix_range_end(prefix: &[u8]) -> Vec<u8> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < 0xff {
+            end.push(last + 1);
+            return end;
+        }
+    }
+    // The prefix only consists of 0xff bytes, so the range ends with the last key
+    vec![0]
+}
+
+#[derive(Debug, Clone)]
+pub struct EtcdProvider {
+    endpoints: Vec<String>,
+    prefix: String,
+    address_field: String,
+    username: Option<String>,
+    password: Option<String>,
+    ca_cert: Option<PathBuf>,
+    client_cert: Option<PathBuf>,
+    client_key: Option<PathBuf>,
+}
+
+impl TryFrom<ParsedArgs> for EtcdProvider {
+    type Error = DiscoverError;
+
+    fn try_from(args: ParsedArgs) -> Result<Self, Self::Error> {
+        let mut endpoints = vec![DEFAULT_ENDPOINT.to_string()];
+        let mut prefix = None;
+        let mut address_field = "address".to_string();
+        let mut username = None;
+        let mut password = None;
+        let mut ca_cert = None;
+        let mut client_cert = None;
+        let mut client_key = None;
+
+        for (key, value) in args {
+            match &key[..] {
+                "endpoints" => {
+                    endpoints = value
+                        .split(',')
+                        .map(|endpoint| endpoint.trim_end_matches('/').to_string())
+                        .collect()
+                }
+                "prefix" => prefix = Some(value),
+                "address_field" => address_field = value,
+                "username" => username = Some(value),
+                "password" => password = Some(value),
+                "ca_cert" => ca_cert = Some(PathBuf::from(value)),
+                "client_cert" => client_cert = Some(PathBuf::from(value)),
+                "client_key" => client_key = Some(PathBuf::from(value)),
+                _ => return Err(DiscoverError::UnexpectedArgument(key)),
+            }
+        }
+
+        let prefix = prefix.ok_or_else(|| DiscoverError::MissingArgument("prefix".into()))?;
+        if username.is_some() && password.is_none() {
+            return Err(DiscoverError::MissingArgument("password".into()));
+        }
+        if password.is_some() && username.is_none() {
+            return Err(DiscoverError::MissingArgument("username".into()));
+        }
+        if client_cert.is_some() && client_key.is_none() {
+            return Err(DiscoverError::MissingArgument("client_key".into()));
+        }
+        if client_key.is_some() && client_cert.is_none() {
+            return Err(DiscoverError::MissingArgument("client_cert".into()));
+        }
+
+        Ok(EtcdProvider {
+            endpoints,
+            prefix,
+            address_field,
+            username,
+            password,
+            ca_cert,
+            client_cert,
+            client_key,
+        })
+    }
+}
+
+impl TryFrom<Vec<String>> for EtcdProvider {
+    type Error = DiscoverError;
+
+    fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
+        let args = ParsedArgs::try_from(value)?;
+        match *args.provider() {
+            SupportedProvider::Etcd => EtcdProvider::try_from(args),
+            _ => Err(DiscoverError::MalformedArgument(
+                format!("provider={}", args.provider()),
+                "you should not see this ...".to_string(),
+            )),
+        }
+    }
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, DiscoverError> {
+    fs::read(path).map_err(|e| {
+        DiscoverError::ProviderRequestFailed(format!("Unable to read {}: {}", path.display(), e))
+    })
+}
+
+impl EtcdProvider {
+    pub fn endpoints(&self) -> &[String] {
+        &self.endpoints
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub fn address_field(&self) -> &str {
+        &self.address_field
+    }
+
+    pub fn username(&self) -> Option<&String> {
+        self.username.as_ref()
+    }
+
+    fn client(&self) -> Result<reqwest::Client, DiscoverError> {
+        let mut builder = reqwest::Client::builder();
+        if let Some(ca_cert) = &self.ca_cert {
+            let cert = reqwest::Certificate::from_pem(&read_file(ca_cert)?)
+                .map_err(|e| DiscoverError::ProviderRequestFailed(format!("{:?}", e)))?;
+            builder = builder.add_root_certificate(cert);
+        }
+        if let (Some(client_cert), Some(client_key)) = (&self.client_cert, &self.client_key) {
+            let identity = tls::identity(&read_file(client_cert)?, &read_file(client_key)?)?;
+            builder = builder.identity(identity);
+        }
+
+        builder
+            .build()
+            .map_err(|e| DiscoverError::ProviderRequestFailed(format!("{:?}", e)))
+    }
+
+    async fn post<Req: Serialize, Res: DeserializeOwned>(
+        client: &reqwest::Client,
+        url: String,
+        token: Option<&str>,
+        body: &Req,
+    ) -> Result<Res, DiscoverError> {
+        let mut req = client.post(url).json(body);
+        if let Some(token) = token {
+            req = req.header("Authorization", token);
+        }
+
+        req.send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(|e| DiscoverError::ProviderRequestFailed(format!("{:?}", e)))?
+            .json::<Res>()
+            .await
+            .map_err(|e| DiscoverError::ProviderRequestFailed(format!("{:?}", e)))
+    }
+
+    async fn get_kvs(
+        &self,
+        client: &reqwest::Client,
+        endpoint: &str,
+    ) -> Result<Vec<KeyValue>, DiscoverError> {
+        let token = match (&self.username, &self.password) {
+            (Some(name), Some(password)) => {
+                let res: AuthenticateResponse = Self::post(
+                    client,
+                    format!("{}/v3/auth/authenticate", endpoint),
+                    None,
+                    &AuthenticateRequest { name, password },
+                )
+                .await?;
+                Some(res.token)
+            }
+            _ => None,
+        };
+
+        let req = RangeRequest {
+            key: base64::encode(&self.prefix),
+            range_end: base64::encode(prefix_range_end(self.prefix.as_bytes())),
+        };
+        let res: RangeResponse = Self::post(
+            client,
+            format!("{}/v3/kv/range", endpoint),
+            token.as_deref(),
+            &req,
+        )
+        .await?;
+
+        Ok(res.kvs)
+    }
+
+    /// Values are either the address itself or a JSON document with the
+    /// address in `address_field`.
+    fn parse_value(&self, key: &str, value: &str) -> Option<String> {
+        let value = value.trim();
+        if value.starts_with('{') {
+            let addr = serde_json::from_str::<serde_json::Value>(value)
+                .ok()
+                .and_then(|doc| doc.get(&self.address_field)?.as_str().map(String::from));
+            if addr.is_none() {
+                warn!(
+                    "Key {} holds a JSON document without a string field {}",
+                    key, self.address_field
+                );
+            }
+            return addr;
+        }
+
+        if value.is_empty() {
+            debug!("Key {} has an empty value", key);
+            return None;
+        }
+        Some(value.to_string())
+    }
+}
+
+#[async_trait::async_trait]
+impl Provider for EtcdProvider {
+    async fn addrs(&self) -> Result<Vec<String>, DiscoverError> {
+        debug!(
+            "Using endpoints={:?} prefix={} address_field={}",
+            self.endpoints, self.prefix, self.address_field
+        );
+
+        let client = self.client()?;
+
+        // Try the endpoints in order until one of them answers
+        let mut errors = Vec::new();
+        let mut kvs = None;
+        for endpoint in &self.endpoints {
+            match self.get_kvs(&client, endpoint).await {
+                Ok(res) => {
+                    kvs = Some(res);
+                    break;
+                }
+                Err(e) => {
+                    warn!("Unable to read keys from etcd endpoint {}: {}", endpoint, e);
+                    errors.push(format!("{}: {}", endpoint, e));
+                }
+            }
+        }
+        let kvs = kvs.ok_or_else(|| DiscoverError::ProviderRequestFailed(errors.join("; ")))?;
+        debug!("Found {} keys", kvs.len());
+
+        let addrs = kvs
+            .into_iter()
+            .filter_map(|kv| {
+                let key = base64::decode(&kv.key)
+                    .map(|key| String::from_utf8_lossy(&key).to_string())
+                    .unwrap_or(kv.key);
+                let value = match base64::decode(&kv.value) {
+                    Ok(value) => String::from_utf8_lossy(&value).to_string(),
+                    Err(e) => {
+                        warn!("Key {} has a malformed value: {}", key, e);
+                        return None;
+                    }
+                };
+
+                let addr = self.parse_value(&key, &value)?;
+                info!("Found key {} with address {}", key, addr);
+                Some(addr)
+            })
+            .collect::<Vec<_>>();
+
+        debug!("Found ip addresses: {:?}", addrs);
+        Ok(addrs)
+    }
+
+    fn help() -> &'static str {
+        "etcd:
+
+	provider:      \"etcd\"
+	endpoints:     Comma separated list of etcd urls. Defaults to \"http://127.0.0.1:2379\".
+	prefix:        The key prefix to read, e.g. \"/services/consul/\"
+	address_field: The field holding the address when a value is a JSON document. Defaults to \"address\".
+	username:      The user to authenticate as
+	password:      The password of the user
+	ca_cert:       Path of the PEM encoded CA certificate to verify the endpoints with
+	client_cert:   Path of the PEM encoded client certificate
+	client_key:    Path of the PEM encoded client key, PKCS#8, SEC1 (\"BEGIN EC PRIVATE KEY\") or
+	               PKCS#1 (\"BEGIN RSA PRIVATE KEY\", as written by cfssl)
+
+	Every key under the prefix is read through the etcd v3 JSON gateway. Each value
+	must either be an address or a JSON document with the address in address_field.
+	The endpoints are tried in order until one of them answers.
+"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::providers::{
+        stub::{self, StubResponse},
+        tls::test::{EC_CERT, EC_KEY},
+    };
+
+    #[test]
+    fn etcd_provider_from_string() {
+        let args = "provider=etcd endpoints=https://10.0.0.1:2379/,https://10.0.0.2:2379 prefix=/services/consul/ address_field=ip username=discover password=secret ca_cert=/etc/etcd/ca.pem";
+
+        let res = ParsedArgs::try_from(args.to_string());
+        assert!(res.is_ok());
+        let res = EtcdProvider::try_from(res.unwrap());
+        assert!(res.is_ok());
+        let provider = res.unwrap();
+        assert_eq!(
+            provider.endpoints(),
+            &[
+                "https://10.0.0.1:2379".to_string(),
+                "https://10.0.0.2:2379".to_string()
+            ]
+        );
+        assert_eq!(provider.prefix(), "/services/consul/");
+        assert_eq!(provider.address_field(), "ip");
+        assert_eq!(provider.username(), Some(&"discover".to_string()));
+    }
+
+    #[test]
+    fn fail_on_incomplete_credentials() {
+        let cases = vec![
+            ("provider=etcd endpoints=http://a:2379", "prefix"),
+            ("provider=etcd prefix=/a username=u", "password"),
+            ("provider=etcd prefix=/a password=p", "username"),
+            ("provider=etcd prefix=/a client_cert=/c.pem", "client_key"),
+        ];
+
+        for (args, missing) in cases {
+            let res = EtcdProvider::try_from(ParsedArgs::try_from(args.to_string()).unwrap());
+            assert_eq!(
+                res.unwrap_err(),
+                DiscoverError::MissingArgument(missing.to_string()),
+                "{}",
+                args
+            );
+        }
+    }
+
+    #[test]
+    fn client_with_sec1_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let cert = dir.path().join("client.pem");
+        let key = dir.path().join("client-key.pem");
+        fs::write(&cert, EC_CERT).unwrap();
+        fs::write(&key, EC_KEY).unwrap();
+
+        let args = format!(
+            "provider=etcd prefix=/a client_cert={} client_key={}",
+            cert.display(),
+            key.display()
+        );
+        let provider = EtcdProvider::try_from(ParsedArgs::try_from(args).unwrap()).unwrap();
+        assert!(provider.client().is_ok());
+    }
+
+    #[test]
+    fn range_end_of_prefix() {
+        assert_eq!(prefix_range_end(b"/services/"), b"/services0".to_vec());
+        assert_eq!(prefix_range_end(b"a\xff"), b"b".to_vec());
+        assert_eq!(prefix_range_end(b"\xff\xff"), vec![0]);
+    }
+
+    #[tokio::test]
+    async fn read_addresses_under_prefix() {
+        let kvs = serde_json::json!({
+            "header": { "revision": "7" },
+            "kvs": [
+                { "key": base64::encode("/services/consul/a"), "value": base64::encode("10.0.0.1") },
+                { "key": base64::encode("/services/consul/b"), "value": base64::encode(r#"{"address": "10.0.0.2", "port": 8301}"#) },
+                { "key": base64::encode("/services/consul/c"), "value": base64::encode(r#"{"ip": "10.0.0.3"}"#) },
+                { "key": base64::encode("/services/consul/d") }
+            ],
+            "count": "4"
+        })
+        .to_string();
+        let stub = stub::serve_tcp(move |req| match &req.path[..] {
+            "/v3/auth/authenticate" => StubResponse::json(r#"{"token": "sometoken.42"}"#),
+            "/v3/kv/range" => StubResponse::json(&kvs),
+            _ => StubResponse::new(404, "text/plain", "not found"),
+        })
+        .await;
+        // Nothing listens on the first endpoint, so the provider must fall back to the second
+        let unavailable = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            format!("http://{}", listener.local_addr().unwrap())
+        };
+
+        let args = vec![
+            "provider=etcd".to_string(),
+            format!("endpoints={},{}", unavailable, stub.url()),
+            "prefix=/services/consul/".to_string(),
+            "username=discover".to_string(),
+            "password=secret".to_string(),
+        ];
+        let provider = EtcdProvider::try_from(args).unwrap();
+        assert_eq!(
+            provider.addrs().await,
+            Ok(vec!["10.0.0.1".to_string(), "10.0.0.2".to_string()])
+        );
+
+        let requests = stub.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&requests[0].body).unwrap(),
+            serde_json::json!({ "name": "discover", "password": "secret" })
+        );
+        assert_eq!(requests[1].header("Authorization"), Some("sometoken.42"));
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&requests[1].body).unwrap(),
+            serde_json::json!({
+                "key": base64::encode("/services/consul/"),
+                "range_end": base64::encode("/services/consul0"),
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn fail_when_no_endpoint_answers() {
+        let stub = stub::serve_tcp(|_| StubResponse::new(503, "text/plain", "unavailable")).await;
+
+        let args = vec![
+            "provider=etcd".to_string(),
+            format!("endpoints={}", stub.url()),
+            "prefix=/services/consul/".to_string(),
+        ];
+        let provider = EtcdProvider::try_from(args).unwrap();
+        assert!(matches!(
+            provider.addrs().await,
+            Err(DiscoverError::ProviderRequestFailed(_))
+        ));
+    }
+}
@@ -0,0 +1,418 @@
+use log::{debug, info};
+use serde::Deserialize;
+use std::{collections::HashMap, convert::TryFrom};
+
+use crate::{args::ParsedArgs, SupportedProvider};
+
+use super::{parse_bool, DiscoverError, Provider};
+
+#[derive(Debug, Clone, Deserialize)]
+struct ApplicationResponse {
+    pub application: Application,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Application {
+    #[serde(default)]
+    pub instance: Instances,
+}
+
+/// Eureka serializes an application with a single instance as an object
+/// instead of a list with one element.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum Instances {
+    Many(Vec<Instance>),
+    One(Box<Instance>),
+}
+
+impl Default for Instances {
+    fn default() -> Self {
+        Instances::Many(Vec::new())
+    }
+}
+
+impl IntoIterator for Instances {
+    type Item = Instance;
+
+    type IntoIter = std::vec::IntoIter<Instance>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        match self {
+            Instances::Many(instances) => instances.into_iter(),
+            Instances::One(instance) => vec![*instance].into_iter(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Instance {
+    #[serde(default)]
+    pub instance_id: String,
+    pub ip_addr: String,
+    pub status: String,
+    pub port: Option<Port>,
+    pub secure_port: Option<Port>,
+    #[serde(default)]
+    pub metadata: HashMap<String, serde_json::Value>,
+}
+
+/// Depending on the Eureka version the port and the enabled flag are either
+/// strings or a number and a boolean.
+#[derive(Debug, Clone, Deserialize)]
+struct Port {
+    #[serde(rename = "$")]
+    pub port: serde_json::Value,
+    #[serde(rename = "@enabled")]
+    pub enabled: serde_json::Value,
+}
+
+impl Port {
+    fn enabled_port(&self) -> Option<String> {
+        let enabled = match &self.enabled {
+            serde_json::Value::Bool(enabled) => *enabled,
+            serde_json::Value::String(enabled) => enabled == "true",
+            _ => false,
+        };
+        if !enabled {
+            return None;
+        }
+
+        match &self.port {
+            serde_json::Value::Number(port) => Some(port.to_string()),
+            serde_json::Value::String(port) => Some(port.clone()),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EurekaProvider {
+    url: String,
+    app: String,
+    status: String,
+    metadata_key: Option<String>,
+    metadata_value: Option<String>,
+    secure: bool,
+}
+
+impl TryFrom<ParsedArgs> for EurekaProvider {
+    type Error = DiscoverError;
+
+    fn try_from(args: ParsedArgs) -> Result<Self, Self::Error> {
+        let mut url = None;
+        let mut app = None;
+        let mut status = "UP".to_string();
+        let mut metadata_key = None;
+        let mut metadata_value = None;
+        let mut secure = false;
+
+        for (key, value) in args {
+            match &key[..] {
+                "url" => url = Some(value.trim_end_matches('/').to_string()),
+                "app" => app = Some(value.to_uppercase()),
+                "status" => status = value.to_uppercase(),
+                "metadata_key" => metadata_key = Some(value),
+                "metadata_value" => metadata_value = Some(value),
+                "secure" => secure = parse_bool(&key, &value)?,
+                _ => return Err(DiscoverError::UnexpectedArgument(key)),
+            }
+        }
+
+        let url = url.ok_or_else(|| DiscoverError::MissingArgument("url".into()))?;
+        let app = app.ok_or_else(|| DiscoverError::MissingArgument("app".into()))?;
+        if metadata_key.is_some() && metadata_value.is_none() {
+            return Err(DiscoverError::MissingArgument("metadata_value".into()));
+        }
+        if metadata_value.is_some() && metadata_key.is_none() {
+            return Err(DiscoverError::MissingArgument("metadata_key".into()));
+        }
+
+        Ok(EurekaProvider {
+            url,
+            app,
+            status,
+            metadata_key,
+            metadata_value,
+            secure,
+        })
+    }
+}
+
+impl TryFrom<Vec<String>> for EurekaProvider {
+    type Error = DiscoverError;
+
+    fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
+        let args = ParsedArgs::try_from(value)?;
+        match *args.provider() {
+            SupportedProvider::Eureka => EurekaProvider::try_from(args),
+            _ => Err(DiscoverError::MalformedArgument(
+                format!("provider={}", args.provider()),
+                "you should not see this ...".to_string(),
+            )),
+        }
+    }
+}
+
+impl EurekaProvider {
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn app(&self) -> &str {
+        &self.app
+    }
+
+    pub fn status(&self) -> &str {
+        &self.status
+    }
+
+    pub fn metadata_key(&self) -> Option<&String> {
+        self.metadata_key.as_ref()
+    }
+
+    pub fn metadata_value(&self) -> Option<&String> {
+        self.metadata_value.as_ref()
+    }
+
+    pub fn secure(&self) -> bool {
+        self.secure
+    }
+
+    async fn get_instances(&self) -> Result<Vec<Instance>, DiscoverError> {
+        debug!(
+            "Using url={} app={} status={} metadata_key={:?} metadata_value={:?}",
+            self.url, self.app, self.status, self.metadata_key, self.metadata_value
+        );
+
+        let res = reqwest::Client::new()
+            .get(format!("{}/eureka/apps/{}", self.url, self.app))
+            .header("Accept", "application/json")
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(|e| DiscoverError::ProviderRequestFailed(format!("{:?}", e)))?
+            .json::<ApplicationResponse>()
+            .await
+            .map_err(|e| DiscoverError::ProviderRequestFailed(format!("{:?}", e)))?;
+
+        Ok(res.application.instance.into_iter().collect())
+    }
+
+    fn has_metadata(&self, instance: &Instance) -> bool {
+        match (&self.metadata_key, &self.metadata_value) {
+            (Some(key), Some(value)) => {
+                instance.metadata.get(key).and_then(|v| v.as_str()) == Some(value.as_str())
+            }
+            _ => true,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Provider for EurekaProvider {
+    async fn addrs(&self) -> Result<Vec<String>, DiscoverError> {
+        let instances = self.get_instances().await?;
+        debug!("Found {} instances", instances.len());
+
+        let addrs = instances
+            .into_iter()
+            .filter(|instance| instance.status == self.status)
+            .filter(|instance| self.has_metadata(instance))
+            .filter_map(|instance| {
+                let port = if self.secure {
+                    instance.secure_port.as_ref()
+                } else {
+                    instance.port.as_ref()
+                };
+
+                match port.and_then(Port::enabled_port) {
+                    Some(port) => {
+                        info!(
+                            "Found instance {} with address {}:{}",
+                            instance.instance_id, instance.ip_addr, port
+                        );
+                        Some(format!("{}:{}", instance.ip_addr, port))
+                    }
+                    None => {
+                        debug!(
+                            "Instance {} has no enabled {} port",
+                            instance.instance_id,
+                            if self.secure { "secure" } else { "non-secure" }
+                        );
+                        None
+                    }
+                }
+            })
+            .collect::<Vec<_>>();
+
+        debug!("Found addresses: {:?}", addrs);
+        Ok(addrs)
+    }
+
+    fn help() -> &'static str {
+        "Netflix Eureka:
+
+	provider:       \"eureka\"
+	url:            The url of the Eureka server, e.g. \"http://eureka.example.com:8761\"
+	app:            The name of the application to discover
+	status:         The status instances must have. Defaults to \"UP\".
+	metadata_key:   The instance metadata key to filter on
+	metadata_value: The instance metadata value to filter on
+	secure:         \"true\" or \"false\". Return the secure port instead of the non-secure port.
+	                Defaults to \"false\".
+
+	The instances are fetched from /eureka/apps/<app> and returned as \"ipAddr:port\".
+	Instances where the requested port is not enabled are skipped.
+"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::providers::stub::{self, StubResponse};
+
+    const APPLICATION: &str = r#"{
+        "application": {
+            "name": "CONSUL",
+            "instance": [
+                {
+                    "instanceId": "consul-1:8500",
+                    "app": "CONSUL",
+                    "ipAddr": "10.0.0.1",
+                    "status": "UP",
+                    "port": { "$": 8500, "@enabled": "true" },
+                    "securePort": { "$": 8501, "@enabled": "true" },
+                    "metadata": { "role": "server" }
+                },
+                {
+                    "instanceId": "consul-2:8500",
+                    "app": "CONSUL",
+                    "ipAddr": "10.0.0.2",
+                    "status": "DOWN",
+                    "port": { "$": 8500, "@enabled": "true" },
+                    "securePort": { "$": 8501, "@enabled": "true" },
+                    "metadata": { "role": "server" }
+                },
+                {
+                    "instanceId": "consul-3:8500",
+                    "app": "CONSUL",
+                    "ipAddr": "10.0.0.3",
+                    "status": "UP",
+                    "port": { "$": "8500", "@enabled": true },
+                    "securePort": { "$": "8501", "@enabled": false },
+                    "metadata": { "role": "client" }
+                }
+            ]
+        }
+    }"#;
+
+    #[test]
+    fn eureka_provider_from_string() {
+        let args = "provider=eureka url=http://eureka:8761/ app=consul status=starting metadata_key=role metadata_value=server secure=true";
+
+        let res = ParsedArgs::try_from(args.to_string());
+        assert!(res.is_ok());
+        let res = EurekaProvider::try_from(res.unwrap());
+        assert!(res.is_ok());
+        let provider = res.unwrap();
+        assert_eq!(provider.url(), "http://eureka:8761");
+        assert_eq!(provider.app(), "CONSUL");
+        assert_eq!(provider.status(), "STARTING");
+        assert_eq!(provider.metadata_key(), Some(&"role".to_string()));
+        assert_eq!(provider.metadata_value(), Some(&"server".to_string()));
+        assert!(provider.secure());
+    }
+
+    #[test]
+    fn fail_on_missing_arguments() {
+        let cases = vec![
+            ("provider=eureka app=consul", "url"),
+            ("provider=eureka url=http://eureka:8761", "app"),
+            (
+                "provider=eureka url=http://eureka:8761 app=consul metadata_key=role",
+                "metadata_value",
+            ),
+        ];
+
+        for (args, missing) in cases {
+            let res = EurekaProvider::try_from(ParsedArgs::try_from(args.to_string()).unwrap());
+            assert_eq!(
+                res.unwrap_err(),
+                DiscoverError::MissingArgument(missing.to_string()),
+                "{}",
+                args
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn return_up_instances_with_port() {
+        let stub = stub::serve_tcp(|_| StubResponse::json(APPLICATION)).await;
+
+        let args = vec![
+            "provider=eureka".to_string(),
+            format!("url={}", stub.url()),
+            "app=consul".to_string(),
+        ];
+        let provider = EurekaProvider::try_from(args).unwrap();
+        assert_eq!(
+            provider.addrs().await,
+            Ok(vec![
+                "10.0.0.1:8500".to_string(),
+                "10.0.0.3:8500".to_string()
+            ])
+        );
+
+        let requests = stub.requests();
+        assert_eq!(requests[0].path, "/eureka/apps/CONSUL");
+        assert_eq!(requests[0].header("Accept"), Some("application/json"));
+    }
+
+    #[tokio::test]
+    async fn filter_on_metadata_and_return_secure_port() {
+        let stub = stub::serve_tcp(|_| StubResponse::json(APPLICATION)).await;
+
+        let args = vec![
+            "provider=eureka".to_string(),
+            format!("url={}", stub.url()),
+            "app=consul".to_string(),
+            "metadata_key=role".to_string(),
+            "metadata_value=server".to_string(),
+            "secure=true".to_string(),
+        ];
+        let provider = EurekaProvider::try_from(args).unwrap();
+        assert_eq!(
+            provider.addrs().await,
+            Ok(vec!["10.0.0.1:8501".to_string()])
+        );
+    }
+
+    #[tokio::test]
+    async fn application_with_a_single_instance() {
+        let stub = stub::serve_tcp(|_| {
+            StubResponse::json(
+                r#"{"application": {"name": "CONSUL", "instance": {
+                    "instanceId": "consul-1:8500",
+                    "ipAddr": "10.0.0.1",
+                    "status": "UP",
+                    "port": { "$": 8500, "@enabled": "true" }
+                }}}"#,
+            )
+        })
+        .await;
+
+        let args = vec![
+            "provider=eureka".to_string(),
+            format!("url={}", stub.url()),
+            "app=consul".to_string(),
+        ];
+        let provider = EurekaProvider::try_from(args).unwrap();
+        assert_eq!(
+            provider.addrs().await,
+            Ok(vec!["10.0.0.1:8500".to_string()])
+        );
+    }
+}
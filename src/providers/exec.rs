@@ -0,0 +1,306 @@
+use log::{debug, info};
+use std::{convert::TryFrom, process::Stdio, time::Duration};
+use tokio::process::Command;
+
+use crate::{args::ParsedArgs, SupportedProvider};
+
+use super::{DiscoverError, Provider};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub struct ExecProvider {
+    command: String,
+    args: Vec<String>,
+    timeout: Duration,
+}
+
+fn parse_timeout(value: &str) -> Result<Duration, DiscoverError> {
+    let malformed = || {
+        DiscoverError::MalformedArgument(
+            format!("timeout={}", value),
+            format!(
+                "{} is not a valid timeout. Expected a number followed by an optional unit: ms, s or m.",
+                value
+            ),
+        )
+    };
+
+    let (amount, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(idx) => value.split_at(idx),
+        None => (value, "s"),
+    };
+    let amount = amount.parse::<u64>().map_err(|_| malformed())?;
+
+    match unit {
+        "ms" => Ok(Duration::from_millis(amount)),
+        "s" => Ok(Duration::from_secs(amount)),
+        "m" => amount
+            .checked_mul(60)
+            .map(Duration::from_secs)
+            .ok_or_else(malformed),
+        _ => Err(malformed()),
+    }
+}
+
+/// Parses the stdout of the command.
+///
+/// Output starting with `[` is parsed as a JSON array of addresses, anything
+/// else is treated as one address per line.
+fn parse_output(stdout: &str) -> Result<Vec<String>, DiscoverError> {
+    let stdout = stdout.trim();
+    if stdout.starts_with('[') {
+        return serde_json::from_str::<Vec<String>>(stdout).map_err(|e| {
+            DiscoverError::ProviderRequestFailed(format!(
+                "Unable to parse command output as a JSON array of addresses: {}",
+                e
+            ))
+        });
+    }
+
+    Ok(stdout
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+impl TryFrom<ParsedArgs> for ExecProvider {
+    type Error = DiscoverError;
+
+    fn try_from(args: ParsedArgs) -> Result<Self, Self::Error> {
+        let mut command = None;
+        let mut cmd_args = Vec::new();
+        let mut timeout = DEFAULT_TIMEOUT;
+
+        for (key, value) in args {
+            match &key[..] {
+                "command" => command = Some(value),
+                "args" => cmd_args = value.split(',').map(String::from).collect(),
+                "timeout" => timeout = parse_timeout(&value)?,
+                _ => return Err(DiscoverError::UnexpectedArgument(key)),
+            }
+        }
+
+        let command = command.ok_or_else(|| DiscoverError::MissingArgument("command".into()))?;
+
+        Ok(ExecProvider {
+            command,
+            args: cmd_args,
+            timeout,
+        })
+    }
+}
+
+impl TryFrom<Vec<String>> for ExecProvider {
+    type Error = DiscoverError;
+
+    fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
+        let args = ParsedArgs::try_from(value)?;
+        match *args.provider() {
+            SupportedProvider::Exec => ExecProvider::try_from(args),
+            _ => Err(DiscoverError::MalformedArgument(
+                format!("provider={}", args.provider()),
+                "you should not see this ...".to_string(),
+            )),
+        }
+    }
+}
+
+impl ExecProvider {
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+}
+
+#[async_trait::async_trait]
+impl Provider for ExecProvider {
+    async fn addrs(&self) -> Result<Vec<String>, DiscoverError> {
+        debug!(
+            "Using command={} args={:?} timeout={:?}",
+            self.command, self.args, self.timeout
+        );
+
+        // The command is spawned directly and never through a shell, so the
+        // arguments are passed to it verbatim.
+        let child = Command::new(&self.command)
+            .args(&self.args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| {
+                DiscoverError::ProviderRequestFailed(format!(
+                    "Unable to spawn command `{}`: {}",
+                    self.command, e
+                ))
+            })?;
+
+        let output = tokio::time::timeout(self.timeout, child.wait_with_output())
+            .await
+            .map_err(|_| {
+                DiscoverError::ProviderRequestFailed(format!(
+                    "Command `{}` timed out after {:?}",
+                    self.command, self.timeout
+                ))
+            })?
+            .map_err(|e| {
+                DiscoverError::ProviderRequestFailed(format!(
+                    "Unable to read output of command `{}`: {}",
+                    self.command, e
+                ))
+            })?;
+
+        if !output.status.success() {
+            return Err(DiscoverError::ProviderRequestFailed(format!(
+                "Command `{}` failed with {}. Stderr: {}",
+                self.command,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+
+        let addrs = parse_output(&String::from_utf8_lossy(&output.stdout))?;
+        info!("Command `{}` returned addresses: {:?}", self.command, addrs);
+
+        Ok(addrs)
+    }
+
+    fn help() -> &'static str {
+        "Exec:
+
+	provider: \"exec\"
+	command:  The executable to run. It is spawned directly and never through a shell.
+	args:     Comma separated list of arguments to pass to the command
+	timeout:  How long to wait for the command, e.g. \"500ms\", \"10s\" or \"1m\". Defaults to \"10s\".
+
+	The command must print the addresses to stdout, either one per line or as a
+	JSON array of strings. A non-zero exit status or a timeout is reported as an
+	error together with the stderr of the command.
+"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn provider(args: &[&str]) -> Result<ExecProvider, DiscoverError> {
+        let args = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        ExecProvider::try_from(args)
+    }
+
+    #[test]
+    fn exec_provider_from_string() {
+        let args = "provider=exec command=/usr/local/bin/discover args=--env,prod timeout=500ms";
+
+        let res = ParsedArgs::try_from(args.to_string());
+        assert!(res.is_ok());
+        let res = ExecProvider::try_from(res.unwrap());
+        assert!(res.is_ok());
+        let provider = res.unwrap();
+        assert_eq!(provider.command(), "/usr/local/bin/discover");
+        assert_eq!(provider.args(), &["--env".to_string(), "prod".to_string()]);
+        assert_eq!(provider.timeout(), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn fail_on_missing_command() {
+        let res = provider(&["provider=exec", "args=foo"]);
+        assert_eq!(
+            res.unwrap_err(),
+            DiscoverError::MissingArgument("command".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_timeouts() {
+        assert_eq!(parse_timeout("3"), Ok(Duration::from_secs(3)));
+        assert_eq!(parse_timeout("3s"), Ok(Duration::from_secs(3)));
+        assert_eq!(parse_timeout("250ms"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_timeout("2m"), Ok(Duration::from_secs(120)));
+        for malformed in &["", "s", "10h", "-1", "1.5s", "18446744073709551615m"] {
+            assert!(parse_timeout(malformed).is_err(), "{}", malformed);
+        }
+    }
+
+    #[test]
+    fn parse_newline_and_json_output() {
+        assert_eq!(
+            parse_output("10.0.0.1\n\n  10.0.0.2  \n"),
+            Ok(vec!["10.0.0.1".to_string(), "10.0.0.2".to_string()])
+        );
+        assert_eq!(
+            parse_output(" [\"10.0.0.1\", \"fd00::1\"]\n"),
+            Ok(vec!["10.0.0.1".to_string(), "fd00::1".to_string()])
+        );
+        assert_eq!(parse_output(""), Ok(vec![]));
+        assert!(parse_output("[10.0.0.1]").is_err());
+    }
+
+    #[tokio::test]
+    async fn returns_addresses_printed_by_command() {
+        let p = provider(&[
+            "provider=exec",
+            "command=printf",
+            "args=10.0.0.1\\n10.0.0.2\\n",
+        ])
+        .unwrap();
+        assert_eq!(
+            p.addrs().await,
+            Ok(vec!["10.0.0.1".to_string(), "10.0.0.2".to_string()])
+        );
+    }
+
+    #[tokio::test]
+    async fn arguments_are_not_interpreted_by_a_shell() {
+        let p = provider(&["provider=exec", "command=echo", "args=$HOME,;,10.0.0.1"]).unwrap();
+        assert_eq!(p.addrs().await, Ok(vec!["$HOME ; 10.0.0.1".to_string()]));
+    }
+
+    #[tokio::test]
+    async fn fail_on_non_zero_exit_with_stderr() {
+        let p = provider(&[
+            "provider=exec",
+            "command=sh",
+            "args=-c,echo boom >&2; exit 3",
+        ])
+        .unwrap();
+        match p.addrs().await {
+            Err(DiscoverError::ProviderRequestFailed(msg)) => {
+                assert!(msg.contains("boom"), "{}", msg)
+            }
+            res => panic!("Expected ProviderRequestFailed, got {:?}", res),
+        }
+    }
+
+    #[tokio::test]
+    async fn fail_on_timeout() {
+        let p = provider(&["provider=exec", "command=sleep", "args=5", "timeout=100ms"]).unwrap();
+        match p.addrs().await {
+            Err(DiscoverError::ProviderRequestFailed(msg)) => {
+                assert!(msg.contains("timed out"), "{}", msg)
+            }
+            res => panic!("Expected ProviderRequestFailed, got {:?}", res),
+        }
+    }
+
+    #[tokio::test]
+    async fn fail_on_unknown_command() {
+        let p = provider(&["provider=exec", "command=/nonexistent/node-discover-plugin"]).unwrap();
+        assert!(matches!(
+            p.addrs().await,
+            Err(DiscoverError::ProviderRequestFailed(_))
+        ));
+    }
+}
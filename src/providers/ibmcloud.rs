@@ -0,0 +1,556 @@
+use log::{debug, info};
+use serde::Deserialize;
+use serde_json::json;
+use std::{
+    collections::HashSet,
+    convert::TryFrom,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{args::ParsedArgs, SupportedProvider};
+
+use super::{DiscoverError, Provider};
+
+const DEFAULT_IAM_URL: &str = "https://iam.cloud.ibm.com";
+const DEFAULT_SEARCH_URL: &str = "https://api.global-search-tagging.cloud.ibm.com";
+/// The date of the VPC API version this provider is written against
+const VPC_API_VERSION: &str = "2024-01-01";
+const PAGE_SIZE: &str = "100";
+/// Tokens are renewed this long before they expire
+const TOKEN_EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Deserialize)]
+struct TokenResponse {
+    pub access_token: String,
+    /// Lifetime of the token in seconds
+    pub expires_in: u64,
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    pub access_token: String,
+    pub expires_at: Instant,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct InstancePage {
+    pub instances: Vec<Instance>,
+    pub next: Option<Link>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Link {
+    pub href: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Instance {
+    pub crn: String,
+    pub name: String,
+    pub status: String,
+    pub primary_network_interface: Option<NetworkInterface>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct NetworkInterface {
+    pub primary_ip: Option<ReservedIp>,
+    /// Deprecated in favour of primary_ip, still returned by older API versions
+    pub primary_ipv4_address: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ReservedIp {
+    pub address: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct SearchResponse {
+    pub items: Vec<SearchItem>,
+    pub search_cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct SearchItem {
+    pub crn: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct IBMCloudProvider {
+    api_key: String,
+    region: String,
+    resource_group_id: Option<String>,
+    tag: Option<String>,
+    iam_url: String,
+    vpc_url: String,
+    search_url: String,
+    /// The IAM token, shared by every request until it expires
+    token: Arc<Mutex<Option<Token>>>,
+}
+
+impl TryFrom<ParsedArgs> for IBMCloudProvider {
+    type Error = DiscoverError;
+
+    fn try_from(args: ParsedArgs) -> Result<Self, Self::Error> {
+        let mut api_key = None;
+        let mut region = None;
+        let mut resource_group_id = None;
+        let mut tag = None;
+        let mut iam_url = DEFAULT_IAM_URL.to_string();
+        let mut vpc_url = None;
+        let mut search_url = DEFAULT_SEARCH_URL.to_string();
+
+        for (key, value) in args {
+            match &key[..] {
+                "api_key" => api_key = Some(value),
+                "region" => region = Some(value),
+                "resource_group_id" => resource_group_id = Some(value),
+                "tag" => tag = Some(value),
+                "iam_url" => iam_url = value.trim_end_matches('/').to_string(),
+                "vpc_url" => vpc_url = Some(value.trim_end_matches('/').to_string()),
+                "search_url" => search_url = value.trim_end_matches('/').to_string(),
+                _ => return Err(DiscoverError::UnexpectedArgument(key)),
+            }
+        }
+
+        let api_key = api_key.ok_or_else(|| DiscoverError::MissingArgument("api_key".into()))?;
+        let region = region.ok_or_else(|| DiscoverError::MissingArgument("region".into()))?;
+        let vpc_url = vpc_url.unwrap_or_else(|| format!("https://{}.iaas.cloud.ibm.com", region));
+
+        Ok(IBMCloudProvider {
+            api_key,
+            region,
+            resource_group_id,
+            tag,
+            iam_url,
+            vpc_url,
+            search_url,
+            token: Arc::new(Mutex::new(None)),
+        })
+    }
+}
+
+impl TryFrom<Vec<String>> for IBMCloudProvider {
+    type Error = DiscoverError;
+
+    fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
+        let args = ParsedArgs::try_from(value)?;
+        match *args.provider() {
+            SupportedProvider::IBMCloud => IBMCloudProvider::try_from(args),
+            _ => Err(DiscoverError::MalformedArgument(
+                format!("provider={}", args.provider()),
+                "you should not see this ...".to_string(),
+            )),
+        }
+    }
+}
+
+fn request_failed(e: reqwest::Error) -> DiscoverError {
+    DiscoverError::ProviderRequestFailed(format!("{:?}", e))
+}
+
+impl IBMCloudProvider {
+    pub fn api_key(&self) -> &str {
+        &self.api_key
+    }
+
+    pub fn region(&self) -> &str {
+        &self.region
+    }
+
+    pub fn resource_group_id(&self) -> Option<&str> {
+        self.resource_group_id.as_deref()
+    }
+
+    pub fn tag(&self) -> Option<&str> {
+        self.tag.as_deref()
+    }
+
+    pub fn vpc_url(&self) -> &str {
+        &self.vpc_url
+    }
+
+    /// Returns the cached IAM token, or exchanges the API key for a new one
+    /// when there is none or it is about to expire.
+    async fn get_token(&self, client: &reqwest::Client) -> Result<String, DiscoverError> {
+        if let Some(token) = self.token.lock().unwrap().as_ref() {
+            if token.expires_at > Instant::now() + TOKEN_EXPIRY_MARGIN {
+                return Ok(token.access_token.clone());
+            }
+        }
+
+        debug!("Requesting IAM token from {}", self.iam_url);
+        let res = client
+            .post(format!("{}/identity/token", self.iam_url))
+            .header("Accept", "application/json")
+            .form(&[
+                ("grant_type", "urn:ibm:params:oauth:grant-type:apikey"),
+                ("apikey", self.api_key.as_str()),
+            ])
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(request_failed)?
+            .json::<TokenResponse>()
+            .await
+            .map_err(request_failed)?;
+
+        let token = Token {
+            access_token: res.access_token,
+            expires_at: Instant::now() + Duration::from_secs(res.expires_in),
+        };
+        let access_token = token.access_token.clone();
+        *self.token.lock().unwrap() = Some(token);
+
+        Ok(access_token)
+    }
+
+    async fn get_instances(
+        &self,
+        client: &reqwest::Client,
+    ) -> Result<Vec<Instance>, DiscoverError> {
+        let mut query = vec![
+            ("version", VPC_API_VERSION),
+            ("generation", "2"),
+            ("limit", PAGE_SIZE),
+        ];
+        if let Some(resource_group_id) = &self.resource_group_id {
+            query.push(("resource_group.id", resource_group_id));
+        }
+
+        let mut instances = Vec::new();
+        let mut start: Option<String> = None;
+        loop {
+            let mut req = client
+                .get(format!("{}/v1/instances", self.vpc_url))
+                .query(&query);
+            if let Some(start) = &start {
+                req = req.query(&[("start", start)]);
+            }
+            let token = self.get_token(client).await?;
+            let page = req
+                .bearer_auth(token)
+                .send()
+                .await
+                .and_then(|res| res.error_for_status())
+                .map_err(request_failed)?
+                .json::<InstancePage>()
+                .await
+                .map_err(request_failed)?;
+            instances.extend(page.instances);
+
+            // Only the start token of `next` is used, so the token is only
+            // ever sent to `vpc_url`.
+            let next = match page.next {
+                Some(next) => next,
+                None => break,
+            };
+            let next_start = reqwest::Url::parse(&next.href)
+                .ok()
+                .and_then(|url| {
+                    url.query_pairs()
+                        .find(|(key, _)| key == "start")
+                        .map(|(_, value)| value.into_owned())
+                })
+                .ok_or_else(|| {
+                    DiscoverError::ProviderRequestFailed(format!(
+                        "Unable to follow the next page {}",
+                        next.href
+                    ))
+                })?;
+            // A token that does not advance would page forever
+            if start.as_ref() == Some(&next_start) {
+                return Err(DiscoverError::ProviderRequestFailed(format!(
+                    "The next page repeats the start token {}",
+                    next_start
+                )));
+            }
+            start = Some(next_start);
+        }
+
+        Ok(instances)
+    }
+
+    /// Returns the CRNs of the VPC instances that carry `tag`.
+    async fn get_tagged_crns(
+        &self,
+        client: &reqwest::Client,
+        tag: &str,
+    ) -> Result<HashSet<String>, DiscoverError> {
+        let query = format!(
+            "tags:\"{}\" AND family:is AND type:instance AND region:{}",
+            tag.replace('"', "\\\""),
+            self.region
+        );
+
+        let mut crns = HashSet::new();
+        let mut search_cursor: Option<String> = None;
+        loop {
+            let token = self.get_token(client).await?;
+            let mut body = json!({ "query": query, "fields": ["crn"] });
+            if let Some(cursor) = &search_cursor {
+                body["search_cursor"] = json!(cursor);
+            }
+
+            let res = client
+                .post(format!("{}/v3/resources/search", self.search_url))
+                .query(&[("limit", "1000")])
+                .bearer_auth(token)
+                .json(&body)
+                .send()
+                .await
+                .and_then(|res| res.error_for_status())
+                .map_err(request_failed)?
+                .json::<SearchResponse>()
+                .await
+                .map_err(request_failed)?;
+
+            // An empty page marks the end of the results
+            if res.items.is_empty() {
+                break;
+            }
+            crns.extend(res.items.into_iter().map(|item| item.crn));
+            search_cursor = res.search_cursor;
+            if search_cursor.is_none() {
+                break;
+            }
+        }
+
+        Ok(crns)
+    }
+}
+
+#[async_trait::async_trait]
+impl Provider for IBMCloudProvider {
+    async fn addrs(&self) -> Result<Vec<String>, DiscoverError> {
+        debug!(
+            "Using region={} resource_group_id={:?} tag={:?} vpc_url={}",
+            self.region, self.resource_group_id, self.tag, self.vpc_url
+        );
+
+        let client = reqwest::Client::new();
+        let instances = self.get_instances(&client).await?;
+        debug!("Found {} instances", instances.len());
+
+        let tagged = match &self.tag {
+            Some(tag) => {
+                let crns = self.get_tagged_crns(&client, tag).await?;
+                debug!("Found {} instances tagged {}", crns.len(), tag);
+                Some(crns)
+            }
+            None => None,
+        };
+
+        let mut addrs = Vec::new();
+        for instance in instances {
+            if instance.status != "running" {
+                debug!(
+                    "Skipping instance {} with status {}",
+                    instance.name, instance.status
+                );
+                continue;
+            }
+            if let Some(tagged) = &tagged {
+                if !tagged.contains(&instance.crn) {
+                    debug!("Skipping instance {} without tag", instance.name);
+                    continue;
+                }
+            }
+
+            let addr = instance.primary_network_interface.and_then(|interface| {
+                interface
+                    .primary_ip
+                    .map(|ip| ip.address)
+                    .or(interface.primary_ipv4_address)
+            });
+            match addr {
+                Some(addr) => {
+                    info!("Instance {} has private ip {}", instance.name, addr);
+                    addrs.push(addr);
+                }
+                None => debug!("Instance {} has no primary ip", instance.name),
+            }
+        }
+
+        Ok(addrs)
+    }
+
+    fn help() -> &'static str {
+        "IBM Cloud VPC:
+
+	provider:          \"ibmcloud\"
+	api_key:           The IBM Cloud API key
+	region:            The VPC region, e.g. \"us-south\"
+	resource_group_id: Only return instances in the resource group with this id. The id is shown by
+	                   `ibmcloud resource groups`, names are not accepted.
+	tag:               Only return instances with this user tag, e.g. \"consul:server\"
+	iam_url:           Base url of IAM. Defaults to \"https://iam.cloud.ibm.com\".
+	vpc_url:           Base url of the VPC API. Defaults to \"https://<region>.iaas.cloud.ibm.com\".
+	search_url:        Base url of the Global Search and Tagging API.
+	                   Defaults to \"https://api.global-search-tagging.cloud.ibm.com\".
+
+	Running instances are returned with the IP of their primary network interface. The
+	API key is exchanged for an IAM token which is reused until it is about to expire.
+"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::providers::stub::{self, StubResponse};
+
+    #[test]
+    fn ibmcloud_provider_from_string() {
+        let args = "provider=ibmcloud api_key=secret region=eu-de resource_group_id=0123abcd tag=consul:server";
+
+        let res = ParsedArgs::try_from(args.to_string());
+        assert!(res.is_ok());
+        let res = IBMCloudProvider::try_from(res.unwrap());
+        assert!(res.is_ok());
+        let provider = res.unwrap();
+        assert_eq!(provider.api_key(), "secret");
+        assert_eq!(provider.region(), "eu-de");
+        assert_eq!(provider.resource_group_id(), Some("0123abcd"));
+        assert_eq!(provider.tag(), Some("consul:server"));
+        assert_eq!(provider.vpc_url(), "https://eu-de.iaas.cloud.ibm.com");
+
+        let res = IBMCloudProvider::try_from(
+            ParsedArgs::try_from("provider=ibmcloud api_key=secret".to_string()).unwrap(),
+        );
+        assert_eq!(
+            res.unwrap_err(),
+            DiscoverError::MissingArgument("region".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn list_tagged_instances_with_cached_token() {
+        let stub = stub::serve_tcp(|req| {
+            let path = req.path.split_once('?').map_or(&req.path[..], |(path, _)| path);
+            let body = match path {
+                "/identity/token" => {
+                    r#"{"access_token": "iam-token", "token_type": "Bearer", "expires_in": 3600}"#
+                        .to_string()
+                }
+                "/v1/instances" if req.path.contains("start=page2") => r#"{"instances": [
+                    {"crn": "crn:3", "name": "consul-3", "status": "running",
+                     "primary_network_interface": {"primary_ipv4_address": "10.240.0.6"}}
+                ]}"#
+                .to_string(),
+                // The next link points at the public endpoint, not at vpc_url
+                "/v1/instances" => r#"{"instances": [
+                    {"crn": "crn:1", "name": "consul-1", "status": "running",
+                     "primary_network_interface": {"primary_ip": {"address": "10.240.0.4"}}},
+                    {"crn": "crn:2", "name": "consul-2", "status": "stopped",
+                     "primary_network_interface": {"primary_ip": {"address": "10.240.0.5"}}},
+                    {"crn": "crn:4", "name": "web-1", "status": "running",
+                     "primary_network_interface": {"primary_ip": {"address": "10.240.0.7"}}}
+                ], "next": {"href": "https://us-south.iaas.cloud.ibm.com/v1/instances?version=2024-01-01&start=page2"}}"#
+                .to_string(),
+                "/v3/resources/search" if req.body.contains("search_cursor") => {
+                    r#"{"items": []}"#.to_string()
+                }
+                "/v3/resources/search" => r#"{"items": [
+                    {"crn": "crn:1"}, {"crn": "crn:2"}, {"crn": "crn:3"}
+                ], "search_cursor": "cursor"}"#
+                .to_string(),
+                _ => return StubResponse::new(404, "application/json", "{}"),
+            };
+            StubResponse::json(&body)
+        })
+        .await;
+
+        let args = vec![
+            "provider=ibmcloud".to_string(),
+            "api_key=secret".to_string(),
+            "region=us-south".to_string(),
+            "resource_group_id=0123abcd".to_string(),
+            "tag=consul:server".to_string(),
+            format!("iam_url={}", stub.url()),
+            format!("vpc_url={}", stub.url()),
+            format!("search_url={}", stub.url()),
+        ];
+        let provider = IBMCloudProvider::try_from(args).unwrap();
+        let expected = vec!["10.240.0.4".to_string(), "10.240.0.6".to_string()];
+        assert_eq!(provider.addrs().await, Ok(expected.clone()));
+        assert_eq!(provider.addrs().await, Ok(expected));
+
+        let requests = stub.requests();
+        let token_requests = requests
+            .iter()
+            .filter(|req| req.path == "/identity/token")
+            .collect::<Vec<_>>();
+        assert_eq!(token_requests.len(), 1);
+        assert!(token_requests[0].body.contains("apikey=secret"));
+
+        let first_page = requests
+            .iter()
+            .find(|req| req.path.starts_with("/v1/instances"))
+            .unwrap();
+        assert!(first_page.path.contains("resource_group.id=0123abcd"));
+        let second_page = requests
+            .iter()
+            .find(|req| req.path.contains("start=page2"))
+            .unwrap();
+        assert!(second_page.path.contains("resource_group.id=0123abcd"));
+        for req in requests.iter().filter(|req| req.path != "/identity/token") {
+            assert_eq!(req.header("authorization"), Some("Bearer iam-token"));
+        }
+    }
+
+    #[tokio::test]
+    async fn fail_when_start_token_repeats() {
+        let stub = stub::serve_tcp(|req| {
+            if req.path == "/identity/token" {
+                return StubResponse::json(
+                    r#"{"access_token": "iam-token", "token_type": "Bearer", "expires_in": 3600}"#,
+                );
+            }
+            StubResponse::json(
+                r#"{"instances": [], "next": {"href": "https://us-south.iaas.cloud.ibm.com/v1/instances?start=page2"}}"#,
+            )
+        })
+        .await;
+
+        let args = vec![
+            "provider=ibmcloud".to_string(),
+            "api_key=secret".to_string(),
+            "region=us-south".to_string(),
+            format!("iam_url={}", stub.url()),
+            format!("vpc_url={}", stub.url()),
+        ];
+        let provider = IBMCloudProvider::try_from(args).unwrap();
+        assert!(matches!(
+            provider.addrs().await,
+            Err(DiscoverError::ProviderRequestFailed(_))
+        ));
+        let pages = stub
+            .requests()
+            .iter()
+            .filter(|req| req.path.starts_with("/v1/instances"))
+            .count();
+        assert_eq!(pages, 2);
+    }
+
+    #[tokio::test]
+    async fn fail_on_rejected_api_key() {
+        let stub = stub::serve_tcp(|_| {
+            StubResponse::new(400, "application/json", r#"{"errorCode": "BXNIM0415E"}"#)
+        })
+        .await;
+
+        let args = vec![
+            "provider=ibmcloud".to_string(),
+            "api_key=invalid".to_string(),
+            "region=us-south".to_string(),
+            format!("iam_url={}", stub.url()),
+            format!("vpc_url={}", stub.url()),
+        ];
+        let provider = IBMCloudProvider::try_from(args).unwrap();
+        assert!(matches!(
+            provider.addrs().await,
+            Err(DiscoverError::ProviderRequestFailed(_))
+        ));
+    }
+}
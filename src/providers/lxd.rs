@@ -0,0 +1,504 @@
+use log::{debug, info};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{args::ParsedArgs, SupportedProvider};
+
+use super::{parse_bool, tls, unix_socket, DiscoverError, Provider};
+
+/// Sockets of the snap and native LXD packages and of Incus, in the order they are tried.
+const DEFAULT_SOCKETS: &[&str] = &[
+    "/var/snap/lxd/common/lxd/unix.socket",
+    "/var/lib/lxd/unix.socket",
+    "/var/lib/incus/unix.socket",
+];
+
+#[derive(Debug, Clone, Deserialize)]
+struct ApiResponse<T> {
+    pub metadata: T,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Instance {
+    pub name: String,
+    pub status: String,
+    #[serde(default)]
+    pub config: HashMap<String, String>,
+    pub state: Option<InstanceState>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct InstanceState {
+    #[serde(default)]
+    pub network: Option<HashMap<String, InstanceNetwork>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct InstanceNetwork {
+    #[serde(default)]
+    pub addresses: Vec<InstanceAddress>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct InstanceAddress {
+    pub family: String,
+    pub address: String,
+    pub scope: String,
+}
+
+/// How to reach the LXD or Incus API.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LxdEndpoint {
+    /// Path of the local Unix socket. When `None` the default locations are tried.
+    Unix(Option<PathBuf>),
+    /// Base url of the remote HTTPS API authenticated with a client certificate.
+    Https {
+        url: String,
+        client_cert: PathBuf,
+        client_key: PathBuf,
+        insecure_ssl: bool,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct LxdProvider {
+    endpoint: LxdEndpoint,
+    project: String,
+    config_key: String,
+    config_value: String,
+    interface: String,
+    family: String,
+}
+
+impl TryFrom<ParsedArgs> for LxdProvider {
+    type Error = DiscoverError;
+
+    fn try_from(args: ParsedArgs) -> Result<Self, Self::Error> {
+        let mut socket = None;
+        let mut url = None;
+        let mut client_cert = None;
+        let mut client_key = None;
+        let mut insecure_ssl = false;
+        let mut project = "default".to_string();
+        let mut config_key = None;
+        let mut config_value = None;
+        let mut interface = "eth0".to_string();
+        let mut family = "inet".to_string();
+
+        for (key, value) in args {
+            match &key[..] {
+                "socket" => socket = Some(PathBuf::from(value)),
+                "url" => url = Some(value.trim_end_matches('/').to_string()),
+                "client_cert" => client_cert = Some(PathBuf::from(value)),
+                "client_key" => client_key = Some(PathBuf::from(value)),
+                "insecure_ssl" => insecure_ssl = parse_bool(&key, &value)?,
+                "project" => project = value,
+                "config_key" => {
+                    if !value.starts_with("user.") {
+                        return Err(DiscoverError::MalformedArgument(
+                            format!("config_key={}", value),
+                            "Only user.* config keys can be used to filter instances".to_string(),
+                        ));
+                    }
+                    config_key = Some(value)
+                }
+                "config_value" => config_value = Some(value),
+                "interface" => interface = value,
+                "family" => match &value[..] {
+                    "inet" | "inet6" => family = value,
+                    _ => {
+                        return Err(DiscoverError::MalformedArgument(
+                            format!("family={}", value),
+                            format!(
+                                "{} is not a valid family. Valid families are: inet and inet6.",
+                                value
+                            ),
+                        ))
+                    }
+                },
+                _ => return Err(DiscoverError::UnexpectedArgument(key)),
+            }
+        }
+
+        let endpoint = match url {
+            Some(url) => {
+                if let Some(socket) = socket {
+                    return Err(DiscoverError::MalformedArgument(
+                        format!("socket={}", socket.display()),
+                        "socket and url can not be used together".into(),
+                    ));
+                }
+                LxdEndpoint::Https {
+                    url,
+                    client_cert: client_cert
+                        .ok_or_else(|| DiscoverError::MissingArgument("client_cert".into()))?,
+                    client_key: client_key
+                        .ok_or_else(|| DiscoverError::MissingArgument("client_key".into()))?,
+                    insecure_ssl,
+                }
+            }
+            None => {
+                if let Some(key) = client_cert
+                    .as_ref()
+                    .map(|_| "client_cert")
+                    .or_else(|| client_key.as_ref().map(|_| "client_key"))
+                {
+                    return Err(DiscoverError::UnexpectedArgument(key.into()));
+                }
+                LxdEndpoint::Unix(socket)
+            }
+        };
+
+        let config_key =
+            config_key.ok_or_else(|| DiscoverError::MissingArgument("config_key".into()))?;
+        let config_value =
+            config_value.ok_or_else(|| DiscoverError::MissingArgument("config_value".into()))?;
+
+        Ok(LxdProvider {
+            endpoint,
+            project,
+            config_key,
+            config_value,
+            interface,
+            family,
+        })
+    }
+}
+
+impl TryFrom<Vec<String>> for LxdProvider {
+    type Error = DiscoverError;
+
+    fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
+        let args = ParsedArgs::try_from(value)?;
+        match *args.provider() {
+            SupportedProvider::Lxd => LxdProvider::try_from(args),
+            _ => Err(DiscoverError::MalformedArgument(
+                format!("provider={}", args.provider()),
+                "you should not see this ...".to_string(),
+            )),
+        }
+    }
+}
+
+fn read_pem(path: &Path) -> Result<Vec<u8>, DiscoverError> {
+    fs::read(path).map_err(|e| {
+        DiscoverError::ProviderRequestFailed(format!("Unable to read {}: {}", path.display(), e))
+    })
+}
+
+impl LxdProvider {
+    pub fn endpoint(&self) -> &LxdEndpoint {
+        &self.endpoint
+    }
+
+    pub fn project(&self) -> &str {
+        &self.project
+    }
+
+    pub fn config_key(&self) -> &str {
+        &self.config_key
+    }
+
+    pub fn config_value(&self) -> &str {
+        &self.config_value
+    }
+
+    pub fn interface(&self) -> &str {
+        &self.interface
+    }
+
+    pub fn family(&self) -> &str {
+        &self.family
+    }
+
+    async fn get_instances(&self) -> Result<Vec<Instance>, DiscoverError> {
+        debug!(
+            "Using endpoint={:?} project={} config_key={} config_value={}",
+            self.endpoint, self.project, self.config_key, self.config_value
+        );
+
+        let path = format!(
+            "/1.0/instances?recursion=2&project={}",
+            url::form_urlencoded::byte_serialize(self.project.as_bytes()).collect::<String>()
+        );
+
+        let res = match &self.endpoint {
+            LxdEndpoint::Unix(socket) => {
+                let socket = match socket {
+                    Some(socket) => socket.clone(),
+                    None => DEFAULT_SOCKETS
+                        .iter()
+                        .map(PathBuf::from)
+                        .find(|socket| socket.exists())
+                        .ok_or_else(|| {
+                            DiscoverError::ProviderRequestFailed(format!(
+                                "No LXD or Incus socket found at any of {:?}",
+                                DEFAULT_SOCKETS
+                            ))
+                        })?,
+                };
+                unix_socket::get_json::<ApiResponse<Vec<Instance>>>(&socket, &path, &[]).await?
+            }
+            LxdEndpoint::Https {
+                url,
+                client_cert,
+                client_key,
+                insecure_ssl,
+            } => {
+                let identity = tls::identity(&read_pem(client_cert)?, &read_pem(client_key)?)?;
+                reqwest::Client::builder()
+                    .identity(identity)
+                    .danger_accept_invalid_certs(*insecure_ssl)
+                    .build()
+                    .map_err(|e| DiscoverError::ProviderRequestFailed(format!("{:?}", e)))?
+                    .get(format!("{}{}", url, path))
+                    .send()
+                    .await
+                    .and_then(|res| res.error_for_status())
+                    .map_err(|e| DiscoverError::ProviderRequestFailed(format!("{:?}", e)))?
+                    .json::<ApiResponse<Vec<Instance>>>()
+                    .await
+                    .map_err(|e| DiscoverError::ProviderRequestFailed(format!("{:?}", e)))?
+            }
+        };
+
+        Ok(res.metadata)
+    }
+}
+
+#[async_trait::async_trait]
+impl Provider for LxdProvider {
+    async fn addrs(&self) -> Result<Vec<String>, DiscoverError> {
+        let instances = self.get_instances().await?;
+        debug!("Found {} instances", instances.len());
+
+        let addrs = instances
+            .into_iter()
+            .filter(|instance| instance.status == "Running")
+            .filter(|instance| instance.config.get(&self.config_key) == Some(&self.config_value))
+            .flat_map(|instance| {
+                let network = instance
+                    .state
+                    .and_then(|state| state.network)
+                    .and_then(|mut network| network.remove(&self.interface));
+                let addrs = network
+                    .map(|network| network.addresses)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter(|addr| addr.family == self.family && addr.scope == "global")
+                    .map(|addr| addr.address)
+                    .collect::<Vec<_>>();
+
+                if addrs.is_empty() {
+                    debug!(
+                        "Instance {} has no {} address on interface {}",
+                        instance.name, self.family, self.interface
+                    );
+                } else {
+                    info!(
+                        "Found instance {} with IPs {:?} on interface {}",
+                        instance.name, addrs, self.interface
+                    );
+                }
+                addrs
+            })
+            .collect::<Vec<_>>();
+
+        debug!("Found ip addresses: {:?}", addrs);
+        Ok(addrs)
+    }
+
+    fn help() -> &'static str {
+        "LXD / Incus:
+
+	provider:     \"lxd\"
+	socket:       Path of the local Unix socket. Defaults to the first that exists of
+	              /var/snap/lxd/common/lxd/unix.socket, /var/lib/lxd/unix.socket and
+	              /var/lib/incus/unix.socket.
+	url:          The url of a remote HTTPS API, e.g. \"https://10.0.0.2:8443\". Can not be used with socket.
+	client_cert:  Path of the PEM encoded client certificate trusted by the remote. Required with url.
+	client_key:   Path of the PEM encoded client key, PKCS#8, SEC1 (\"BEGIN EC PRIVATE KEY\", as written by
+	              lxc) or PKCS#1 (\"BEGIN RSA PRIVATE KEY\"). Required with url.
+	insecure_ssl: \"true\" or \"false\". Skip verification of the remote certificate. Defaults to \"false\".
+	project:      The project to list instances in. Defaults to \"default\".
+	config_key:   The user.* config key to filter on, e.g. \"user.consul-role\"
+	config_value: The config value to filter on
+	interface:    The interface to return the addresses of. Defaults to \"eth0\".
+	family:       \"inet\" or \"inet6\". Defaults to \"inet\".
+"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::providers::stub::{self, StubResponse};
+
+    const INSTANCES: &str = r#"{
+        "type": "sync",
+        "status": "Success",
+        "metadata": [
+            {
+                "name": "consul-1",
+                "status": "Running",
+                "config": { "user.consul-role": "server" },
+                "state": { "network": {
+                    "lo": { "addresses": [{ "family": "inet", "address": "127.0.0.1", "netmask": "8", "scope": "local" }] },
+                    "eth0": { "addresses": [
+                        { "family": "inet", "address": "10.141.0.11", "netmask": "24", "scope": "global" },
+                        { "family": "inet6", "address": "fd42::11", "netmask": "64", "scope": "global" },
+                        { "family": "inet6", "address": "fe80::11", "netmask": "64", "scope": "link" }
+                    ]}
+                }}
+            },
+            {
+                "name": "consul-2",
+                "status": "Stopped",
+                "config": { "user.consul-role": "server" },
+                "state": { "network": null }
+            },
+            {
+                "name": "consul-client",
+                "status": "Running",
+                "config": { "user.consul-role": "client" },
+                "state": { "network": {
+                    "eth0": { "addresses": [{ "family": "inet", "address": "10.141.0.12", "netmask": "24", "scope": "global" }] }
+                }}
+            },
+            {
+                "name": "consul-3",
+                "status": "Running",
+                "config": { "user.consul-role": "server" },
+                "state": { "network": {
+                    "eth0": { "addresses": [
+                        { "family": "inet", "address": "10.141.0.13", "netmask": "24", "scope": "global" },
+                        { "family": "inet6", "address": "fd42::13", "netmask": "64", "scope": "global" }
+                    ]}
+                }}
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn lxd_provider_from_string() {
+        let args = "provider=lxd url=https://10.0.0.2:8443/ client_cert=/etc/discover/client.crt client_key=/etc/discover/client.key project=consul config_key=user.consul-role config_value=server interface=enp5s0 family=inet6";
+
+        let res = ParsedArgs::try_from(args.to_string());
+        assert!(res.is_ok());
+        let res = LxdProvider::try_from(res.unwrap());
+        assert!(res.is_ok());
+        let provider = res.unwrap();
+        assert_eq!(
+            provider.endpoint(),
+            &LxdEndpoint::Https {
+                url: "https://10.0.0.2:8443".to_string(),
+                client_cert: PathBuf::from("/etc/discover/client.crt"),
+                client_key: PathBuf::from("/etc/discover/client.key"),
+                insecure_ssl: false,
+            }
+        );
+        assert_eq!(provider.project(), "consul");
+        assert_eq!(provider.config_key(), "user.consul-role");
+        assert_eq!(provider.config_value(), "server");
+        assert_eq!(provider.interface(), "enp5s0");
+        assert_eq!(provider.family(), "inet6");
+    }
+
+    #[test]
+    fn defaults_to_local_socket() {
+        let args = "provider=lxd config_key=user.consul-role config_value=server";
+
+        let provider =
+            LxdProvider::try_from(ParsedArgs::try_from(args.to_string()).unwrap()).unwrap();
+        assert_eq!(provider.endpoint(), &LxdEndpoint::Unix(None));
+        assert_eq!(provider.project(), "default");
+        assert_eq!(provider.interface(), "eth0");
+        assert_eq!(provider.family(), "inet");
+    }
+
+    #[test]
+    fn fail_on_invalid_arguments() {
+        let cases = vec![
+            (
+                "provider=lxd config_key=volatile.eth0.hwaddr config_value=x",
+                DiscoverError::MalformedArgument(
+                    "config_key=volatile.eth0.hwaddr".into(),
+                    "Only user.* config keys can be used to filter instances".into(),
+                ),
+            ),
+            (
+                "provider=lxd url=https://10.0.0.2:8443 config_key=user.role config_value=server",
+                DiscoverError::MissingArgument("client_cert".into()),
+            ),
+            (
+                "provider=lxd url=https://10.0.0.2:8443 socket=/run/lxd.socket config_key=user.role config_value=server",
+                DiscoverError::MalformedArgument(
+                    "socket=/run/lxd.socket".into(),
+                    "socket and url can not be used together".into(),
+                ),
+            ),
+            (
+                "provider=lxd client_key=/tmp/key config_key=user.role config_value=server",
+                DiscoverError::UnexpectedArgument("client_key".into()),
+            ),
+            (
+                "provider=lxd config_key=user.role",
+                DiscoverError::MissingArgument("config_value".into()),
+            ),
+        ];
+
+        for (args, err) in cases {
+            let res = LxdProvider::try_from(ParsedArgs::try_from(args.to_string()).unwrap());
+            assert_eq!(res.unwrap_err(), err, "{}", args);
+        }
+    }
+
+    #[tokio::test]
+    async fn list_instances_over_unix_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("unix.socket");
+        let stub = stub::serve_unix(&socket, |_| StubResponse::json(INSTANCES)).await;
+
+        let args = vec![
+            "provider=lxd".to_string(),
+            format!("socket={}", socket.display()),
+            "project=consul".to_string(),
+            "config_key=user.consul-role".to_string(),
+            "config_value=server".to_string(),
+        ];
+        let provider = LxdProvider::try_from(args).unwrap();
+        assert_eq!(
+            provider.addrs().await,
+            Ok(vec!["10.141.0.11".to_string(), "10.141.0.13".to_string()])
+        );
+        assert_eq!(
+            stub.requests()[0].path,
+            "/1.0/instances?recursion=2&project=consul"
+        );
+    }
+
+    #[tokio::test]
+    async fn return_ipv6_addresses() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("unix.socket");
+        let _stub = stub::serve_unix(&socket, |_| StubResponse::json(INSTANCES)).await;
+
+        let args = vec![
+            "provider=lxd".to_string(),
+            format!("socket={}", socket.display()),
+            "config_key=user.consul-role".to_string(),
+            "config_value=server".to_string(),
+            "family=inet6".to_string(),
+        ];
+        let provider = LxdProvider::try_from(args).unwrap();
+        assert_eq!(
+            provider.addrs().await,
+            Ok(vec!["fd42::11".to_string(), "fd42::13".to_string()])
+        );
+    }
+}
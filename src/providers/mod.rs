@@ -1,7 +1,59 @@
+#[cfg(feature = "ansible")]
+pub mod ansible;
 #[cfg(feature = "aws")]
 pub mod aws;
+#[cfg(feature = "aws-cloudmap")]
+pub mod aws_cloudmap;
+#[cfg(feature = "aws")]
+mod aws_config;
+#[cfg(feature = "aws-ecs")]
+pub mod aws_ecs;
+#[cfg(feature = "aws")]
+mod aws_metadata;
+#[cfg(feature = "aws-targetgroup")]
+pub mod aws_targetgroup;
 #[cfg(feature = "digitalocean")]
 pub mod digitalocean;
+#[cfg(feature = "docker")]
+pub mod docker;
+#[cfg(feature = "etcd")]
+pub mod etcd;
+#[cfg(feature = "eureka")]
+pub mod eureka;
+#[cfg(feature = "exec")]
+pub mod exec;
+#[cfg(feature = "ibmcloud")]
+pub mod ibmcloud;
+#[cfg(feature = "lxd")]
+pub mod lxd;
+#[cfg(feature = "netbox")]
+pub mod netbox;
+#[cfg(feature = "proxmox")]
+pub mod proxmox;
+#[cfg(feature = "tailscale")]
+pub mod tailscale;
+#[cfg(feature = "tfstate")]
+pub mod tfstate;
+#[cfg(any(feature = "etcd", feature = "lxd"))]
+mod tls;
+
+#[cfg(all(
+    test,
+    any(
+        feature = "aws",
+        feature = "docker",
+        feature = "etcd",
+        feature = "eureka",
+        feature = "ibmcloud",
+        feature = "lxd",
+        feature = "netbox",
+        feature = "proxmox",
+        feature = "tailscale"
+    )
+))]
+mod stub;
+#[cfg(any(feature = "docker", feature = "lxd", feature = "tailscale"))]
+mod unix_socket;
 
 use std::convert::TryFrom;
 
@@ -18,3 +70,24 @@ pub trait Provider: TryFrom<ParsedArgs> + Send + Sync {
     /// provider needs to know should also be explained.
     fn help() -> &'static str;
 }
+
+/// Parses the value of a boolean argument, which must be exactly `true` or
+/// `false`.
+#[cfg(any(
+    feature = "aws",
+    feature = "tailscale",
+    feature = "proxmox",
+    feature = "lxd",
+    feature = "netbox",
+    feature = "eureka"
+))]
+fn parse_bool(key: &str, value: &str) -> Result<bool, DiscoverError> {
+    match value {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(DiscoverError::MalformedArgument(
+            format!("{}={}", key, value),
+            "Expected either true or false".to_string(),
+        )),
+    }
+}
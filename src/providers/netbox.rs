@@ -0,0 +1,442 @@
+use log::{debug, info};
+use serde::Deserialize;
+use std::convert::TryFrom;
+
+use crate::{args::ParsedArgs, SupportedProvider};
+
+use super::{parse_bool, DiscoverError, Provider};
+
+/// The default MAX_PAGE_SIZE of NetBox.
+const PAGE_SIZE: &str = "1000";
+
+#[derive(Debug, Clone, Deserialize)]
+struct Page {
+    pub next: Option<String>,
+    pub results: Vec<Object>,
+}
+
+/// A device or a virtual machine.
+#[derive(Debug, Clone, Deserialize)]
+struct Object {
+    pub name: Option<String>,
+    pub primary_ip4: Option<IpAddress>,
+    pub primary_ip6: Option<IpAddress>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct IpAddress {
+    /// The address with its prefix length, e.g. "10.0.0.1/24"
+    pub address: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct NetBoxProvider {
+    url: String,
+    token: Option<String>,
+    sites: Vec<String>,
+    roles: Vec<String>,
+    tags: Vec<String>,
+    statuses: Vec<String>,
+    family: String,
+    insecure_ssl: bool,
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .filter(|item| !item.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+impl TryFrom<ParsedArgs> for NetBoxProvider {
+    type Error = DiscoverError;
+
+    fn try_from(args: ParsedArgs) -> Result<Self, Self::Error> {
+        let mut url = None;
+        let mut token = None;
+        let mut sites = Vec::new();
+        let mut roles = Vec::new();
+        let mut tags = Vec::new();
+        let mut statuses = vec!["active".to_string()];
+        let mut family = "inet".to_string();
+        let mut insecure_ssl = false;
+
+        for (key, value) in args {
+            match &key[..] {
+                "url" => url = Some(value.trim_end_matches('/').to_string()),
+                "token" => token = Some(value),
+                "site" => sites = split_list(&value),
+                "role" => roles = split_list(&value),
+                "tag" => tags = split_list(&value),
+                "status" => statuses = split_list(&value),
+                "family" => match &value[..] {
+                    "inet" | "inet6" => family = value,
+                    _ => {
+                        return Err(DiscoverError::MalformedArgument(
+                            format!("family={}", value),
+                            format!(
+                                "{} is not a valid family. Valid families are: inet and inet6.",
+                                value
+                            ),
+                        ))
+                    }
+                },
+                "insecure_ssl" => insecure_ssl = parse_bool(&key, &value)?,
+                _ => return Err(DiscoverError::UnexpectedArgument(key)),
+            }
+        }
+
+        let url = url.ok_or_else(|| DiscoverError::MissingArgument("url".into()))?;
+
+        Ok(NetBoxProvider {
+            url,
+            token,
+            sites,
+            roles,
+            tags,
+            statuses,
+            family,
+            insecure_ssl,
+        })
+    }
+}
+
+impl TryFrom<Vec<String>> for NetBoxProvider {
+    type Error = DiscoverError;
+
+    fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
+        let args = ParsedArgs::try_from(value)?;
+        match *args.provider() {
+            SupportedProvider::NetBox => NetBoxProvider::try_from(args),
+            _ => Err(DiscoverError::MalformedArgument(
+                format!("provider={}", args.provider()),
+                "you should not see this ...".to_string(),
+            )),
+        }
+    }
+}
+
+impl NetBoxProvider {
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn token(&self) -> Option<&str> {
+        self.token.as_deref()
+    }
+
+    pub fn sites(&self) -> &[String] {
+        &self.sites
+    }
+
+    pub fn roles(&self) -> &[String] {
+        &self.roles
+    }
+
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    pub fn statuses(&self) -> &[String] {
+        &self.statuses
+    }
+
+    pub fn family(&self) -> &str {
+        &self.family
+    }
+
+    pub fn insecure_ssl(&self) -> bool {
+        self.insecure_ssl
+    }
+
+    /// The filters of the first page. The `next` links of NetBox carry them
+    /// over to the following pages.
+    fn query(&self) -> Vec<(&str, &str)> {
+        let mut query = vec![("limit", PAGE_SIZE)];
+        for (key, values) in &[
+            ("site", &self.sites),
+            ("role", &self.roles),
+            ("tag", &self.tags),
+            ("status", &self.statuses),
+        ] {
+            query.extend(values.iter().map(|value| (*key, value.as_str())));
+        }
+        query
+    }
+
+    /// Fetches every page of `endpoint`, e.g. "dcim/devices".
+    async fn get_objects(
+        &self,
+        client: &reqwest::Client,
+        endpoint: &str,
+    ) -> Result<Vec<Object>, DiscoverError> {
+        let mut objects = Vec::new();
+        let mut req = client
+            .get(format!("{}/api/{}/", self.url, endpoint))
+            .query(&self.query());
+
+        loop {
+            if let Some(token) = &self.token {
+                req = req.header("Authorization", format!("Token {}", token));
+            }
+            let page = req
+                .header("Accept", "application/json")
+                .send()
+                .await
+                .and_then(|res| res.error_for_status())
+                .map_err(|e| DiscoverError::ProviderRequestFailed(format!("{:?}", e)))?
+                .json::<Page>()
+                .await
+                .map_err(|e| DiscoverError::ProviderRequestFailed(format!("{:?}", e)))?;
+            objects.extend(page.results);
+
+            // Only the query of `next` is used. NetBox builds it from the Host
+            // header it received, which differs from `url` behind a proxy, and
+            // the token must only be sent to `url`.
+            match page.next {
+                Some(next) => {
+                    let query = reqwest::Url::parse(&next)
+                        .ok()
+                        .and_then(|next| next.query().map(String::from))
+                        .ok_or_else(|| {
+                            DiscoverError::ProviderRequestFailed(format!(
+                                "Unable to follow the next page {}",
+                                next
+                            ))
+                        })?;
+                    req = client.get(format!("{}/api/{}/?{}", self.url, endpoint, query));
+                }
+                None => break,
+            }
+        }
+
+        Ok(objects)
+    }
+}
+
+#[async_trait::async_trait]
+impl Provider for NetBoxProvider {
+    async fn addrs(&self) -> Result<Vec<String>, DiscoverError> {
+        debug!(
+            "Using url={} sites={:?} roles={:?} tags={:?} statuses={:?} family={}",
+            self.url, self.sites, self.roles, self.tags, self.statuses, self.family
+        );
+
+        let client = reqwest::Client::builder()
+            .danger_accept_invalid_certs(self.insecure_ssl)
+            .build()
+            .map_err(|e| DiscoverError::ProviderRequestFailed(format!("{:?}", e)))?;
+
+        let mut objects = self.get_objects(&client, "dcim/devices").await?;
+        debug!("Found {} devices", objects.len());
+        let vms = self
+            .get_objects(&client, "virtualization/virtual-machines")
+            .await?;
+        debug!("Found {} virtual machines", vms.len());
+        objects.extend(vms);
+
+        let mut addrs = Vec::new();
+        for object in objects {
+            let name = object.name.unwrap_or_default();
+            let primary_ip = match &self.family[..] {
+                "inet6" => object.primary_ip6,
+                _ => object.primary_ip4,
+            };
+            match primary_ip {
+                Some(ip) => {
+                    // Strip the prefix length
+                    let addr = ip.address.split('/').next().unwrap_or_default().to_string();
+                    info!("{} has primary ip {}", name, addr);
+                    addrs.push(addr);
+                }
+                None => debug!("{} has no primary {} address", name, self.family),
+            }
+        }
+
+        Ok(addrs)
+    }
+
+    fn help() -> &'static str {
+        "NetBox:
+
+	provider:     \"netbox\"
+	url:          Base url of NetBox, e.g. \"https://netbox.example.com\"
+	token:        The API token. Optional if NetBox allows anonymous reads.
+	site:         Comma separated site slugs to filter on
+	role:         Comma separated role slugs to filter on
+	tag:          Comma separated tag slugs, matching objects carry every tag
+	status:       Comma separated statuses to filter on. Defaults to \"active\".
+	family:       \"inet\" or \"inet6\". Defaults to \"inet\".
+	insecure_ssl: \"true\" or \"false\". Skip verification of the API certificate. Defaults to \"false\".
+
+	Both devices and virtual machines are returned as their primary IPv4 or IPv6
+	address. The token only needs read access to DCIM and virtualization.
+"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::providers::stub::{self, StubResponse};
+
+    #[test]
+    fn netbox_provider_from_string() {
+        let args = "provider=netbox url=https://netbox.example.com/ token=0123456789abcdef site=ams1,fra1 role=consul-server tag=consul";
+
+        let res = ParsedArgs::try_from(args.to_string());
+        assert!(res.is_ok());
+        let res = NetBoxProvider::try_from(res.unwrap());
+        assert!(res.is_ok());
+        let provider = res.unwrap();
+        assert_eq!(provider.url(), "https://netbox.example.com");
+        assert_eq!(provider.token(), Some("0123456789abcdef"));
+        assert_eq!(provider.sites(), &["ams1".to_string(), "fra1".to_string()]);
+        assert_eq!(provider.roles(), &["consul-server".to_string()]);
+        assert_eq!(provider.tags(), &["consul".to_string()]);
+        assert_eq!(provider.statuses(), &["active".to_string()]);
+        assert_eq!(provider.family(), "inet");
+        assert!(!provider.insecure_ssl());
+
+        let res =
+            NetBoxProvider::try_from(ParsedArgs::try_from("provider=netbox".to_string()).unwrap());
+        assert_eq!(
+            res.unwrap_err(),
+            DiscoverError::MissingArgument("url".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn page_through_devices_and_virtual_machines() {
+        let stub = stub::serve_tcp(|req| {
+            let host = req.header("host").unwrap_or_default();
+            let body = match req.path.split_once('?').map(|(path, _)| path) {
+                Some("/api/dcim/devices/") if req.path.contains("offset=2") => {
+                    r#"{"count": 3, "next": null, "results": [
+                        {"name": "consul-3", "primary_ip4": {"address": "10.0.0.3/24"}, "primary_ip6": null}
+                    ]}"#
+                    .to_string()
+                }
+                Some("/api/dcim/devices/") => format!(
+                    r#"{{"count": 3, "next": "http://{}/api/dcim/devices/?limit=2&offset=2&site=ams1", "results": [
+                        {{"name": "consul-1", "primary_ip4": {{"address": "10.0.0.1/24"}}, "primary_ip6": {{"address": "2001:db8::1/64"}}}},
+                        {{"name": "consul-2", "primary_ip4": null, "primary_ip6": null}}
+                    ]}}"#,
+                    host
+                ),
+                Some("/api/virtualization/virtual-machines/") => {
+                    r#"{"count": 1, "next": null, "results": [
+                        {"name": "consul-vm", "primary_ip4": {"address": "10.0.1.1/32"}, "primary_ip6": {"address": "2001:db8::2/128"}}
+                    ]}"#
+                    .to_string()
+                }
+                _ => return StubResponse::new(404, "application/json", r#"{"detail": "Not found."}"#),
+            };
+            StubResponse::json(&body)
+        })
+        .await;
+
+        let args = vec![
+            "provider=netbox".to_string(),
+            format!("url={}", stub.url()),
+            "token=0123456789abcdef".to_string(),
+            "site=ams1".to_string(),
+            "tag=consul,server".to_string(),
+        ];
+        let provider = NetBoxProvider::try_from(args).unwrap();
+        assert_eq!(
+            provider.addrs().await,
+            Ok(vec![
+                "10.0.0.1".to_string(),
+                "10.0.0.3".to_string(),
+                "10.0.1.1".to_string(),
+            ])
+        );
+
+        let requests = stub.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(
+            requests[0].path,
+            "/api/dcim/devices/?limit=1000&site=ams1&tag=consul&tag=server&status=active"
+        );
+        assert!(requests[1].path.contains("offset=2"));
+        for request in &requests {
+            assert_eq!(
+                request.header("authorization"),
+                Some("Token 0123456789abcdef")
+            );
+        }
+
+        let args = vec![
+            "provider=netbox".to_string(),
+            format!("url={}", stub.url()),
+            "family=inet6".to_string(),
+        ];
+        let provider = NetBoxProvider::try_from(args).unwrap();
+        assert_eq!(
+            provider.addrs().await,
+            Ok(vec!["2001:db8::1".to_string(), "2001:db8::2".to_string()])
+        );
+        assert_eq!(stub.requests()[3].header("authorization"), None);
+    }
+
+    #[tokio::test]
+    async fn request_the_next_page_from_the_configured_url() {
+        let stub = stub::serve_tcp(|req| {
+            let body = match req.path.split_once('?').map(|(path, _)| path) {
+                Some("/api/dcim/devices/") if req.path.contains("offset=1") => {
+                    r#"{"count": 2, "next": null, "results": [
+                        {"name": "consul-2", "primary_ip4": {"address": "10.0.0.2/24"}, "primary_ip6": null}
+                    ]}"#
+                }
+                Some("/api/dcim/devices/") => {
+                    r#"{"count": 2, "next": "https://netbox.example.invalid/api/dcim/devices/?limit=1&offset=1", "results": [
+                        {"name": "consul-1", "primary_ip4": {"address": "10.0.0.1/24"}, "primary_ip6": null}
+                    ]}"#
+                }
+                _ => r#"{"count": 0, "next": null, "results": []}"#,
+            };
+            StubResponse::json(body)
+        })
+        .await;
+
+        let args = vec![
+            "provider=netbox".to_string(),
+            format!("url={}", stub.url()),
+            "token=0123456789abcdef".to_string(),
+        ];
+        let provider = NetBoxProvider::try_from(args).unwrap();
+        assert_eq!(
+            provider.addrs().await,
+            Ok(vec!["10.0.0.1".to_string(), "10.0.0.2".to_string()])
+        );
+
+        let requests = stub.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[1].path, "/api/dcim/devices/?limit=1&offset=1");
+        assert_eq!(
+            requests[1].header("authorization"),
+            Some("Token 0123456789abcdef")
+        );
+    }
+
+    #[tokio::test]
+    async fn fail_on_api_error() {
+        let stub = stub::serve_tcp(|_| {
+            StubResponse::new(403, "application/json", r#"{"detail": "Invalid token"}"#)
+        })
+        .await;
+
+        let args = vec![
+            "provider=netbox".to_string(),
+            format!("url={}", stub.url()),
+            "token=invalid".to_string(),
+        ];
+        let provider = NetBoxProvider::try_from(args).unwrap();
+        assert!(matches!(
+            provider.addrs().await,
+            Err(DiscoverError::ProviderRequestFailed(_))
+        ));
+    }
+}
@@ -0,0 +1,490 @@
+use log::{debug, info, warn};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer};
+use std::convert::TryFrom;
+
+use crate::{args::ParsedArgs, SupportedProvider};
+
+use super::{parse_bool, DiscoverError, Provider};
+
+#[derive(Debug, Clone, Deserialize)]
+struct ApiResponse<T> {
+    pub data: T,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Node {
+    pub node: String,
+    /// "online", "offline" or "unknown"
+    #[serde(default)]
+    pub status: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Guest {
+    #[serde(deserialize_with = "deserialize_vmid")]
+    pub vmid: String,
+    #[serde(default)]
+    pub name: String,
+    pub status: String,
+    #[serde(default)]
+    pub tags: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct AgentInterfaces {
+    pub result: Vec<AgentInterface>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct AgentInterface {
+    pub name: String,
+    #[serde(rename = "ip-addresses", default)]
+    pub ip_addresses: Vec<AgentIpAddress>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct AgentIpAddress {
+    #[serde(rename = "ip-address")]
+    pub ip_address: String,
+    #[serde(rename = "ip-address-type")]
+    pub ip_address_type: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct LxcInterface {
+    pub name: String,
+    /// Address in CIDR notation, e.g. `10.0.0.6/24`
+    pub inet: Option<String>,
+}
+
+/// Proxmox returns the vmid as a number on most endpoints, but as a string on some versions.
+fn deserialize_vmid<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Vmid {
+        Number(u64),
+        String(String),
+    }
+
+    Ok(match Vmid::deserialize(deserializer)? {
+        Vmid::Number(id) => id.to_string(),
+        Vmid::String(id) => id,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum GuestType {
+    Qemu,
+    Lxc,
+}
+
+impl GuestType {
+    fn path(&self) -> &'static str {
+        match self {
+            GuestType::Qemu => "qemu",
+            GuestType::Lxc => "lxc",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ProxmoxProvider {
+    url: String,
+    token_id: String,
+    token_secret: String,
+    node: Option<String>,
+    tags: Vec<String>,
+    insecure_ssl: bool,
+}
+
+impl TryFrom<ParsedArgs> for ProxmoxProvider {
+    type Error = DiscoverError;
+
+    fn try_from(args: ParsedArgs) -> Result<Self, Self::Error> {
+        let mut url = None;
+        let mut token_id = None;
+        let mut token_secret = None;
+        let mut node = None;
+        let mut tags = None;
+        let mut insecure_ssl = false;
+
+        for (key, value) in args {
+            match &key[..] {
+                "url" => url = Some(value.trim_end_matches('/').to_string()),
+                "token_id" => token_id = Some(value),
+                "token_secret" => token_secret = Some(value),
+                "node" => node = Some(value),
+                "tags" => tags = Some(value.split(',').map(String::from).collect()),
+                "insecure_ssl" => insecure_ssl = parse_bool(&key, &value)?,
+                _ => return Err(DiscoverError::UnexpectedArgument(key)),
+            }
+        }
+
+        let url = url.ok_or_else(|| DiscoverError::MissingArgument("url".into()))?;
+        let token_id = token_id.ok_or_else(|| DiscoverError::MissingArgument("token_id".into()))?;
+        let token_secret =
+            token_secret.ok_or_else(|| DiscoverError::MissingArgument("token_secret".into()))?;
+        let tags = tags.ok_or_else(|| DiscoverError::MissingArgument("tags".into()))?;
+
+        Ok(ProxmoxProvider {
+            url,
+            token_id,
+            token_secret,
+            node,
+            tags,
+            insecure_ssl,
+        })
+    }
+}
+
+impl TryFrom<Vec<String>> for ProxmoxProvider {
+    type Error = DiscoverError;
+
+    fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
+        let args = ParsedArgs::try_from(value)?;
+        match *args.provider() {
+            SupportedProvider::Proxmox => ProxmoxProvider::try_from(args),
+            _ => Err(DiscoverError::MalformedArgument(
+                format!("provider={}", args.provider()),
+                "you should not see this ...".to_string(),
+            )),
+        }
+    }
+}
+
+impl ProxmoxProvider {
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn token_id(&self) -> &str {
+        &self.token_id
+    }
+
+    pub fn token_secret(&self) -> &str {
+        &self.token_secret
+    }
+
+    pub fn node(&self) -> Option<&String> {
+        self.node.as_ref()
+    }
+
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    pub fn insecure_ssl(&self) -> bool {
+        self.insecure_ssl
+    }
+
+    /// A guest matches when it carries every configured tag. Proxmox stores
+    /// the tags of a guest as a single `;` separated string.
+    fn has_tags(&self, guest: &Guest) -> bool {
+        let guest_tags = guest.tags.split(&[';', ',', ' '][..]).collect::<Vec<_>>();
+        self.tags.iter().all(|tag| guest_tags.contains(&&tag[..]))
+    }
+
+    async fn get<T: DeserializeOwned>(
+        &self,
+        client: &reqwest::Client,
+        path: &str,
+    ) -> Result<T, DiscoverError> {
+        let res = client
+            .get(format!("{}/api2/json{}", self.url, path))
+            .header(
+                "Authorization",
+                format!("PVEAPIToken={}={}", self.token_id, self.token_secret),
+            )
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(|e| DiscoverError::ProviderRequestFailed(format!("{:?}", e)))?;
+
+        res.json::<ApiResponse<T>>()
+            .await
+            .map(|res| res.data)
+            .map_err(|e| DiscoverError::ProviderRequestFailed(format!("{:?}", e)))
+    }
+
+    async fn get_nodes(&self, client: &reqwest::Client) -> Result<Vec<String>, DiscoverError> {
+        if let Some(node) = &self.node {
+            return Ok(vec![node.clone()]);
+        }
+
+        let nodes = self.get::<Vec<Node>>(client, "/nodes").await?;
+        Ok(nodes
+            .into_iter()
+            .filter(|node| {
+                let online = node.status == "online";
+                if !online {
+                    warn!("Skipping node {} with status {:?}", node.node, node.status);
+                }
+                online
+            })
+            .map(|node| node.node)
+            .collect())
+    }
+
+    async fn get_guest_addrs(
+        &self,
+        client: &reqwest::Client,
+        node: &str,
+        guest_type: GuestType,
+        guest: &Guest,
+    ) -> Result<Vec<String>, DiscoverError> {
+        let addrs = match guest_type {
+            GuestType::Qemu => {
+                let path = format!(
+                    "/nodes/{}/qemu/{}/agent/network-get-interfaces",
+                    node, guest.vmid
+                );
+                self.get::<AgentInterfaces>(client, &path)
+                    .await?
+                    .result
+                    .into_iter()
+                    .filter(|interface| interface.name != "lo")
+                    .flat_map(|interface| interface.ip_addresses)
+                    .filter(|addr| addr.ip_address_type == "ipv4")
+                    .map(|addr| addr.ip_address)
+                    .collect::<Vec<_>>()
+            }
+            GuestType::Lxc => {
+                let path = format!("/nodes/{}/lxc/{}/interfaces", node, guest.vmid);
+                self.get::<Vec<LxcInterface>>(client, &path)
+                    .await?
+                    .into_iter()
+                    .filter(|interface| interface.name != "lo")
+                    .filter_map(|interface| interface.inet)
+                    .map(|inet| inet.split('/').next().unwrap_or_default().to_string())
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        Ok(addrs
+            .into_iter()
+            .filter(|addr| !addr.starts_with("127."))
+            .collect())
+    }
+}
+
+#[async_trait::async_trait]
+impl Provider for ProxmoxProvider {
+    async fn addrs(&self) -> Result<Vec<String>, DiscoverError> {
+        debug!(
+            "Using url={} node={:?} tags={:?}",
+            self.url, self.node, self.tags
+        );
+
+        let client = reqwest::Client::builder()
+            .danger_accept_invalid_certs(self.insecure_ssl)
+            .build()
+            .map_err(|e| DiscoverError::ProviderRequestFailed(format!("{:?}", e)))?;
+
+        let mut addrs = Vec::new();
+        for node in self.get_nodes(&client).await? {
+            for guest_type in &[GuestType::Qemu, GuestType::Lxc] {
+                let path = format!("/nodes/{}/{}", node, guest_type.path());
+                // A cluster member that is down should not hide the guests of
+                // the other nodes.
+                let guests = match self.get::<Vec<Guest>>(&client, &path).await {
+                    Ok(guests) => guests,
+                    Err(e) => {
+                        warn!(
+                            "Unable to list the {} guests of node {}: {}",
+                            guest_type.path(),
+                            node,
+                            e
+                        );
+                        continue;
+                    }
+                };
+                debug!(
+                    "Found {} {} guests on node {}",
+                    guests.len(),
+                    guest_type.path(),
+                    node
+                );
+
+                for guest in guests {
+                    if guest.status != "running" || !self.has_tags(&guest) {
+                        continue;
+                    }
+
+                    // A single guest without a running QEMU guest agent should
+                    // not hide every other guest.
+                    match self
+                        .get_guest_addrs(&client, &node, *guest_type, &guest)
+                        .await
+                    {
+                        Ok(guest_addrs) => {
+                            info!(
+                                "Found guest {} ({}) on node {} with IPs: {:?}",
+                                guest.name, guest.vmid, node, guest_addrs
+                            );
+                            addrs.extend(guest_addrs);
+                        }
+                        Err(e) => warn!(
+                            "Unable to retrieve IPs of guest {} ({}) on node {}: {}",
+                            guest.name, guest.vmid, node, e
+                        ),
+                    }
+                }
+            }
+        }
+
+        debug!("Found ip addresses: {:?}", addrs);
+        Ok(addrs)
+    }
+
+    fn help() -> &'static str {
+        "Proxmox VE:
+
+	provider:     \"proxmox\"
+	url:          The url of the Proxmox VE API, e.g. \"https://pve.example.com:8006\"
+	token_id:     The API token id, e.g. \"user@pam!discover\"
+	token_secret: The API token secret
+	node:         The node to list guests on. Defaults to all online nodes in the cluster.
+	tags:         Comma separated list of tags a guest must have
+	insecure_ssl: \"true\" or \"false\". Skip verification of the API certificate. Defaults to \"false\".
+
+	Both QEMU virtual machines and LXC containers that are running are returned. The
+	IPs of virtual machines are read from the QEMU guest agent, which must be
+	installed and enabled. The token needs the 'VM.Audit' and 'VM.Monitor' privileges.
+"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::providers::stub::{self, StubResponse};
+
+    #[test]
+    fn proxmox_provider_from_string() {
+        let args = "provider=proxmox url=https://pve.example.com:8006/ token_id=root@pam!discover token_secret=0a1b2c node=pve1 tags=consul,server insecure_ssl=true";
+
+        let res = ParsedArgs::try_from(args.to_string());
+        assert!(res.is_ok());
+        let res = ProxmoxProvider::try_from(res.unwrap());
+        assert!(res.is_ok());
+        let provider = res.unwrap();
+        assert_eq!(provider.url(), "https://pve.example.com:8006");
+        assert_eq!(provider.token_id(), "root@pam!discover");
+        assert_eq!(provider.token_secret(), "0a1b2c");
+        assert_eq!(provider.node(), Some(&"pve1".to_string()));
+        assert_eq!(
+            provider.tags(),
+            &["consul".to_string(), "server".to_string()]
+        );
+        assert!(provider.insecure_ssl());
+    }
+
+    #[test]
+    fn fail_on_missing_token_secret() {
+        let args = "provider=proxmox url=https://pve.example.com:8006 token_id=root@pam!discover tags=consul";
+
+        let res = ProxmoxProvider::try_from(ParsedArgs::try_from(args.to_string()).unwrap());
+        assert_eq!(
+            res.unwrap_err(),
+            DiscoverError::MissingArgument("token_secret".to_string())
+        );
+    }
+
+    #[test]
+    fn fail_on_malformed_insecure_ssl() {
+        let args = "provider=proxmox url=https://pve.example.com:8006 token_id=a token_secret=b tags=consul insecure_ssl=yes";
+
+        let res = ProxmoxProvider::try_from(ParsedArgs::try_from(args.to_string()).unwrap());
+        assert_eq!(
+            res.unwrap_err(),
+            DiscoverError::MalformedArgument(
+                "insecure_ssl=yes".to_string(),
+                "Expected either true or false".to_string()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn list_tagged_vms_and_containers() {
+        let stub = stub::serve_tcp(|req| {
+            let body = match &req.path[..] {
+                "/api2/json/nodes" => {
+                    r#"{"data": [
+                        {"node": "pve1", "status": "online"},
+                        {"node": "pve2", "status": "offline"},
+                        {"node": "pve3", "status": "online"}
+                    ]}"#
+                }
+                "/api2/json/nodes/pve3/qemu" | "/api2/json/nodes/pve3/lxc" => {
+                    return StubResponse::new(
+                        595,
+                        "application/json",
+                        r#"{"data": null, "message": "no route to host"}"#,
+                    )
+                }
+                "/api2/json/nodes/pve1/qemu" => {
+                    r#"{"data": [
+                        {"vmid": 100, "name": "consul-1", "status": "running", "tags": "consul;server"},
+                        {"vmid": 101, "name": "consul-2", "status": "stopped", "tags": "consul;server"},
+                        {"vmid": 102, "name": "web-1", "status": "running", "tags": "web"},
+                        {"vmid": 103, "name": "consul-3", "status": "running", "tags": "server;consul"}
+                    ]}"#
+                }
+                "/api2/json/nodes/pve1/qemu/100/agent/network-get-interfaces" => {
+                    r#"{"data": {"result": [
+                        {"name": "lo", "ip-addresses": [{"ip-address": "127.0.0.1", "ip-address-type": "ipv4", "prefix": 8}]},
+                        {"name": "eth0", "ip-addresses": [
+                            {"ip-address": "10.0.0.10", "ip-address-type": "ipv4", "prefix": 24},
+                            {"ip-address": "fe80::1", "ip-address-type": "ipv6", "prefix": 64}
+                        ]}
+                    ]}}"#
+                }
+                "/api2/json/nodes/pve1/qemu/103/agent/network-get-interfaces" => {
+                    return StubResponse::new(
+                        500,
+                        "application/json",
+                        r#"{"data": null, "message": "QEMU guest agent is not running"}"#,
+                    )
+                }
+                "/api2/json/nodes/pve1/lxc" => {
+                    r#"{"data": [{"vmid": "200", "name": "consul-ct", "status": "running", "tags": "server;consul"}]}"#
+                }
+                "/api2/json/nodes/pve1/lxc/200/interfaces" => {
+                    r#"{"data": [
+                        {"name": "lo", "inet": "127.0.0.1/8"},
+                        {"name": "eth0", "inet": "10.0.0.20/24", "inet6": "fe80::2/64"}
+                    ]}"#
+                }
+                _ => return StubResponse::new(404, "application/json", "{}"),
+            };
+            StubResponse::json(body)
+        })
+        .await;
+
+        let args = vec![
+            "provider=proxmox".to_string(),
+            format!("url={}", stub.url()),
+            "token_id=root@pam!discover".to_string(),
+            "token_secret=0a1b2c".to_string(),
+            "tags=consul,server".to_string(),
+        ];
+        let provider = ProxmoxProvider::try_from(args).unwrap();
+        assert_eq!(
+            provider.addrs().await,
+            Ok(vec!["10.0.0.10".to_string(), "10.0.0.20".to_string()])
+        );
+
+        let requests = stub.requests();
+        // The offline node is not queried, the unreachable one is skipped
+        assert!(!requests.iter().any(|req| req.path.contains("/pve2")));
+        assert!(requests
+            .iter()
+            .any(|req| req.path == "/api2/json/nodes/pve3/lxc"));
+        assert!(
+            requests
+                .iter()
+                .all(|req| req.header("Authorization")
+                    == Some("PVEAPIToken=root@pam!discover=0a1b2c"))
+        );
+    }
+}
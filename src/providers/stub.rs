@@ -0,0 +1,172 @@
+//! A minimal HTTP/1.1 server used by the provider tests to stand in for the
+//! real provider APIs, listening either on a local TCP port or on a Unix socket.
+// Each provider's tests only use part of this module
+#![allow(dead_code)]
+
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, UnixListener},
+};
+
+#[derive(Debug, Clone)]
+pub struct StubRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl StubRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StubResponse {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl StubResponse {
+    pub fn json(body: &str) -> Self {
+        Self::new(200, "application/json", body)
+    }
+
+    pub fn new(status: u16, content_type: &'static str, body: &str) -> Self {
+        Self {
+            status,
+            content_type,
+            body: body.to_string(),
+        }
+    }
+}
+
+type Handler = Arc<dyn Fn(&StubRequest) -> StubResponse + Send + Sync>;
+
+/// A running stub server. Every request it receives is recorded.
+pub struct Stub {
+    url: String,
+    requests: Arc<Mutex<Vec<StubRequest>>>,
+}
+
+impl Stub {
+    /// Base url of the server, e.g. `http://127.0.0.1:34567`. Empty for Unix sockets.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn requests(&self) -> Vec<StubRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+pub async fn serve_tcp<F>(handler: F) -> Stub
+where
+    F: Fn(&StubRequest) -> StubResponse + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let handler: Handler = Arc::new(handler);
+
+    let recorded = requests.clone();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(handle(stream, handler.clone(), recorded.clone()));
+        }
+    });
+
+    Stub { url, requests }
+}
+
+pub async fn serve_unix<F>(socket: &Path, handler: F) -> Stub
+where
+    F: Fn(&StubRequest) -> StubResponse + Send + Sync + 'static,
+{
+    let listener = UnixListener::bind(socket).unwrap();
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let handler: Handler = Arc::new(handler);
+
+    let recorded = requests.clone();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(handle(stream, handler.clone(), recorded.clone()));
+        }
+    });
+
+    Stub {
+        url: String::new(),
+        requests,
+    }
+}
+
+async fn handle<S>(mut stream: S, handler: Handler, requests: Arc<Mutex<Vec<StubRequest>>>)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+
+    let head_end = loop {
+        if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos;
+        }
+        match stream.read(&mut chunk).await {
+            Ok(0) | Err(_) => return,
+            Ok(n) => buf.extend_from_slice(&chunk[..n]),
+        }
+    };
+
+    let head = String::from_utf8_lossy(&buf[..head_end]).to_string();
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let method = request_line.next().unwrap_or_default().to_string();
+    let path = request_line.next().unwrap_or_default().to_string();
+    let headers = lines
+        .filter_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            Some((key.trim().to_string(), value.trim().to_string()))
+        })
+        .collect::<Vec<_>>();
+
+    let content_length = headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.parse::<usize>().ok())
+        .unwrap_or(0);
+    let mut body = buf[head_end + 4..].to_vec();
+    while body.len() < content_length {
+        match stream.read(&mut chunk).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => body.extend_from_slice(&chunk[..n]),
+        }
+    }
+
+    let request = StubRequest {
+        method,
+        path,
+        headers,
+        body: String::from_utf8_lossy(&body).to_string(),
+    };
+    let response = handler(&request);
+    requests.lock().unwrap().push(request);
+
+    let response = format!(
+        "HTTP/1.1 {} Stub\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        response.content_type,
+        response.body.len(),
+        response.body
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
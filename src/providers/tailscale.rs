@@ -0,0 +1,512 @@
+use log::{debug, info};
+use serde::Deserialize;
+use std::{collections::HashMap, convert::TryFrom, path::PathBuf};
+
+use crate::{args::ParsedArgs, SupportedProvider};
+
+use super::{parse_bool, unix_socket, DiscoverError, Provider};
+
+const DEFAULT_SOCKET: &str = "/var/run/tailscale/tailscaled.sock";
+const DEFAULT_API_URL: &str = "https://api.tailscale.com";
+/// tailscaled rejects LocalAPI requests for any other host
+const LOCAL_API_HOST: &str = "local-tailscaled.sock";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Status {
+    #[serde(rename = "Self")]
+    pub self_: Option<PeerStatus>,
+    #[serde(default)]
+    pub peer: HashMap<String, PeerStatus>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PeerStatus {
+    #[serde(default)]
+    pub host_name: String,
+    #[serde(rename = "TailscaleIPs", default)]
+    pub tailscale_ips: Vec<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub online: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Devices {
+    pub devices: Vec<Device>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Device {
+    #[serde(default)]
+    pub hostname: String,
+    #[serde(default)]
+    pub addresses: Vec<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub connected_to_control: Option<bool>,
+}
+
+/// A node of the tailnet, from either the LocalAPI or the control-plane API.
+#[derive(Debug, Clone, PartialEq)]
+struct Node {
+    pub name: String,
+    pub ips: Vec<String>,
+    pub tags: Vec<String>,
+    /// Unknown when the control-plane API does not report it
+    pub online: Option<bool>,
+}
+
+impl From<PeerStatus> for Node {
+    fn from(peer: PeerStatus) -> Self {
+        Node {
+            name: peer.host_name,
+            ips: peer.tailscale_ips,
+            tags: peer.tags,
+            online: Some(peer.online),
+        }
+    }
+}
+
+impl From<Device> for Node {
+    fn from(device: Device) -> Self {
+        Node {
+            name: device.hostname,
+            ips: device.addresses,
+            tags: device.tags,
+            online: device.connected_to_control,
+        }
+    }
+}
+
+/// Where the tailnet is read from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TailscaleEndpoint {
+    /// Path of the Unix socket of the local tailscaled
+    LocalApi(PathBuf),
+    /// The control-plane API
+    Api {
+        url: String,
+        tailnet: String,
+        api_key: String,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct TailscaleProvider {
+    endpoint: TailscaleEndpoint,
+    tag: Option<String>,
+    online: bool,
+    family: String,
+}
+
+impl TryFrom<ParsedArgs> for TailscaleProvider {
+    type Error = DiscoverError;
+
+    fn try_from(args: ParsedArgs) -> Result<Self, Self::Error> {
+        let mut socket = None;
+        let mut api_key = None;
+        let mut tailnet = None;
+        let mut url = None;
+        let mut tag = None;
+        let mut online = true;
+        let mut family = "inet".to_string();
+
+        for (key, value) in args {
+            match &key[..] {
+                "socket" => socket = Some(PathBuf::from(value)),
+                "api_key" => api_key = Some(value),
+                "tailnet" => tailnet = Some(value),
+                "url" => url = Some(value.trim_end_matches('/').to_string()),
+                "tag" => {
+                    // Accept both "tag:server" and "server"
+                    tag = Some(if value.starts_with("tag:") {
+                        value
+                    } else {
+                        format!("tag:{}", value)
+                    })
+                }
+                "online" => online = parse_bool(&key, &value)?,
+                "family" => match &value[..] {
+                    "inet" | "inet6" => family = value,
+                    _ => {
+                        return Err(DiscoverError::MalformedArgument(
+                            format!("family={}", value),
+                            format!(
+                                "{} is not a valid family. Valid families are: inet and inet6.",
+                                value
+                            ),
+                        ))
+                    }
+                },
+                _ => return Err(DiscoverError::UnexpectedArgument(key)),
+            }
+        }
+
+        let endpoint = match api_key {
+            Some(api_key) => {
+                if let Some(socket) = socket {
+                    return Err(DiscoverError::MalformedArgument(
+                        format!("socket={}", socket.display()),
+                        "socket can not be combined with api_key".to_string(),
+                    ));
+                }
+                TailscaleEndpoint::Api {
+                    url: url.unwrap_or_else(|| DEFAULT_API_URL.to_string()),
+                    // "-" is the default tailnet of the API key
+                    tailnet: tailnet.unwrap_or_else(|| "-".to_string()),
+                    api_key,
+                }
+            }
+            None => {
+                for (key, value) in [("tailnet", tailnet), ("url", url)] {
+                    if let Some(value) = value {
+                        return Err(DiscoverError::MalformedArgument(
+                            format!("{}={}", key, value),
+                            format!("{} requires api_key", key),
+                        ));
+                    }
+                }
+                let socket = socket.unwrap_or_else(|| PathBuf::from(DEFAULT_SOCKET));
+                TailscaleEndpoint::LocalApi(socket)
+            }
+        };
+
+        Ok(TailscaleProvider {
+            endpoint,
+            tag,
+            online,
+            family,
+        })
+    }
+}
+
+impl TryFrom<Vec<String>> for TailscaleProvider {
+    type Error = DiscoverError;
+
+    fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
+        let args = ParsedArgs::try_from(value)?;
+        match *args.provider() {
+            SupportedProvider::Tailscale => TailscaleProvider::try_from(args),
+            _ => Err(DiscoverError::MalformedArgument(
+                format!("provider={}", args.provider()),
+                "you should not see this ...".to_string(),
+            )),
+        }
+    }
+}
+
+impl TailscaleProvider {
+    pub fn endpoint(&self) -> &TailscaleEndpoint {
+        &self.endpoint
+    }
+
+    pub fn tag(&self) -> Option<&str> {
+        self.tag.as_deref()
+    }
+
+    pub fn online(&self) -> bool {
+        self.online
+    }
+
+    pub fn family(&self) -> &str {
+        &self.family
+    }
+
+    async fn get_nodes(&self) -> Result<Vec<Node>, DiscoverError> {
+        match &self.endpoint {
+            TailscaleEndpoint::LocalApi(socket) => {
+                let status: Status = unix_socket::get_json(
+                    socket,
+                    "/localapi/v0/status",
+                    &[("Host", LOCAL_API_HOST)],
+                )
+                .await?;
+
+                let mut peers = status.peer.into_iter().collect::<Vec<_>>();
+                // The peers are keyed by node key, sort them for a stable order
+                peers.sort_by(|(a, _), (b, _)| a.cmp(b));
+                Ok(status
+                    .self_
+                    .into_iter()
+                    .chain(peers.into_iter().map(|(_, peer)| peer))
+                    .map(Node::from)
+                    .collect())
+            }
+            TailscaleEndpoint::Api {
+                url,
+                tailnet,
+                api_key,
+            } => {
+                let devices = reqwest::Client::new()
+                    .get(format!("{}/api/v2/tailnet/{}/devices", url, tailnet))
+                    .bearer_auth(api_key)
+                    .send()
+                    .await
+                    .and_then(|res| res.error_for_status())
+                    .map_err(|e| DiscoverError::ProviderRequestFailed(format!("{:?}", e)))?
+                    .json::<Devices>()
+                    .await
+                    .map_err(|e| DiscoverError::ProviderRequestFailed(format!("{:?}", e)))?;
+
+                Ok(devices.devices.into_iter().map(Node::from).collect())
+            }
+        }
+    }
+
+    fn is_wanted(&self, node: &Node) -> bool {
+        if let Some(tag) = &self.tag {
+            if !node.tags.contains(tag) {
+                debug!("Skipping node {} without {}", node.name, tag);
+                return false;
+            }
+        }
+        if self.online && node.online == Some(false) {
+            debug!("Skipping offline node {}", node.name);
+            return false;
+        }
+        true
+    }
+}
+
+#[async_trait::async_trait]
+impl Provider for TailscaleProvider {
+    async fn addrs(&self) -> Result<Vec<String>, DiscoverError> {
+        debug!(
+            "Using endpoint={:?} tag={:?} online={} family={}",
+            match &self.endpoint {
+                TailscaleEndpoint::LocalApi(socket) => socket.display().to_string(),
+                TailscaleEndpoint::Api { url, tailnet, .. } =>
+                    format!("{} tailnet={}", url, tailnet),
+            },
+            self.tag,
+            self.online,
+            self.family
+        );
+
+        let nodes = self.get_nodes().await?;
+        debug!("Found {} nodes", nodes.len());
+
+        let inet6 = self.family == "inet6";
+        let mut addrs = Vec::new();
+        for node in nodes.into_iter().filter(|node| self.is_wanted(node)) {
+            for ip in node.ips.iter().filter(|ip| ip.contains(':') == inet6) {
+                info!("Node {} has {} address {}", node.name, self.family, ip);
+                addrs.push(ip.clone());
+            }
+        }
+
+        Ok(addrs)
+    }
+
+    fn help() -> &'static str {
+        "Tailscale:
+
+	provider: \"tailscale\"
+	socket:   Path of the tailscaled LocalAPI socket. Defaults to
+	          \"/var/run/tailscale/tailscaled.sock\".
+	api_key:  Use the control-plane API with this API key instead of the local tailscaled
+	tailnet:  The tailnet to list the devices of with api_key. Defaults to the tailnet of
+	          the API key.
+	url:      Base url of the control-plane API. Defaults to \"https://api.tailscale.com\".
+	tag:      Only return nodes with this ACL tag, e.g. \"tag:consul-server\"
+	online:   Only return nodes that are online, \"true\" or \"false\". Defaults to \"true\".
+	family:   \"inet\" or \"inet6\". Defaults to \"inet\".
+
+	The local node is returned as well when it matches. The control-plane API only
+	reports whether a device is online when it is connected to the control plane.
+"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::providers::stub::{self, StubResponse};
+
+    const STATUS: &str = r#"{
+        "Version": "1.70.0",
+        "TailscaleIPs": ["100.64.0.1", "fd7a:115c:a1e0::1"],
+        "Self": {
+            "HostName": "consul-1",
+            "TailscaleIPs": ["100.64.0.1", "fd7a:115c:a1e0::1"],
+            "Tags": ["tag:consul-server"],
+            "Online": true
+        },
+        "Peer": {
+            "nodekey:bb": {
+                "HostName": "consul-3",
+                "TailscaleIPs": ["100.64.0.3", "fd7a:115c:a1e0::3"],
+                "Tags": ["tag:consul-server"],
+                "Online": false
+            },
+            "nodekey:aa": {
+                "HostName": "consul-2",
+                "TailscaleIPs": ["100.64.0.2", "fd7a:115c:a1e0::2"],
+                "Tags": ["tag:consul-server", "tag:prod"],
+                "Online": true
+            },
+            "nodekey:cc": {
+                "HostName": "laptop",
+                "TailscaleIPs": ["100.64.0.4", "fd7a:115c:a1e0::4"],
+                "Online": true
+            }
+        }
+    }"#;
+
+    const DEVICES: &str = r#"{
+        "devices": [
+            {
+                "hostname": "consul-1",
+                "addresses": ["100.64.0.1", "fd7a:115c:a1e0::1"],
+                "tags": ["tag:consul-server"],
+                "connectedToControl": true
+            },
+            {
+                "hostname": "consul-2",
+                "addresses": ["100.64.0.2", "fd7a:115c:a1e0::2"],
+                "tags": ["tag:consul-server"]
+            },
+            {
+                "hostname": "consul-3",
+                "addresses": ["100.64.0.3", "fd7a:115c:a1e0::3"],
+                "tags": ["tag:consul-server"],
+                "connectedToControl": false
+            },
+            {
+                "hostname": "laptop",
+                "addresses": ["100.64.0.4", "fd7a:115c:a1e0::4"],
+                "connectedToControl": true
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn tailscale_provider_from_string() {
+        let args = "provider=tailscale tag=consul-server";
+
+        let res = ParsedArgs::try_from(args.to_string());
+        assert!(res.is_ok());
+        let res = TailscaleProvider::try_from(res.unwrap());
+        assert!(res.is_ok());
+        let provider = res.unwrap();
+        assert_eq!(
+            provider.endpoint(),
+            &TailscaleEndpoint::LocalApi(PathBuf::from(DEFAULT_SOCKET))
+        );
+        assert_eq!(provider.tag(), Some("tag:consul-server"));
+        assert!(provider.online());
+        assert_eq!(provider.family(), "inet");
+
+        let args = "provider=tailscale api_key=tskey-api-xyz tailnet=example.com family=inet6";
+        let provider =
+            TailscaleProvider::try_from(ParsedArgs::try_from(args.to_string()).unwrap()).unwrap();
+        assert_eq!(
+            provider.endpoint(),
+            &TailscaleEndpoint::Api {
+                url: DEFAULT_API_URL.to_string(),
+                tailnet: "example.com".to_string(),
+                api_key: "tskey-api-xyz".to_string(),
+            }
+        );
+        assert_eq!(provider.family(), "inet6");
+    }
+
+    #[test]
+    fn fail_on_malformed_arguments() {
+        let cases = vec![
+            (
+                "provider=tailscale online=yes",
+                DiscoverError::MalformedArgument(
+                    "online=yes".to_string(),
+                    "Expected either true or false".to_string(),
+                ),
+            ),
+            (
+                "provider=tailscale tailnet=example.com",
+                DiscoverError::MalformedArgument(
+                    "tailnet=example.com".to_string(),
+                    "tailnet requires api_key".to_string(),
+                ),
+            ),
+            (
+                "provider=tailscale api_key=tskey socket=/tmp/tailscaled.sock",
+                DiscoverError::MalformedArgument(
+                    "socket=/tmp/tailscaled.sock".to_string(),
+                    "socket can not be combined with api_key".to_string(),
+                ),
+            ),
+        ];
+
+        for (args, err) in cases {
+            let res = TailscaleProvider::try_from(ParsedArgs::try_from(args.to_string()).unwrap());
+            assert_eq!(res.unwrap_err(), err, "{}", args);
+        }
+    }
+
+    #[tokio::test]
+    async fn list_peers_from_local_api() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("tailscaled.sock");
+        let stub = stub::serve_unix(&socket, |_| StubResponse::json(STATUS)).await;
+
+        let args = vec![
+            "provider=tailscale".to_string(),
+            format!("socket={}", socket.display()),
+            "tag=tag:consul-server".to_string(),
+        ];
+        let provider = TailscaleProvider::try_from(args).unwrap();
+        assert_eq!(
+            provider.addrs().await,
+            Ok(vec!["100.64.0.1".to_string(), "100.64.0.2".to_string()])
+        );
+
+        let requests = stub.requests();
+        assert_eq!(requests[0].path, "/localapi/v0/status");
+        assert_eq!(requests[0].header("host"), Some(LOCAL_API_HOST));
+
+        let args = vec![
+            "provider=tailscale".to_string(),
+            format!("socket={}", socket.display()),
+            "online=false".to_string(),
+            "family=inet6".to_string(),
+        ];
+        let provider = TailscaleProvider::try_from(args).unwrap();
+        assert_eq!(
+            provider.addrs().await,
+            Ok(vec![
+                "fd7a:115c:a1e0::1".to_string(),
+                "fd7a:115c:a1e0::2".to_string(),
+                "fd7a:115c:a1e0::3".to_string(),
+                "fd7a:115c:a1e0::4".to_string(),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn list_devices_from_api() {
+        let stub = stub::serve_tcp(|_| StubResponse::json(DEVICES)).await;
+
+        let args = vec![
+            "provider=tailscale".to_string(),
+            format!("url={}", stub.url()),
+            "api_key=tskey-api-xyz".to_string(),
+            "tag=consul-server".to_string(),
+        ];
+        let provider = TailscaleProvider::try_from(args).unwrap();
+        assert_eq!(
+            provider.addrs().await,
+            Ok(vec!["100.64.0.1".to_string(), "100.64.0.2".to_string()])
+        );
+
+        let requests = stub.requests();
+        assert_eq!(requests[0].path, "/api/v2/tailnet/-/devices");
+        assert_eq!(
+            requests[0].header("authorization"),
+            Some("Bearer tskey-api-xyz")
+        );
+    }
+}
@@ -0,0 +1,473 @@
+use log::{debug, info};
+use serde::Deserialize;
+use std::{convert::TryFrom, fs, path::PathBuf};
+
+use crate::{args::ParsedArgs, SupportedProvider};
+
+use super::{DiscoverError, Provider};
+
+#[derive(Debug, Clone, Deserialize)]
+struct State {
+    pub version: u64,
+    #[serde(default)]
+    pub resources: Vec<Resource>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Resource {
+    /// Address of the module the resource belongs to, e.g. `module.consul.module.vm`.
+    /// Resources in the root module have no module.
+    pub module: Option<String>,
+    pub mode: String,
+    #[serde(rename = "type")]
+    pub resource_type: String,
+    pub name: String,
+    #[serde(default)]
+    pub instances: Vec<ResourceInstance>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ResourceInstance {
+    #[serde(default)]
+    pub attributes: serde_json::Value,
+}
+
+/// Looks up a dotted attribute path like `network_interface.0.network_ip`,
+/// where numeric segments index into lists.
+fn lookup<'a>(value: &'a serde_json::Value, path: &str) -> Option<&'a serde_json::Value> {
+    path.split('.')
+        .try_fold(value, |value, segment| match value {
+            serde_json::Value::Array(items) => items.get(segment.parse::<usize>().ok()?),
+            _ => value.get(segment),
+        })
+}
+
+#[derive(Debug, Clone)]
+pub struct TfstateProvider {
+    path: PathBuf,
+    resource_type: String,
+    attribute: String,
+    module: Option<String>,
+    tag_key: Option<String>,
+    tag_value: Option<String>,
+}
+
+impl TryFrom<ParsedArgs> for TfstateProvider {
+    type Error = DiscoverError;
+
+    fn try_from(args: ParsedArgs) -> Result<Self, Self::Error> {
+        let mut path = None;
+        let mut resource_type = None;
+        let mut attribute = None;
+        let mut module = None;
+        let mut tag_key = None;
+        let mut tag_value = None;
+
+        for (key, value) in args {
+            match &key[..] {
+                "path" => path = Some(PathBuf::from(value)),
+                "resource_type" => resource_type = Some(value),
+                "attribute" => attribute = Some(value),
+                "module" => module = Some(value),
+                "tag_key" => tag_key = Some(value),
+                "tag_value" => tag_value = Some(value),
+                _ => return Err(DiscoverError::UnexpectedArgument(key)),
+            }
+        }
+
+        let path = path.ok_or_else(|| DiscoverError::MissingArgument("path".into()))?;
+        let resource_type =
+            resource_type.ok_or_else(|| DiscoverError::MissingArgument("resource_type".into()))?;
+        let attribute =
+            attribute.ok_or_else(|| DiscoverError::MissingArgument("attribute".into()))?;
+        if tag_key.is_some() && tag_value.is_none() {
+            return Err(DiscoverError::MissingArgument("tag_value".into()));
+        }
+        if tag_value.is_some() && tag_key.is_none() {
+            return Err(DiscoverError::MissingArgument("tag_key".into()));
+        }
+
+        Ok(TfstateProvider {
+            path,
+            resource_type,
+            attribute,
+            module,
+            tag_key,
+            tag_value,
+        })
+    }
+}
+
+impl TryFrom<Vec<String>> for TfstateProvider {
+    type Error = DiscoverError;
+
+    fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
+        let args = ParsedArgs::try_from(value)?;
+        match *args.provider() {
+            SupportedProvider::Tfstate => TfstateProvider::try_from(args),
+            _ => Err(DiscoverError::MalformedArgument(
+                format!("provider={}", args.provider()),
+                "you should not see this ...".to_string(),
+            )),
+        }
+    }
+}
+
+impl TfstateProvider {
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    pub fn resource_type(&self) -> &str {
+        &self.resource_type
+    }
+
+    pub fn attribute(&self) -> &str {
+        &self.attribute
+    }
+
+    pub fn module(&self) -> Option<&String> {
+        self.module.as_ref()
+    }
+
+    pub fn tag_key(&self) -> Option<&String> {
+        self.tag_key.as_ref()
+    }
+
+    pub fn tag_value(&self) -> Option<&String> {
+        self.tag_value.as_ref()
+    }
+
+    fn read_state(&self) -> Result<State, DiscoverError> {
+        let content = fs::read_to_string(&self.path).map_err(|e| {
+            DiscoverError::ProviderRequestFailed(format!(
+                "Unable to read {}: {}",
+                self.path.display(),
+                e
+            ))
+        })?;
+        let state = serde_json::from_str::<State>(&content).map_err(|e| {
+            DiscoverError::ProviderRequestFailed(format!(
+                "Unable to parse {}: {}",
+                self.path.display(),
+                e
+            ))
+        })?;
+
+        if state.version != 4 {
+            return Err(DiscoverError::ProviderRequestFailed(format!(
+                "Unsupported state version {} in {}. Only version 4 is supported.",
+                state.version,
+                self.path.display()
+            )));
+        }
+
+        Ok(state)
+    }
+
+    /// A resource in a nested module matches a module filter on any of its parents.
+    fn in_module(&self, resource: &Resource) -> bool {
+        match (&self.module, &resource.module) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some(wanted), Some(module)) => {
+                module == wanted || module.starts_with(&format!("{}.", wanted))
+            }
+        }
+    }
+
+    fn has_tag(&self, attributes: &serde_json::Value) -> bool {
+        match (&self.tag_key, &self.tag_value) {
+            (Some(key), Some(value)) => {
+                attributes
+                    .get("tags")
+                    .and_then(|tags| tags.get(key))
+                    .and_then(|tag| tag.as_str())
+                    == Some(value.as_str())
+            }
+            _ => true,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Provider for TfstateProvider {
+    async fn addrs(&self) -> Result<Vec<String>, DiscoverError> {
+        debug!(
+            "Using path={} resource_type={} attribute={} module={:?} tag_key={:?} tag_value={:?}",
+            self.path.display(),
+            self.resource_type,
+            self.attribute,
+            self.module,
+            self.tag_key,
+            self.tag_value
+        );
+
+        let state = self.read_state()?;
+        debug!("Found {} resources", state.resources.len());
+
+        let mut addrs = Vec::new();
+        for resource in state.resources {
+            if resource.mode != "managed"
+                || resource.resource_type != self.resource_type
+                || !self.in_module(&resource)
+            {
+                continue;
+            }
+
+            let address = match &resource.module {
+                Some(module) => format!("{}.{}.{}", module, resource.resource_type, resource.name),
+                None => format!("{}.{}", resource.resource_type, resource.name),
+            };
+
+            for instance in resource.instances {
+                if !self.has_tag(&instance.attributes) {
+                    continue;
+                }
+
+                match lookup(&instance.attributes, &self.attribute) {
+                    Some(serde_json::Value::String(addr)) if !addr.is_empty() => {
+                        info!("Found resource {} with {}", address, addr);
+                        addrs.push(addr.clone());
+                    }
+                    Some(serde_json::Value::Array(values)) => {
+                        for addr in values.iter().filter_map(|v| v.as_str()) {
+                            info!("Found resource {} with {}", address, addr);
+                            addrs.push(addr.to_string());
+                        }
+                    }
+                    _ => debug!("Resource {} has no attribute {}", address, self.attribute),
+                }
+            }
+        }
+
+        debug!("Found ip addresses: {:?}", addrs);
+        Ok(addrs)
+    }
+
+    fn help() -> &'static str {
+        "Terraform state:
+
+	provider:      \"tfstate\"
+	path:          Path of the terraform.tfstate file
+	resource_type: The resource type to look for, e.g. \"aws_instance\"
+	attribute:     The attribute holding the address, e.g. \"private_ip\". Nested attributes
+	               are separated by dots, e.g. \"network_interface.0.network_ip\".
+	module:        Only look at resources in this module and its child modules, e.g. \"module.consul\"
+	tag_key:       The key in the tags attribute to filter on
+	tag_value:     The tag value to filter on
+
+	Only version 4 state files, written by Terraform 0.12 and later, are supported.
+	No credentials are needed since the state file is read from disk.
+"
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+
+    const STATE: &str = r#"{
+        "version": 4,
+        "terraform_version": "1.5.7",
+        "serial": 12,
+        "lineage": "4b1f8a45-1a9d-6b8e-0c3c-2a6e2c5b5f0e",
+        "outputs": {},
+        "resources": [
+            {
+                "mode": "data",
+                "type": "aws_instance",
+                "name": "existing",
+                "instances": [{ "attributes": { "private_ip": "10.0.9.9", "tags": { "consul": "server" } } }]
+            },
+            {
+                "mode": "managed",
+                "type": "aws_instance",
+                "name": "bastion",
+                "instances": [{ "attributes": { "private_ip": "10.0.0.5", "tags": { "role": "bastion" } } }]
+            },
+            {
+                "module": "module.consul",
+                "mode": "managed",
+                "type": "aws_instance",
+                "name": "server",
+                "instances": [
+                    { "index_key": 0, "attributes": { "private_ip": "10.0.1.10", "tags": { "consul": "server" } } },
+                    { "index_key": 1, "attributes": { "private_ip": "10.0.1.11", "tags": { "consul": "server" } } }
+                ]
+            },
+            {
+                "module": "module.consul.module.clients",
+                "mode": "managed",
+                "type": "aws_instance",
+                "name": "client",
+                "instances": [
+                    { "attributes": { "private_ip": "10.0.2.10", "secondary_private_ips": ["10.0.2.20", "10.0.2.21"], "tags": { "consul": "client" } } }
+                ]
+            },
+            {
+                "module": "module.consulate",
+                "mode": "managed",
+                "type": "aws_instance",
+                "name": "other",
+                "instances": [{ "attributes": { "private_ip": "10.0.3.10", "tags": null } }]
+            },
+            {
+                "mode": "managed",
+                "type": "google_compute_instance",
+                "name": "vm",
+                "instances": [{ "attributes": { "network_interface": [{ "network_ip": "10.128.0.2" }] } }]
+            }
+        ]
+    }"#;
+
+    fn state_file(content: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(content.as_bytes()).unwrap();
+        file
+    }
+
+    fn provider(file: &tempfile::NamedTempFile, args: &[&str]) -> TfstateProvider {
+        let mut args = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        args.push("provider=tfstate".to_string());
+        args.push(format!("path={}", file.path().display()));
+        TfstateProvider::try_from(args).unwrap()
+    }
+
+    #[test]
+    fn tfstate_provider_from_string() {
+        let args = "provider=tfstate path=/srv/terraform.tfstate resource_type=aws_instance attribute=private_ip module=module.consul tag_key=consul tag_value=server";
+
+        let res = ParsedArgs::try_from(args.to_string());
+        assert!(res.is_ok());
+        let res = TfstateProvider::try_from(res.unwrap());
+        assert!(res.is_ok());
+        let provider = res.unwrap();
+        assert_eq!(provider.path(), &PathBuf::from("/srv/terraform.tfstate"));
+        assert_eq!(provider.resource_type(), "aws_instance");
+        assert_eq!(provider.attribute(), "private_ip");
+        assert_eq!(provider.module(), Some(&"module.consul".to_string()));
+        assert_eq!(provider.tag_key(), Some(&"consul".to_string()));
+        assert_eq!(provider.tag_value(), Some(&"server".to_string()));
+    }
+
+    #[test]
+    fn fail_on_missing_arguments() {
+        let cases = vec![
+            ("provider=tfstate resource_type=aws_instance attribute=private_ip", "path"),
+            ("provider=tfstate path=a attribute=private_ip", "resource_type"),
+            ("provider=tfstate path=a resource_type=aws_instance", "attribute"),
+            (
+                "provider=tfstate path=a resource_type=aws_instance attribute=private_ip tag_key=consul",
+                "tag_value",
+            ),
+        ];
+
+        for (args, missing) in cases {
+            let res = TfstateProvider::try_from(ParsedArgs::try_from(args.to_string()).unwrap());
+            assert_eq!(
+                res.unwrap_err(),
+                DiscoverError::MissingArgument(missing.to_string()),
+                "{}",
+                args
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn return_attribute_of_managed_resources_in_all_modules() {
+        let file = state_file(STATE);
+        let p = provider(
+            &file,
+            &["resource_type=aws_instance", "attribute=private_ip"],
+        );
+        assert_eq!(
+            p.addrs().await,
+            Ok(vec![
+                "10.0.0.5".to_string(),
+                "10.0.1.10".to_string(),
+                "10.0.1.11".to_string(),
+                "10.0.2.10".to_string(),
+                "10.0.3.10".to_string(),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn filter_on_module_and_tags() {
+        let file = state_file(STATE);
+
+        let p = provider(
+            &file,
+            &[
+                "resource_type=aws_instance",
+                "attribute=private_ip",
+                "module=module.consul",
+            ],
+        );
+        assert_eq!(
+            p.addrs().await,
+            Ok(vec![
+                "10.0.1.10".to_string(),
+                "10.0.1.11".to_string(),
+                "10.0.2.10".to_string(),
+            ])
+        );
+
+        let p = provider(
+            &file,
+            &[
+                "resource_type=aws_instance",
+                "attribute=private_ip",
+                "tag_key=consul",
+                "tag_value=server",
+            ],
+        );
+        assert_eq!(
+            p.addrs().await,
+            Ok(vec!["10.0.1.10".to_string(), "10.0.1.11".to_string()])
+        );
+    }
+
+    #[tokio::test]
+    async fn return_nested_and_list_attributes() {
+        let file = state_file(STATE);
+
+        let p = provider(
+            &file,
+            &[
+                "resource_type=google_compute_instance",
+                "attribute=network_interface.0.network_ip",
+            ],
+        );
+        assert_eq!(p.addrs().await, Ok(vec!["10.128.0.2".to_string()]));
+
+        let p = provider(
+            &file,
+            &[
+                "resource_type=aws_instance",
+                "attribute=secondary_private_ips",
+            ],
+        );
+        assert_eq!(
+            p.addrs().await,
+            Ok(vec!["10.0.2.20".to_string(), "10.0.2.21".to_string()])
+        );
+    }
+
+    #[tokio::test]
+    async fn fail_on_unsupported_state_version() {
+        let file = state_file(r#"{"version": 3, "modules": []}"#);
+        let p = provider(
+            &file,
+            &["resource_type=aws_instance", "attribute=private_ip"],
+        );
+        match p.addrs().await {
+            Err(DiscoverError::ProviderRequestFailed(msg)) => {
+                assert!(msg.contains("Unsupported state version 3"), "{}", msg)
+            }
+            res => panic!("Expected ProviderRequestFailed, got {:?}", res),
+        }
+    }
+}
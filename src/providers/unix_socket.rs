@@ -0,0 +1,50 @@
+//! HTTP requests against local daemons that expose their API on a Unix socket.
+
+use hyper::{body, Body, Client, Request};
+use hyperlocal::{UnixClientExt, Uri};
+use serde::de::DeserializeOwned;
+use std::path::Path;
+
+use super::DiscoverError;
+
+/// Sends a GET request for `path` with the extra `headers` to the HTTP server
+/// listening on `socket` and deserializes the JSON response body.
+pub(crate) async fn get_json<T: DeserializeOwned>(
+    socket: &Path,
+    path: &str,
+    headers: &[(&str, &str)],
+) -> Result<T, DiscoverError> {
+    let mut req = Request::get(Uri::new(socket, path));
+    for (name, value) in headers {
+        req = req.header(*name, *value);
+    }
+    let req = req
+        .body(Body::empty())
+        .map_err(|e| DiscoverError::ProviderRequestFailed(format!("{:?}", e)))?;
+
+    let res = Client::unix().request(req).await.map_err(|e| {
+        DiscoverError::ProviderRequestFailed(format!(
+            "Unable to connect to {}: {}",
+            socket.display(),
+            e
+        ))
+    })?;
+
+    let status = res.status();
+    let bytes = body::to_bytes(res.into_body())
+        .await
+        .map_err(|e| DiscoverError::ProviderRequestFailed(format!("{:?}", e)))?;
+
+    if !status.is_success() {
+        return Err(DiscoverError::ProviderRequestFailed(format!(
+            "{} {} returned {}: {}",
+            socket.display(),
+            path,
+            status,
+            String::from_utf8_lossy(&bytes).trim()
+        )));
+    }
+
+    serde_json::from_slice(&bytes)
+        .map_err(|e| DiscoverError::ProviderRequestFailed(format!("{:?}", e)))
+}